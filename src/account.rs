@@ -1,61 +1,116 @@
-use bitvec::prelude::*;
 use blake2b_simd::{Hash, Params};
 use byteorder::{BigEndian, WriteBytesExt};
 use ed25519_dalek::{PublicKey, SecretKey};
-use log::info;
-use std::iter::FromIterator;
-use std::sync::mpsc;
+use log::{error, info, warn};
+use std::collections::VecDeque;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::address::Address;
-use crate::block::Block;
-use crate::common::{bytes_to_hexstring, encode_nano_base_32};
+use crate::address::{address_to_public_key, public_key_to_address, Address};
+use crate::audit::{audit, AuditEntry, AuditReason};
+use crate::block::{hex_to_bytes, Block};
+use crate::common::{bytes_to_hexstring, hexstring_to_bytes};
 use crate::config::CONFIG;
 use crate::rpc::*;
+use crate::schedule::{PresignedBlock, ScheduledPayment};
 use crate::seed::Seed;
 use crate::unit::Raw;
-use crate::ws::WsSubscription;
+use crate::work::{generate_work, receive_difficulty, send_difficulty};
+use crate::ws::{ws_watch, WsSubscription};
 
 pub struct Account {
-    seed: Seed,
+    seed: Option<Seed>,
     index: u32,
-    private_key: Hash,
+    private_key: Option<Hash>,
     public_key: PublicKey,
     address: Address,
+    /// Balance after the last block we published, new blocks are built on it
     balance: Raw,
+    /// Last block we published, which can be ahead of the confirmed frontier
     frontier: String,
     frontier_confirmed: bool,
+    confirmed_balance: Raw,
+    confirmed_frontier: String,
     confirmation_height: u64,
+    /// Published blocks that are not confirmed yet, oldest first
+    unconfirmed: VecDeque<PublishedBlock>,
     rpc_tx: Sender<RpcCommand>,
     representative: Address,
+    /// Recently received send block hashes, oldest first
+    received: VecDeque<String>,
 }
 
+/// A block we published, kept until it is confirmed so it can be published again or rebuilt
+struct PublishedBlock {
+    hash: String,
+    block: Block,
+    subtype: SUBTYPE,
+    /// amount sent or received
+    amount: Raw,
+    /// destination address for sends, source block hash for receives
+    link: String,
+    published: Instant,
+}
+
+/// Number of received send block hashes remembered to skip duplicate confirmations
+const RECEIVED_HISTORY: usize = 1000;
+
+/// Node error for a block hash it does not have
+const BLOCK_NOT_FOUND: &str = "Block not found";
+/// Node error for processing a block it already has
+const OLD_BLOCK: &str = "Old block";
+
 impl Account {
     pub fn new(
         seed: Seed,
         index: u32,
+        representative: Address,
         rpc_tx: Sender<RpcCommand>,
         ws_tx: Sender<WsSubscription>,
-    ) -> Arc<Mutex<Self>> {
+    ) -> Result<Arc<Mutex<Self>>, String> {
         // Derive private key from seed
         let private_key = Self::derive_private_key(seed, index);
 
         // Derive public key from private key
         let public_key = Self::derive_public_key(private_key);
 
+        Self::open(Some(seed), index, Some(private_key), public_key, representative, rpc_tx, ws_tx)
+    }
+
+    /// Create a watch-only account from an address
+    /// It tracks balance and incoming payments, but can't sign any blocks
+    pub fn watch(
+        address: &str,
+        index: u32,
+        rpc_tx: Sender<RpcCommand>,
+        ws_tx: Sender<WsSubscription>,
+    ) -> Result<Arc<Mutex<Self>>, String> {
+        let public_key = PublicKey::from_bytes(&address_to_public_key(address)?)
+            .map_err(|e| format!("Address {} has an invalid public key: {}", address, e))?;
+        Self::open(None, index, None, public_key, "".to_owned(), rpc_tx, ws_tx)
+    }
+
+    fn open(
+        seed: Option<Seed>,
+        index: u32,
+        private_key: Option<Hash>,
+        public_key: PublicKey,
+        representative: Address,
+        rpc_tx: Sender<RpcCommand>,
+        ws_tx: Sender<WsSubscription>,
+    ) -> Result<Arc<Mutex<Self>>, String> {
         // Derive address from public key
         let address = Self::derive_address(public_key);
 
-        // Fetch pending balance
-        let (balance, pending) = Self::fetch_balance(rpc_tx.clone(), &address);
+        // Fetch pending balance, the balance comes with the account info
+        let (_, pending) = Self::fetch_balance(rpc_tx.clone(), &address);
 
         // Fetch account info
         let account_info = Self::fetch_info(rpc_tx.clone(), &address);
-        let frontier = account_info.confirmed_frontier.unwrap();
-        let frontier_confirmed = account_info.frontier == frontier;
+        let confirmed_frontier = account_info.confirmed_frontier.clone().unwrap();
+        let confirmed_balance = account_info.confirmed_balance.clone().unwrap().parse::<Raw>().unwrap();
 
         let account = Self {
             seed,
@@ -63,28 +118,32 @@ impl Account {
             private_key,
             public_key,
             address: address.clone(),
-            balance,
-            frontier,
-            frontier_confirmed,
+            balance: account_info.balance,
+            frontier: account_info.frontier.clone(),
+            frontier_confirmed: account_info.frontier == confirmed_frontier,
+            confirmed_balance,
+            confirmed_frontier,
             confirmation_height: account_info
                 .confirmed_height
                 .unwrap()
                 .parse::<u64>()
                 .unwrap(),
+            unconfirmed: VecDeque::new(),
             rpc_tx: rpc_tx.clone(),
-            representative: CONFIG.representative.clone(),
+            representative,
+            received: VecDeque::new(),
         };
+        let watch_only = account.is_watch_only();
         let account = Arc::new(Mutex::new(account));
 
         // Watch account with websocket client, waits until ws subscription/update is acked
-        let (tx, rx) = mpsc::channel::<()>();
-        let sub = WsSubscription::new(account.clone(), tx);
-        ws_tx.send(sub).unwrap();
-        rx.recv().unwrap();
+        ws_watch(&ws_tx, account.clone())?;
 
         // If there is pending balance, receive it first
-        if pending > 0 {
-            account.lock().unwrap().receive_all();
+        if pending > 0 && watch_only {
+            info!("ACCOUNT {} (watch-only) has {} pending", address, pending);
+        } else if pending > 0 {
+            account.lock().unwrap().receive_all()?;
             Account::await_confirmation(rpc_tx.clone(), address.clone()).expect("Confirmation timeout");
         }
 
@@ -93,127 +152,353 @@ impl Account {
             Account::await_confirmation(rpc_tx, address).expect("Confirmation timeout");
         }
 
-        account
+        Ok(account)
     }
 
-    /// Receive all pending blocks
-    pub fn receive_all(&mut self) {
-        loop {
-            let pending_blocks = rpc_accounts_pending(
-                self.rpc_tx.clone(),
-                vec![self.address()],
-                100, // todo make setting?
-                Some(0),
-                Some(true),
-                None,
-                None,
-                Some(true),
-            )
-            .unwrap();
+    /// Check if the account has no private key, so it can't sign blocks
+    pub fn is_watch_only(&self) -> bool {
+        self.private_key.is_none()
+    }
+
+    /// Get the private key for signing blocks, fails for watch-only accounts
+    fn signing_key(&self) -> Result<String, String> {
+        self.private_key()
+            .ok_or(format!("Account {} is watch-only and can't sign blocks", self.address))
+    }
 
-            if pending_blocks.len() == 0 {
+    /// Receive all pending blocks, stops at the first one that can't be received
+    pub fn receive_all(&mut self) -> Result<(), String> {
+        loop {
+            let options = PendingOptions {
+                threshold: Some(0),
+                source: Some(true),
+                include_only_confirmed: Some(true),
+                ..Default::default()
+            };
+            let pending_blocks =
+                rpc_accounts_pending(self.rpc_tx.clone(), vec![self.address()], CONFIG.wallet.receive_batch_size, options)?;
+
+            if pending_blocks.is_empty() {
                 // We can stop receiving if theres no more pending blocks
-                return;
+                return Ok(());
             }
 
-            for (_, pending_blocks) in &pending_blocks {
+            for pending_blocks in pending_blocks.values() {
                 for send_block in pending_blocks {
-                    self.receive_block(send_block.hash.to_owned(), send_block.amount.unwrap());
+                    self.receive_block(send_block.hash.to_owned(), send_block.amount.unwrap())?;
                 }
             }
         }
     }
 
     /// Receive a send block to this account (and wait for confirmation?)
-    pub fn receive_block(&mut self, hash: String, amount: Raw) {
-        let receive_block: Block;
-        match (self.confirmation_height, self.frontier.as_str()) {
-            (0, "") => {
-                receive_block = rpc_block_create(
-                    self.rpc_tx.clone(),
-                    "0".to_owned(),
-                    self.address.clone(),
-                    self.representative.clone(),
-                    amount,
-                    hash,
-                    self.private_key(),
-                )
-                .unwrap();
-            }
-            (_, _) => {
-                receive_block = rpc_block_create(
-                    self.rpc_tx.clone(),
-                    self.frontier.clone(),
-                    self.address.clone(),
-                    self.representative.clone(),
-                    self.balance + amount,
-                    hash,
-                    self.private_key(),
-                )
-                .unwrap();
-            }
+    /// Watch-only accounts only report the incoming payment
+    /// A send that fails to be received stays pending, so the next catch-up receives it
+    pub fn receive_block(&mut self, hash: String, amount: Raw) -> Result<(), String> {
+        if self.is_watch_only() {
+            info!(
+                account:% = self.address, hash:% = hash;
+                "ACCOUNT {} (watch-only) incoming payment {} of {}",
+                self.address, hash, amount
+            );
+            return Ok(());
         }
-        let hash = rpc_process(self.rpc_tx.clone(), SUBTYPE::RECEIVE, receive_block).unwrap();
-        self.balance += amount;
-        self.frontier_confirmed = false;
-        self.frontier = hash;
+        // The same send block can be reported by both a confirmation and the catch-up after a reconnect
+        if self.received.contains(&hash) {
+            info!("ACCOUNT {} already received {}", self.address, hash);
+            return Ok(());
+        }
+        self.publish(SUBTYPE::RECEIVE, amount, hash.clone(), AuditReason::Receive)?;
+        if self.received.len() == RECEIVED_HISTORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(hash);
+        Ok(())
     }
 
-    /// Send nano (in raw) to a destination nano address
-    pub fn send(&mut self, amount: Raw, destination: Address) -> Result<(), String> {
+    /// Send nano (in raw) to a destination nano address, the reason is recorded in the audit log
+    pub fn send(&mut self, amount: Raw, destination: Address, reason: AuditReason) -> Result<(), String> {
+        self.signing_key()?;
         if self.balance < amount {
             Err(format!(
                 "Account {} insufficient balance ({}) to send {}",
                 self.address, self.balance, amount
             ))
         } else {
-            let block = rpc_block_create(
-                self.rpc_tx.clone(),
-                self.frontier.clone(),
-                self.address.clone(),
-                self.representative.clone(),
-                self.balance - amount,
-                destination,
-                self.private_key(),
-            )
-            .unwrap();
-
-            let hash = rpc_process(self.rpc_tx.clone(), SUBTYPE::SEND, block).unwrap();
-            self.balance -= amount;
-            self.frontier_confirmed = false;
-            self.frontier = hash;
+            self.publish(SUBTYPE::SEND, amount, destination, reason)?;
             Ok(())
         }
     }
 
-    /// Refresh account frontier, balance, and confirmation_height
-    pub fn update_info(&mut self) {
-        let account_info = Account::fetch_info(self.rpc_tx.clone(), &self.address);
+    /// Build and sign a send block on the local head without publishing it, e.g. for a dry run
+    pub fn build_send(&self, amount: Raw, destination: Address) -> Result<(String, Block), String> {
+        let key = self.signing_key()?;
+        if self.balance < amount {
+            return Err(format!(
+                "Account {} insufficient balance ({}) to send {}",
+                self.address, self.balance, amount
+            ));
+        }
+        self.create_block(self.previous(), self.balance - amount, destination, key, &send_difficulty())
+    }
+
+    /// Previous of the next block on the local head, 0 for an unopened account
+    fn previous(&self) -> String {
+        if self.frontier.is_empty() {
+            "0".to_owned()
+        } else {
+            self.frontier.clone()
+        }
+    }
 
-        self.frontier = account_info.confirmed_frontier.unwrap();
+    /// Build a send or receive block on the local head, publish it and move the local head to it
+    /// The link is the destination address for sends, or the source block hash for receives
+    /// Every published block is recorded in the audit log with the reason it was published
+    fn publish(&mut self, subtype: SUBTYPE, amount: Raw, link: String, reason: AuditReason) -> Result<String, String> {
+        let key = self.signing_key()?;
+        let (balance, difficulty) = match subtype {
+            SUBTYPE::RECEIVE => (self.balance + amount, receive_difficulty()),
+            _ => (self.balance - amount, send_difficulty()),
+        };
+        let (_, block) = self.create_block(self.previous(), balance, link.clone(), key, &difficulty)?;
+        let hash = rpc_process(self.rpc_tx.clone(), subtype, block.clone())?;
+        audit(AuditEntry::new(&hash, &self.address, Some(self.index), subtype, amount, &link, reason));
+        self.unconfirmed.push_back(PublishedBlock {
+            hash: hash.clone(),
+            block,
+            subtype,
+            amount,
+            link,
+            published: Instant::now(),
+        });
+        info!(account:% = self.address, hash:% = hash; "ACCOUNT {} published {:?} block {}", self.address, subtype, hash);
+        self.balance = balance;
+        self.frontier_confirmed = false;
+        self.frontier = hash.clone();
+        Ok(hash)
+    }
 
-        self.frontier_confirmed = account_info.frontier == self.frontier;
+    /// Build and sign a chain of send blocks on top of the current frontier without publishing them
+    /// Each block links to the previous one, so they have to be published in order
+    pub fn presign_sends(&self, payments: &[ScheduledPayment]) -> Result<Vec<PresignedBlock>, String> {
+        let key = self.signing_key()?;
+        let total: Raw = payments.iter().map(|p| p.amount).sum();
+        if self.balance < total {
+            return Err(format!(
+                "Account {} insufficient balance ({}) to presign {}",
+                self.address, self.balance, total
+            ));
+        }
+        if self.frontier.is_empty() {
+            return Err(format!("Account {} is not opened yet", self.address));
+        }
 
-        self.balance = account_info
+        let mut previous = self.frontier.clone();
+        let mut balance = self.balance;
+        let mut blocks = vec![];
+        for payment in payments {
+            balance -= payment.amount;
+            let (hash, block) = self.create_block(
+                previous.clone(),
+                balance,
+                payment.destination.clone(),
+                key.clone(),
+                &send_difficulty(),
+            )?;
+            info!(
+                "ACCOUNT presigned send {} of {} to {} due at {}",
+                hash, payment.amount, payment.destination, payment.due
+            );
+            blocks.push(PresignedBlock {
+                hash: hash.clone(),
+                previous,
+                payment: payment.clone(),
+                block,
+            });
+            previous = hash;
+        }
+        Ok(blocks)
+    }
+
+    /// Create a block signed locally, with work from the node, the work server or the local cpu
+    /// The key never leaves the wallet, block_create would send it to the node, which may be a hosted provider
+    /// The link is a block hash for receive blocks or a destination address for send blocks
+    fn create_block(
+        &self,
+        previous: String,
+        balance: Raw,
+        link: String,
+        key: String,
+        difficulty: &str,
+    ) -> Result<(String, Block), String> {
+        let (link, link_as_account) = if link.starts_with("nano_") || link.starts_with("xrb_") {
+            (bytes_to_hexstring(&address_to_public_key(&link)?), link)
+        } else {
+            (link.clone(), public_key_to_address(&hex_to_bytes(&link)?))
+        };
+        let previous = if previous == "0" { "0".repeat(64) } else { previous };
+        let mut block = Block::new(
+            self.address.clone(),
+            previous,
+            self.representative.clone(),
+            balance,
+            link,
+            link_as_account,
+            None,
+            None,
+            None,
+        );
+        block.work = Some(generate_work(self.rpc_tx.clone(), block.work_root()?, difficulty)?);
+        block.sign(&hexstring_to_bytes(&key))?;
+        let hash = block.hash()?;
+        info!("ACCOUNT created block {} locally", hash);
+        Ok((hash, block))
+    }
+
+    /// Refresh the confirmed frontier, balance and confirmation_height
+    /// The local head is kept while our published blocks wait for confirmation, it only follows the node
+    /// once they are all confirmed, or when the node's head is not our local head after a fork or rollback
+    pub fn update_info(&mut self) {
+        let account_info = Account::fetch_info(self.rpc_tx.clone(), &self.address);
+
+        self.confirmed_frontier = account_info.confirmed_frontier.unwrap();
+        self.confirmed_balance = account_info
             .confirmed_balance
             .unwrap()
             .parse::<Raw>()
             .unwrap();
-
         self.confirmation_height = account_info
             .confirmed_height
             .unwrap()
             .parse::<u64>()
             .unwrap();
+
+        // Confirming a block confirms all blocks before it
+        if let Some(i) = self.unconfirmed.iter().position(|p| p.hash == self.confirmed_frontier) {
+            self.unconfirmed.drain(..=i);
+        }
+
+        if self.unconfirmed.is_empty() {
+            self.frontier = account_info.frontier;
+            self.balance = account_info.balance;
+        } else if account_info.frontier != self.frontier {
+            warn!(
+                "ACCOUNT {} node head {} is not our local head {}",
+                self.address, account_info.frontier, self.frontier
+            );
+            self.check_published();
+        }
+        self.frontier_confirmed = self.frontier == self.confirmed_frontier;
     }
 
-    /// Get the account seed as a string
-    pub fn seed(&self) -> String {
-        bytes_to_hexstring(&self.seed)
+    /// Check that the node still has our unconfirmed blocks
+    /// Blocks it does not have are published again, and when that fails because the chain diverged from our
+    /// local view, they are rebuilt on the node's head
+    pub fn check_published(&mut self) {
+        let mut dropped = None;
+        for (i, published) in self.unconfirmed.iter().enumerate() {
+            match rpc_block_info(self.rpc_tx.clone(), &published.hash) {
+                Ok(_) => {}
+                Err(RpcError::Node(e)) if e == BLOCK_NOT_FOUND => {
+                    dropped = Some(i);
+                    break;
+                }
+                Err(e) => {
+                    warn!("ACCOUNT {} could not check block {}: {}", self.address, published.hash, e);
+                    return;
+                }
+            }
+        }
+        let dropped = match dropped {
+            Some(i) => i,
+            None => return,
+        };
+
+        for i in dropped..self.unconfirmed.len() {
+            let published = &self.unconfirmed[i];
+            warn!("ACCOUNT {} node dropped block {}, publishing it again", self.address, published.hash);
+            match rpc_process(self.rpc_tx.clone(), published.subtype, published.block.clone()) {
+                Ok(_) => {}
+                // The node has it after all, e.g. the lookup went to a node that was behind
+                Err(RpcError::Node(e)) if e == OLD_BLOCK => {
+                    info!("ACCOUNT {} node already has block {}", self.address, published.hash);
+                }
+                Err(RpcError::Node(e)) => {
+                    error!(
+                        "ACCOUNT {} chain diverged from our local view at {}: {}",
+                        self.address, published.hash, e
+                    );
+                    self.rebuild(i);
+                    return;
+                }
+                Err(e) => {
+                    warn!("ACCOUNT {} could not publish block {} again: {}", self.address, published.hash, e);
+                    return;
+                }
+            }
+        }
     }
 
-    /// Get the account seed as a bytes array
-    pub fn seed_as_bytes(&self) -> Seed {
+    /// Build the unconfirmed blocks from an index on again, on top of the node's head
+    /// Nothing is rebuilt unless the node confirms that none of the sends among them is on the ledger,
+    /// a send that is rebuilt while the original made it after all would pay twice
+    fn rebuild(&mut self, from: usize) {
+        for published in self.unconfirmed.iter().skip(from).filter(|p| p.subtype == SUBTYPE::SEND) {
+            match rpc_block_info(self.rpc_tx.clone(), &published.hash) {
+                Err(RpcError::Node(e)) if e == BLOCK_NOT_FOUND => {}
+                Ok(_) => {
+                    warn!(
+                        "ACCOUNT {} send {} is on the ledger, not rebuilding",
+                        self.address, published.hash
+                    );
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "ACCOUNT {} could not check send {}, not rebuilding: {}",
+                        self.address, published.hash, e
+                    );
+                    return;
+                }
+            }
+        }
+        let dropped: Vec<PublishedBlock> = self.unconfirmed.drain(from..).collect();
+        let account_info = Account::fetch_info(self.rpc_tx.clone(), &self.address);
+        self.frontier = account_info.frontier;
+        self.balance = account_info.balance;
+        for published in dropped {
+            if published.subtype == SUBTYPE::SEND && self.balance < published.amount {
+                error!(
+                    "ACCOUNT {} can not rebuild send {} of {}, the balance is only {}",
+                    self.address, published.hash, published.amount, self.balance
+                );
+                continue;
+            }
+            match self.publish(published.subtype, published.amount, published.link.clone(), AuditReason::Rebuild) {
+                Ok(hash) => warn!("ACCOUNT {} rebuilt block {} as {}", self.address, published.hash, hash),
+                // e.g. the source of a receive was already received by the block that won the fork
+                Err(e) => error!("ACCOUNT {} can not rebuild block {}: {}", self.address, published.hash, e),
+            }
+        }
+    }
+
+    /// Check if a published block is waiting for confirmation
+    pub fn is_unconfirmed(&self, hash: &str) -> bool {
+        self.unconfirmed.iter().any(|p| p.hash == hash)
+    }
+
+    /// Check if a published block is unconfirmed for longer than a duration
+    pub fn has_unconfirmed_older_than(&self, age: Duration) -> bool {
+        self.unconfirmed.front().is_some_and(|p| p.published.elapsed() > age)
+    }
+
+    /// Get the account seed as a string, None for watch-only accounts
+    pub fn seed(&self) -> Option<String> {
+        self.seed.map(|seed| bytes_to_hexstring(&seed))
+    }
+
+    /// Get the account seed as a bytes array, None for watch-only accounts
+    pub fn seed_as_bytes(&self) -> Option<Seed> {
         self.seed
     }
 
@@ -227,9 +512,9 @@ impl Account {
         self.address.clone()
     }
 
-    /// Get the account private key
-    pub fn private_key(&self) -> String {
-        bytes_to_hexstring(self.private_key.as_bytes())
+    /// Get the account private key, None for watch-only accounts
+    pub fn private_key(&self) -> Option<String> {
+        self.private_key.map(|key| bytes_to_hexstring(key.as_bytes()))
     }
 
     /// Get the account public key
@@ -242,11 +527,31 @@ impl Account {
         self.balance
     }
 
+    /// Get the account frontier, the last block we published
+    pub fn frontier(&self) -> String {
+        self.frontier.clone()
+    }
+
     /// Check if the account frontier block is confirmed
     pub fn frontier_confirmed(&self) -> bool {
         self.frontier_confirmed
     }
 
+    /// Get the confirmed account balance, which excludes blocks we published that are not confirmed yet
+    pub fn confirmed_balance(&self) -> Raw {
+        self.confirmed_balance
+    }
+
+    /// Get the confirmed account frontier
+    pub fn confirmed_frontier(&self) -> String {
+        self.confirmed_frontier.clone()
+    }
+
+    /// Get the hashes of published blocks that are not confirmed yet, oldest first
+    pub fn unconfirmed(&self) -> Vec<String> {
+        self.unconfirmed.iter().map(|p| p.hash.clone()).collect()
+    }
+
     /// Get the account confirmation height
     pub fn confirmation_height(&self) -> u64 {
         self.confirmation_height
@@ -276,30 +581,7 @@ impl Account {
 
     /// Derive address from public key
     pub fn derive_address(public_key: PublicKey) -> Address {
-        // Code based on Feeless project implementation
-        let mut address = String::with_capacity(65);
-        address.push_str("nano_");
-
-        const PKP_LEN: usize = 4 + 8 * 32;
-        const PKP_CAPACITY: usize = 4 + 8 * 32 + 4;
-        let mut bits: BitVec<Msb0, u8> = BitVec::with_capacity(PKP_CAPACITY);
-        let pad: BitVec<Msb0, u8> = bitvec![Msb0, u8; 0; 4];
-        bits.extend_from_bitslice(&pad);
-        bits.extend_from_raw_slice(public_key.as_bytes());
-        debug_assert_eq!(bits.capacity(), PKP_CAPACITY);
-        debug_assert_eq!(bits.len(), PKP_LEN);
-        let public_key_part = encode_nano_base_32(&bits);
-        address.push_str(&public_key_part);
-
-        let result = Params::new()
-            .hash_length(5)
-            .to_state()
-            .update(public_key.as_bytes())
-            .finalize();
-        let bits: BitVec<Msb0, u8> = BitVec::from_iter(result.as_bytes().iter().rev());
-        let checksum = encode_nano_base_32(&bits);
-        address.push_str(&checksum);
-        address
+        public_key_to_address(public_key.as_bytes())
     }
 
     /// Fetch balance and pending balance for address
@@ -326,27 +608,27 @@ impl Account {
                 confirmation_height: None,
                 confirmed_height: Some("0".to_owned()),
                 confirmation_height_frontier: None,
+                representative: None,
             },
         }
     }
 
     /// Block until account frontier block is confirmed (for sends) and all pending balance received or timeout expires
     pub fn await_confirmation(rpc_tx: Sender<RpcCommand>, address: Address) -> Result<(), String> {
-        // move confirmation timeout to config
-        let transaction_timeout =
-            CONFIG.transaction_timeout * 1000;
-        thread::sleep(Duration::from_millis(500));
+        let transaction_timeout = CONFIG.timeouts.transaction * 1000;
+        let poll_interval = CONFIG.timeouts.poll_interval;
+        thread::sleep(Duration::from_millis(poll_interval as u64));
         let info = Account::fetch_info(rpc_tx.clone(), &address);
         let mut balance = info.balance;
         let mut confirmed_balance = info.confirmed_balance.unwrap().parse::<Raw>().unwrap();
-        let mut total_duration: u32 = 500;
+        let mut total_duration: u32 = poll_interval;
         while balance != confirmed_balance {
             // todo non polling solution?
-            thread::sleep(Duration::from_millis(500));
+            thread::sleep(Duration::from_millis(poll_interval as u64));
             let info = Account::fetch_info(rpc_tx.clone(), &address);
             balance = info.balance;
             confirmed_balance = info.confirmed_balance.unwrap().parse::<Raw>().unwrap();
-            total_duration += 500;
+            total_duration += poll_interval;
             if total_duration >= transaction_timeout {
                 info!(
                     "ACCOUNT timed out awaiting frontier confirmation for {}",
@@ -363,19 +645,18 @@ impl Account {
 
     /// Block until an account has reached a given minimum balance and all pending balance received or timeout expires
     pub fn await_minimum_balance(rpc_tx: Sender<RpcCommand>, address: Address, desired_balance: Raw) -> Result<(), String> {
-        // move confirmation timeout to config
-        let transaction_timeout =
-            CONFIG.transaction_timeout * 1000;
-        thread::sleep(Duration::from_millis(500));
+        let transaction_timeout = CONFIG.timeouts.transaction * 1000;
+        let poll_interval = CONFIG.timeouts.poll_interval;
+        thread::sleep(Duration::from_millis(poll_interval as u64));
         let info = Account::fetch_info(rpc_tx.clone(), &address);
         let mut confirmed_balance = info.confirmed_balance.unwrap().parse::<Raw>().unwrap();
-        let mut total_duration: u32 = 500;
+        let mut total_duration: u32 = poll_interval;
         while confirmed_balance < desired_balance {
             // todo non polling solution?
-            thread::sleep(Duration::from_millis(500));
+            thread::sleep(Duration::from_millis(poll_interval as u64));
             let info = Account::fetch_info(rpc_tx.clone(), &address);
             confirmed_balance = info.confirmed_balance.unwrap().parse::<Raw>().unwrap();
-            total_duration += 500;
+            total_duration += poll_interval;
             if total_duration >= transaction_timeout {
                 info!(
                     "ACCOUNT timed out awaiting desired confirmed balance of {} for {}",
@@ -399,7 +680,8 @@ mod tests {
     use crate::common::{generate_random_seed_address, hexstring_to_bytes};
     use crate::config::CONFIG;
     use crate::logger::start_logger;
-    use crate::rpc::start_rpc;
+    use crate::rpc::{mock_node, start_rpc};
+    use serde_json::Value;
     use crate::ws::start_ws;
 
     #[test]
@@ -461,7 +743,7 @@ mod tests {
     fn account() {
         start_logger();
         let rpc_tx = start_rpc();
-        let ws_tx = start_ws();
+        let ws_tx = start_ws().unwrap();
         let (seed, address) = generate_random_seed_address();
 
         // Unopened account info
@@ -480,21 +762,24 @@ mod tests {
                 confirmation_height: None,
                 confirmed_height: Some("0".to_owned()),
                 confirmation_height_frontier: None,
+                representative: None,
             }
         );
 
         // Fund test account from a dev account with available balance
         let dev_account = Account::new(
-            hexstring_to_bytes(&CONFIG.wallet_seed),
+            hexstring_to_bytes(&CONFIG.wallet.seed),
             0,
+            CONFIG.wallet.representative.clone(),
             rpc_tx.clone(),
             ws_tx.clone(),
-        );
+        )
+        .unwrap();
         let dev_address = dev_account.lock().unwrap().address();
 
-        assert!(dev_account.lock().unwrap().send(1, address.clone()).is_ok());
-        assert!(dev_account.lock().unwrap().send(2, address.clone()).is_ok());
-        assert!(dev_account.lock().unwrap().send(3, address.clone()).is_ok());
+        assert!(dev_account.lock().unwrap().send(1, address.clone(), AuditReason::Direct).is_ok());
+        assert!(dev_account.lock().unwrap().send(2, address.clone(), AuditReason::Direct).is_ok());
+        assert!(dev_account.lock().unwrap().send(3, address.clone(), AuditReason::Direct).is_ok());
         assert!(Account::await_confirmation(rpc_tx.clone(), dev_address.clone()).is_ok());
 
         // Check unopened account
@@ -517,11 +802,19 @@ mod tests {
                 confirmation_height: None,
                 confirmed_height: Some("0".to_owned()),
                 confirmation_height_frontier: None,
+                representative: None,
             }
         );
 
         // Open new account & receive multiple blocks)
-        let account = Account::new(seed, 0, rpc_tx.clone(), ws_tx.clone());
+        let account = Account::new(
+            seed,
+            0,
+            CONFIG.wallet.representative.clone(),
+            rpc_tx.clone(),
+            ws_tx.clone(),
+        )
+        .unwrap();
         assert_eq!(
             Account::fetch_balance(rpc_tx.clone(), &address.clone()),
             (6, 0)
@@ -529,16 +822,24 @@ mod tests {
         assert!(account.lock().unwrap().frontier_confirmed());
         assert_eq!(account.lock().unwrap().balance(), 6);
         assert_eq!(account.lock().unwrap().confirmation_height(), 3);
-        assert_eq!(account.lock().unwrap().seed_as_bytes(), seed);
+        assert_eq!(account.lock().unwrap().seed_as_bytes(), Some(seed));
         assert_eq!(account.lock().unwrap().address(), address);
         assert_eq!(account.lock().unwrap().index(), 0);
-        assert_eq!(account.lock().unwrap().representative(), CONFIG.representative);
+        assert_eq!(account.lock().unwrap().representative(), CONFIG.wallet.representative);
 
         // Send more than available balance
-        assert!(account.lock().unwrap().send(7, address.clone()).is_err());
+        assert!(account.lock().unwrap().send(7, address.clone(), AuditReason::Direct).is_err());
+
+        // Watch-only account tracks the balance but can't send
+        let watched = Account::watch(&address, 0, rpc_tx.clone(), ws_tx.clone()).unwrap();
+        assert!(watched.lock().unwrap().is_watch_only());
+        assert_eq!(watched.lock().unwrap().address(), address);
+        assert_eq!(watched.lock().unwrap().balance(), 6);
+        assert_eq!(watched.lock().unwrap().seed(), None);
+        assert!(watched.lock().unwrap().send(1, dev_address.clone(), AuditReason::Direct).is_err());
 
         // Receive single block
-        assert!(dev_account.lock().unwrap().send(1, address.clone()).is_ok());
+        assert!(dev_account.lock().unwrap().send(1, address.clone(), AuditReason::Direct).is_ok());
         assert!(Account::await_confirmation(rpc_tx.clone(), dev_address.clone()).is_ok());
         assert!(Account::await_minimum_balance(rpc_tx.clone(), address.clone(), 7).is_ok());
         assert!(account.lock().unwrap().frontier_confirmed());
@@ -546,10 +847,171 @@ mod tests {
         assert_eq!(account.lock().unwrap().confirmation_height(), 4);
 
         // Refund to dev account
-        assert!(account.lock().unwrap().send(7, dev_address).is_ok());
+        assert!(account.lock().unwrap().send(7, dev_address, AuditReason::Direct).is_ok());
         assert!(Account::await_confirmation(rpc_tx.clone(), address).is_ok());
         assert!(account.lock().unwrap().frontier_confirmed());
         assert_eq!(account.lock().unwrap().balance(), 0);
         assert_eq!(account.lock().unwrap().confirmation_height(), 5);
     }
+
+    const HEAD: &str = "80A6745762493FA21A22718ABFA4F635656A707B48B3324198AC7F3938DE6D4F";
+    const SENT: &str = "8D3AB98B301224253750D448B4BD997132400CEDD0A8432F775724F2D9821C72";
+    const REBUILT: &str = "5D1AA8A45F8736519D707FCB375976A7F9AF795091021D7E9C7548D6F45DD8D5";
+
+    /// Account with one unconfirmed send of 5 to itself, on a node that answers each action from a queue of
+    /// responses and records the actions it was asked for
+    fn account_with_send(responses: Vec<(&'static str, Value)>) -> (Account, Arc<Mutex<Vec<String>>>) {
+        let actions = Arc::new(Mutex::new(vec![]));
+        let asked = actions.clone();
+        let mut responses = VecDeque::from(responses);
+        let rpc_tx = mock_node(move |cmd| {
+            let action = cmd["action"].as_str().unwrap().to_owned();
+            asked.lock().unwrap().push(action.clone());
+            let i = responses.iter().position(|(a, _)| *a == action)?;
+            responses.remove(i).map(|(_, response)| response)
+        });
+
+        let seed = [0u8; 32];
+        let private_key = Account::derive_private_key(seed, 0);
+        let public_key = Account::derive_public_key(private_key);
+        let address = Account::derive_address(public_key);
+        let block = Block::new(
+            address.clone(),
+            HEAD.to_owned(),
+            address.clone(),
+            10,
+            bytes_to_hexstring(&address_to_public_key(&address).unwrap()),
+            address.clone(),
+            None,
+            None,
+            Some("send".to_owned()),
+        );
+        let account = Account {
+            seed: Some(seed),
+            index: 0,
+            private_key: Some(private_key),
+            public_key,
+            address: address.clone(),
+            balance: 10,
+            frontier: SENT.to_owned(),
+            frontier_confirmed: false,
+            confirmed_balance: 15,
+            confirmed_frontier: HEAD.to_owned(),
+            confirmation_height: 1,
+            unconfirmed: VecDeque::from(vec![PublishedBlock {
+                hash: SENT.to_owned(),
+                block,
+                subtype: SUBTYPE::SEND,
+                amount: 5,
+                link: address.clone(),
+                published: Instant::now(),
+            }]),
+            rpc_tx,
+            representative: address,
+            received: VecDeque::new(),
+        };
+        (account, actions)
+    }
+
+    fn node_error(message: &str) -> Value {
+        serde_json::json!({ "error": message })
+    }
+
+    fn unconfirmed_hashes(account: &Account) -> Vec<String> {
+        account.unconfirmed.iter().map(|p| p.hash.clone()).collect()
+    }
+
+    #[test]
+    fn account_check_published_no_node_required() {
+        let block_info: Value = serde_json::from_str(include_str!("../tests/fixtures/rpc/block_info.json")).unwrap();
+        let account_info: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/rpc/account_info.json")).unwrap();
+        let work = serde_json::json!({
+            "work": "0000000000000000",
+            "difficulty": "fffffff800000000",
+            "multiplier": "1",
+            "hash": HEAD,
+        });
+
+        // Only a block the node does not have counts as dropped, any other error leaves it alone
+        let (mut account, actions) = account_with_send(vec![("block_info", node_error("Unable to parse json"))]);
+        account.check_published();
+        assert_eq!(*actions.lock().unwrap(), vec!["block_info"]);
+        assert_eq!(unconfirmed_hashes(&account), vec![SENT]);
+
+        // A block the node has after all is published
+        let (mut account, actions) = account_with_send(vec![
+            ("block_info", node_error(BLOCK_NOT_FOUND)),
+            ("process", node_error(OLD_BLOCK)),
+        ]);
+        account.check_published();
+        assert_eq!(*actions.lock().unwrap(), vec!["block_info", "process"]);
+        assert_eq!(unconfirmed_hashes(&account), vec![SENT]);
+
+        // A send that turns out to be on the ledger is never rebuilt, that would pay twice
+        let (mut account, actions) = account_with_send(vec![
+            ("block_info", node_error(BLOCK_NOT_FOUND)),
+            ("process", node_error("Fork")),
+            ("block_info", block_info),
+        ]);
+        account.check_published();
+        assert_eq!(*actions.lock().unwrap(), vec!["block_info", "process", "block_info"]);
+        assert_eq!(unconfirmed_hashes(&account), vec![SENT]);
+
+        // Nor when the node can't tell
+        let (mut account, actions) = account_with_send(vec![
+            ("block_info", node_error(BLOCK_NOT_FOUND)),
+            ("process", node_error("Fork")),
+        ]);
+        account.check_published();
+        assert_eq!(*actions.lock().unwrap(), vec!["block_info", "process", "block_info"]);
+        assert_eq!(unconfirmed_hashes(&account), vec![SENT]);
+
+        // A send the ledger does not have is rebuilt on the node's head
+        let (mut account, actions) = account_with_send(vec![
+            ("block_info", node_error(BLOCK_NOT_FOUND)),
+            ("process", node_error("Fork")),
+            ("block_info", node_error(BLOCK_NOT_FOUND)),
+            ("account_info", account_info),
+            ("work_generate", work),
+            ("process", serde_json::json!({ "hash": REBUILT })),
+        ]);
+        account.check_published();
+        assert_eq!(
+            *actions.lock().unwrap(),
+            vec!["block_info", "process", "block_info", "account_info", "work_generate", "process"]
+        );
+        assert_eq!(unconfirmed_hashes(&account), vec![REBUILT]);
+        assert_eq!(account.frontier, REBUILT);
+        assert_eq!(account.balance, 11999999999999999918751838129509869131 - 5);
+    }
+
+    #[test]
+    fn account_receive_block_no_node_required() {
+        let work = serde_json::json!({
+            "work": "0000000000000000",
+            "difficulty": "fffffff800000000",
+            "multiplier": "1",
+            "hash": SENT,
+        });
+
+        // A send that could not be received is tried again, only a published receive counts
+        let (mut account, actions) = account_with_send(vec![
+            ("work_generate", work.clone()),
+            ("process", node_error("Fork")),
+            ("work_generate", work),
+            ("process", serde_json::json!({ "hash": REBUILT })),
+        ]);
+        assert!(account.receive_block(HEAD.to_owned(), 3).is_err());
+        assert!(account.received.is_empty());
+        assert_eq!(account.balance, 10);
+        account.receive_block(HEAD.to_owned(), 3).unwrap();
+        assert_eq!(account.received, vec![HEAD]);
+        assert_eq!(account.balance, 13);
+
+        // The same send reported again is not received twice
+        account.receive_block(HEAD.to_owned(), 3).unwrap();
+        assert_eq!(*actions.lock().unwrap(), vec!["work_generate", "process", "work_generate", "process"]);
+        assert_eq!(unconfirmed_hashes(&account), vec![SENT, REBUILT]);
+    }
 }
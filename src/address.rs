@@ -1 +1,70 @@
+use bitvec::prelude::*;
+use blake2b_simd::Params;
+use std::iter::FromIterator;
+
+use crate::common::{decode_nano_base_32, encode_nano_base_32};
+
 pub type Address = String;
+
+/// Decode a nano address into its public key bytes, verifying the checksum
+pub fn address_to_public_key(address: &str) -> Result<[u8; 32], String> {
+    let encoded = if let Some(rest) = address.strip_prefix("nano_") {
+        rest
+    } else if let Some(rest) = address.strip_prefix("xrb_") {
+        rest
+    } else {
+        return Err(format!("Address {} does not start with nano_ or xrb_", address));
+    };
+    if encoded.len() != 60 || !encoded.is_ascii() {
+        return Err(format!("Address {} has an invalid length", address));
+    }
+
+    // 52 characters encode 4 padding bits and the 256 bit public key, the last 8 encode the checksum
+    let key_bytes = decode_nano_base_32(&format!("1111{}", &encoded[..52]))
+        .map_err(|e| format!("Address {} {}", address, e))?;
+    let checksum = decode_nano_base_32(&encoded[52..]).map_err(|e| format!("Address {} {}", address, e))?;
+
+    // Skip the 24 bits of prefix "1111" and padding, which decode to 3 zero bytes
+    if key_bytes[..3] != [0, 0, 0] {
+        return Err(format!("Address {} has invalid padding", address));
+    }
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(&key_bytes[3..]);
+
+    let hash = Params::new().hash_length(5).hash(&public_key);
+    let expected: Vec<u8> = hash.as_bytes().iter().rev().cloned().collect();
+    if checksum != expected {
+        return Err(format!("Address {} has an invalid checksum", address));
+    }
+    Ok(public_key)
+}
+
+/// Check whether a string is a valid nano address
+pub fn validate_address(address: &str) -> Result<(), String> {
+    address_to_public_key(address).map(|_| ())
+}
+
+/// Encode public key bytes as a nano address
+/// Also used for the link_as_account field, where the bytes can be any block hash
+pub fn public_key_to_address(public_key: &[u8; 32]) -> Address {
+    // Code based on Feeless project implementation
+    let mut address = String::with_capacity(65);
+    address.push_str("nano_");
+
+    const PKP_LEN: usize = 4 + 8 * 32;
+    const PKP_CAPACITY: usize = 4 + 8 * 32 + 4;
+    let mut bits: BitVec<Msb0, u8> = BitVec::with_capacity(PKP_CAPACITY);
+    let pad: BitVec<Msb0, u8> = bitvec![Msb0, u8; 0; 4];
+    bits.extend_from_bitslice(&pad);
+    bits.extend_from_raw_slice(public_key);
+    debug_assert_eq!(bits.capacity(), PKP_CAPACITY);
+    debug_assert_eq!(bits.len(), PKP_LEN);
+    let public_key_part = encode_nano_base_32(&bits);
+    address.push_str(&public_key_part);
+
+    let result = Params::new().hash_length(5).to_state().update(public_key).finalize();
+    let bits: BitVec<Msb0, u8> = BitVec::from_iter(result.as_bytes().iter().rev());
+    let checksum = encode_nano_base_32(&bits);
+    address.push_str(&checksum);
+    address
+}
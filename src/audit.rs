@@ -0,0 +1,447 @@
+use blake2b_simd::Params;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::address::Address;
+use crate::common::{bytes_to_hexstring, create_parent_dir, hexstring_to_bytes, write_atomic};
+use crate::config::CONFIG;
+use crate::rpc::{rpc_block_info, RpcCommand, SUBTYPE};
+use crate::unit::Raw;
+
+/// Audit log location, shared by all wallets
+#[cfg(not(test))]
+const AUDIT_PATH: &str = "data/audit.jsonl";
+/// Blocks published by tests against a fake node stay out of the real audit log
+#[cfg(test)]
+const AUDIT_PATH: &str = "test_output/published.jsonl";
+
+/// Previous digest of the first entry
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+static AUDIT: Lazy<Mutex<AuditLog>> = Lazy::new(|| Mutex::new(AuditLog::new(AUDIT_PATH, audit_key())));
+
+/// Key of the audit digests, derived from the seed of the default wallet, or else of the first other wallet
+/// Without the key an edited log can't be chained again, setups with only watch-only wallets get an unkeyed log
+fn audit_key() -> [u8; 32] {
+    let seed = Some(&CONFIG.wallet.seed)
+        .into_iter()
+        .chain(CONFIG.wallets.values().map(|w| &w.seed))
+        .find(|seed| !seed.is_empty());
+    let seed = match seed {
+        Some(seed) => hexstring_to_bytes(seed),
+        None => {
+            warn!("AUDIT no wallet has a seed, the audit log is not keyed");
+            [0; 32]
+        }
+    };
+    let mut key = [0; 32];
+    key.copy_from_slice(Params::new().hash_length(32).key(&seed).personal(b"nano_pool_audkey").hash(b"").as_bytes());
+    key
+}
+
+/// Why the wallet published a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuditReason {
+    /// Send from the main account straight to the destination
+    Direct,
+    /// Send from the main account to a pool account for a pool payment
+    PoolFund,
+    /// Send from a pool account to the destination of a pool payment
+    PoolSend,
+    /// Send of a received pool payment from the pool account to the main account
+    PoolForward,
+    /// Send of a balance left on a pool account to the main account
+    Sweep,
+    /// Receive of a pending block
+    Receive,
+    /// Block built again on the node's head after the chain diverged
+    Rebuild,
+    /// Presigned scheduled payment published when due
+    Scheduled,
+    /// Block signed offline and published with publish_signed
+    Offline,
+}
+
+impl AuditReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditReason::Direct => "direct",
+            AuditReason::PoolFund => "pool-fund",
+            AuditReason::PoolSend => "pool-send",
+            AuditReason::PoolForward => "pool-forward",
+            AuditReason::Sweep => "sweep",
+            AuditReason::Receive => "receive",
+            AuditReason::Rebuild => "rebuild",
+            AuditReason::Scheduled => "scheduled",
+            AuditReason::Offline => "offline",
+        }
+    }
+}
+
+/// A published block, chained to the entry before it by the digest of that entry
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// unix timestamp in seconds
+    pub time: u64,
+    /// block hash
+    pub hash: String,
+    pub account: Address,
+    /// account index in its wallet, unknown for blocks signed offline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// send or receive
+    pub subtype: String,
+    pub amount: Raw,
+    /// destination address of a send, or source block hash of a receive
+    pub link: String,
+    pub reason: String,
+    /// digest of the previous entry
+    pub previous: String,
+    /// keyed blake2b digest of this entry with an empty digest
+    pub digest: String,
+}
+
+impl AuditEntry {
+    pub fn new(
+        hash: &str,
+        account: &str,
+        index: Option<u32>,
+        subtype: SUBTYPE,
+        amount: Raw,
+        link: &str,
+        reason: AuditReason,
+    ) -> Self {
+        let subtype = match subtype {
+            SUBTYPE::SEND => "send",
+            SUBTYPE::RECEIVE => "receive",
+            SUBTYPE::CHANGE => "change",
+        };
+        Self {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            hash: hash.to_owned(),
+            account: account.to_owned(),
+            index,
+            subtype: subtype.to_owned(),
+            amount,
+            link: link.to_owned(),
+            reason: reason.as_str().to_owned(),
+            previous: "".to_owned(),
+            digest: "".to_owned(),
+        }
+    }
+
+    /// Digest over all fields except the digest itself, keyed so an edited log can't be chained again
+    pub fn compute_digest(&self, key: &[u8; 32]) -> String {
+        let mut entry = self.clone();
+        entry.digest = "".to_owned();
+        let json = serde_json::to_vec(&entry).unwrap();
+        mac(key, &json)
+    }
+}
+
+fn mac(key: &[u8; 32], data: &[u8]) -> String {
+    bytes_to_hexstring(Params::new().hash_length(32).key(key).personal(b"nano_pool_audit").hash(data).as_bytes())
+}
+
+/// Number of entries and digest of the last one, stored next to the log so entries removed from its end are noticed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct AuditHead {
+    entries: usize,
+    digest: String,
+    /// keyed digest of the head itself
+    mac: String,
+}
+
+impl AuditHead {
+    fn new(entries: usize, digest: String, key: &[u8; 32]) -> Self {
+        let mac = mac(key, format!("{}:{}", entries, digest).as_bytes());
+        Self { entries, digest, mac }
+    }
+}
+
+/// Record a published block in the audit log, failures are logged since the block is published anyway
+pub fn audit(entry: AuditEntry) {
+    let hash = entry.hash.clone();
+    if let Err(e) = AUDIT.lock().unwrap().append(entry) {
+        error!("AUDIT could not record block {}: {}", hash, e);
+    }
+}
+
+/// Result of checking the audit log
+#[derive(Debug, PartialEq)]
+pub struct AuditReport {
+    pub entries: usize,
+    /// Broken chain links, altered entries and entries that don't match the ledger
+    pub problems: Vec<String>,
+}
+
+/// Recompute the chain of the audit log and check every entry against block_info of the node
+pub fn verify_audit(rpc_tx: Sender<RpcCommand>) -> Result<AuditReport, String> {
+    let log = AUDIT.lock().unwrap();
+    let entries = log.entries()?;
+    let mut problems = log.verify_chain(&entries);
+    drop(log);
+    for (i, entry) in entries.iter().enumerate() {
+        let info = match rpc_block_info(rpc_tx.clone(), &entry.hash) {
+            Ok(info) => info,
+            Err(e) => {
+                problems.push(format!("entry {}: block {} is not on the ledger: {}", i + 1, entry.hash, e));
+                continue;
+            }
+        };
+        let subtype = match info.subtype.as_deref() {
+            Some("open") => "receive",
+            Some(s) => s,
+            None => "",
+        };
+        let link = match subtype {
+            "send" => &info.contents.link_as_account,
+            _ => &info.contents.link,
+        };
+        let mismatches: Vec<String> = [
+            ("account", &info.block_account, &entry.account),
+            ("subtype", &subtype.to_owned(), &entry.subtype),
+            ("amount", &info.amount.to_string(), &entry.amount.to_string()),
+            ("link", link, &entry.link),
+        ]
+        .iter()
+        .filter(|(_, ledger, audited)| !ledger.eq_ignore_ascii_case(audited))
+        .map(|(field, ledger, audited)| format!("{} {} on the ledger, {} audited", field, ledger, audited))
+        .collect();
+        if !mismatches.is_empty() {
+            problems.push(format!("entry {}: block {} {}", i + 1, entry.hash, mismatches.join(", ")));
+        }
+    }
+    info!("AUDIT verified {} entries, {} problems", entries.len(), problems.len());
+    Ok(AuditReport {
+        entries: entries.len(),
+        problems,
+    })
+}
+
+/// Append-only log of every block the wallet published, one json entry per line
+/// Processes using the same log take turns through a lock on the file
+pub struct AuditLog {
+    path: String,
+    key: [u8; 32],
+}
+
+impl AuditLog {
+    pub fn new(path: &str, key: [u8; 32]) -> Self {
+        Self {
+            path: path.to_owned(),
+            key,
+        }
+    }
+
+    fn head_path(&self) -> String {
+        format!("{}.head", self.path)
+    }
+
+    /// All entries in order, fails on a line that is not a valid entry
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, String> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(vec![]),
+        };
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| format!("Audit log {}:{} is not valid: {}", self.path, i + 1, e))
+            })
+            .collect()
+    }
+
+    fn head(&self) -> Result<Option<AuditHead>, String> {
+        match fs::read(self.head_path()) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .map_err(|e| format!("Audit head {} is not valid: {}", self.head_path(), e)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Chain the entry to the last one and append it, the file is synced before returning
+    /// The last entry is read again under the file lock, since another process may have appended meanwhile
+    pub fn append(&self, mut entry: AuditEntry) -> Result<AuditEntry, String> {
+        create_parent_dir(&self.path)?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Could not open audit log {}: {}", self.path, e))?;
+        file.lock()
+            .map_err(|e| format!("Could not lock audit log {}: {}", self.path, e))?;
+
+        let last = match last_line(&mut file).map_err(|e| format!("Could not read audit log {}: {}", self.path, e))? {
+            Some(line) => Some(
+                serde_json::from_str::<AuditEntry>(&line)
+                    .map_err(|e| format!("Audit log {} last entry is not valid: {}", self.path, e))?,
+            ),
+            None => None,
+        };
+        let count = match (&last, self.head()?) {
+            (None, _) => 0,
+            (Some(last), Some(head)) if head.digest == last.digest => head.entries,
+            // The head is written after the entry, so it may be one behind after a crash
+            (Some(last), Some(head)) if head.digest == last.previous => head.entries + 1,
+            _ => self.entries()?.len(),
+        };
+        entry.previous = last.map(|e| e.digest).unwrap_or_else(|| GENESIS.to_owned());
+        entry.digest = entry.compute_digest(&self.key);
+
+        let line = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        writeln!(file, "{}", line)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| format!("Could not write audit log {}: {}", self.path, e))?;
+        let head = AuditHead::new(count + 1, entry.digest.clone(), &self.key);
+        write_atomic(&self.head_path(), &serde_json::to_vec(&head).map_err(|e| e.to_string())?)
+            .map_err(|e| format!("Could not write audit head {}: {}", self.head_path(), e))?;
+        info!("AUDIT {} {} {} of {} by {}", entry.reason, entry.subtype, entry.hash, entry.amount, entry.account);
+        Ok(entry)
+    }
+
+    /// Check that every entry has its own digest and links to the digest of the entry before it,
+    /// and that the log still ends with the entry recorded in the head
+    pub fn verify_chain(&self, entries: &[AuditEntry]) -> Vec<String> {
+        let mut problems = vec![];
+        let mut previous = GENESIS.to_owned();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.previous != previous {
+                problems.push(format!(
+                    "entry {}: block {} does not link to the previous entry, it was removed or reordered",
+                    i + 1,
+                    entry.hash
+                ));
+            }
+            if entry.compute_digest(&self.key) != entry.digest {
+                problems.push(format!("entry {}: block {} was altered", i + 1, entry.hash));
+            }
+            previous = entry.digest.clone();
+        }
+
+        match self.head() {
+            Err(e) => problems.push(e),
+            Ok(None) if entries.is_empty() => {}
+            Ok(None) => problems.push("the head of the log is missing".to_owned()),
+            Ok(Some(head)) if head != AuditHead::new(head.entries, head.digest.clone(), &self.key) => {
+                problems.push("the head of the log was altered".to_owned())
+            }
+            Ok(Some(head)) if head.entries != entries.len() || head.digest != previous => problems.push(format!(
+                "the log has {} entries but {} were recorded, entries were removed from or added to its end",
+                entries.len(),
+                head.entries
+            )),
+            Ok(Some(_)) => {}
+        }
+        problems
+    }
+}
+
+/// Read the last line of a file, starting from its end
+fn last_line(file: &mut File) -> std::io::Result<Option<String>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut window = 4096;
+    loop {
+        let start = len.saturating_sub(window);
+        file.seek(SeekFrom::Start(start))?;
+        let mut data = vec![];
+        Read::by_ref(file).take(len - start).read_to_end(&mut data)?;
+        let text = String::from_utf8_lossy(&data);
+        let text = text.trim_end();
+        match text.rfind('\n') {
+            Some(i) => return Ok(Some(text[i + 1..].to_owned())),
+            None if start == 0 => return Ok(Some(text.to_owned()).filter(|t| !t.trim().is_empty())),
+            None => window *= 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+
+    const ACCOUNT: &str = "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit";
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn entry(hash: char) -> AuditEntry {
+        AuditEntry::new(&hash.to_string().repeat(64), ACCOUNT, Some(0), SUBTYPE::SEND, 5, ACCOUNT, AuditReason::Direct)
+    }
+
+    #[test]
+    fn audit_chain_no_node_required() {
+        let path = &test_path("audit.jsonl");
+        let log = AuditLog::new(path, KEY);
+        let first = log.append(entry('A')).unwrap();
+        assert_eq!(first.previous, GENESIS);
+
+        // The chain continues after a restart
+        let log = AuditLog::new(path, KEY);
+        let receive = AuditEntry::new(&"B".repeat(64), ACCOUNT, Some(1), SUBTYPE::RECEIVE, 5, &"A".repeat(64), AuditReason::Receive);
+        let second = log.append(receive).unwrap();
+        assert_eq!(second.previous, first.digest);
+        let entries = log.entries().unwrap();
+        assert_eq!(entries, vec![first.clone(), second.clone()]);
+        assert!(log.verify_chain(&entries).is_empty());
+
+        // Altered, removed and reordered entries are detected
+        let mut altered = entries.clone();
+        altered[0].amount = 50;
+        assert_eq!(log.verify_chain(&altered), vec![format!("entry 1: block {} was altered", first.hash)]);
+        assert_eq!(
+            log.verify_chain(&entries[1..]),
+            vec![
+                format!(
+                    "entry 1: block {} does not link to the previous entry, it was removed or reordered",
+                    second.hash
+                ),
+                "the log has 1 entries but 2 were recorded, entries were removed from or added to its end".to_owned()
+            ]
+        );
+        assert_eq!(log.verify_chain(&[second.clone(), first.clone()]).len(), 3);
+
+        // Removing the last entry keeps the chain intact, but not the head
+        assert_eq!(
+            log.verify_chain(&entries[..1]),
+            vec!["the log has 1 entries but 2 were recorded, entries were removed from or added to its end"]
+        );
+
+        // Without the key the digests can't be recomputed
+        let other = AuditLog::new(path, [8; 32]);
+        assert_eq!(
+            other.verify_chain(&entries),
+            vec![
+                format!("entry 1: block {} was altered", first.hash),
+                format!("entry 2: block {} was altered", second.hash),
+                "the head of the log was altered".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn audit_shared_log_no_node_required() {
+        let path = &test_path("audit_shared.jsonl");
+        // Two processes appending to the same log keep a single chain
+        let a = AuditLog::new(path, KEY);
+        let b = AuditLog::new(path, KEY);
+        let first = a.append(entry('A')).unwrap();
+        let second = b.append(entry('B')).unwrap();
+        let third = a.append(entry('C')).unwrap();
+        assert_eq!(second.previous, first.digest);
+        assert_eq!(third.previous, second.digest);
+        let entries = a.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(b.verify_chain(&entries).is_empty());
+    }
+}
@@ -1,9 +1,20 @@
+use blake2b_simd::Params;
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey, Signature, Verifier};
 use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
-use crate::address::Address;
+use crate::address::{address_to_public_key, Address};
+use crate::common::bytes_to_hexstring;
 use crate::unit::Raw;
 
-#[derive(Serialize, Deserialize)]
+/// State block hashes are prefixed with 31 zero bytes and the state block type 6
+const STATE_BLOCK_PREAMBLE: [u8; 32] = {
+    let mut preamble = [0; 32];
+    preamble[31] = 6;
+    preamble
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Block {
     pub r#type: String,
     pub account: Address,
@@ -21,6 +32,8 @@ pub struct Block {
 }
 
 impl Block {
+    // One argument per field of a state block
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         account: Address,
         previous: String,
@@ -45,4 +58,91 @@ impl Block {
             subtype,
         }
     }
+
+    /// Calculate the block hash as a hex string
+    pub fn hash(&self) -> Result<String, String> {
+        Ok(bytes_to_hexstring(&self.hash_bytes()?))
+    }
+
+    fn hash_bytes(&self) -> Result<[u8; 32], String> {
+        let balance = self
+            .balance
+            .parse::<Raw>()
+            .map_err(|_| format!("Block balance {} is invalid", self.balance))?;
+        let hash = Params::new()
+            .hash_length(32)
+            .to_state()
+            .update(&STATE_BLOCK_PREAMBLE)
+            .update(&address_to_public_key(&self.account)?)
+            .update(&hex_to_bytes(&self.previous)?)
+            .update(&address_to_public_key(&self.representative)?)
+            .update(&balance.to_be_bytes())
+            .update(&hex_to_bytes(&self.link)?)
+            .finalize();
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(hash.as_bytes());
+        Ok(bytes)
+    }
+
+    /// Root used for work generation, the previous block or the account public key for open blocks
+    pub fn work_root(&self) -> Result<String, String> {
+        let previous = hex_to_bytes(&self.previous)?;
+        if previous == [0; 32] {
+            Ok(bytes_to_hexstring(&address_to_public_key(&self.account)?))
+        } else {
+            Ok(bytes_to_hexstring(&previous))
+        }
+    }
+
+    /// Sign the block hash with the private key of the block account
+    pub fn sign(&mut self, private_key: &[u8]) -> Result<(), String> {
+        let secret = SecretKey::from_bytes(private_key).map_err(|e| e.to_string())?;
+        let public = PublicKey::from(&secret);
+        if public.as_bytes() != &address_to_public_key(&self.account)? {
+            return Err(format!("Private key does not belong to {}", self.account));
+        }
+        let signature = ExpandedSecretKey::from(&secret).sign(&self.hash_bytes()?, &public);
+        self.signature = Some(bytes_to_hexstring(&signature.to_bytes()));
+        Ok(())
+    }
+
+    /// Verify the block signature against the block account
+    pub fn verify_signature(&self) -> Result<(), String> {
+        let signature = match &self.signature {
+            Some(s) => s,
+            None => return Err("Block is not signed".to_owned()),
+        };
+        let bytes = hex_to_vec(signature)?;
+        let signature = Signature::try_from(&bytes[..]).map_err(|e| e.to_string())?;
+        let public = PublicKey::from_bytes(&address_to_public_key(&self.account)?)
+            .map_err(|e| e.to_string())?;
+        public
+            .verify(&self.hash_bytes()?, &signature)
+            .map_err(|_| format!("Block signature is invalid for {}", self.account))
+    }
+}
+
+/// Parse a 32 byte hex string, where "0" is accepted for an empty previous block
+pub fn hex_to_bytes(hex: &str) -> Result<[u8; 32], String> {
+    if hex == "0" {
+        return Ok([0; 32]);
+    }
+    let bytes = hex_to_vec(hex)?;
+    if bytes.len() != 32 {
+        return Err(format!("{} is not a 32 byte hex string", hex));
+    }
+    let mut out = [0; 32];
+    out.copy_from_slice(&bytes);
+    Ok(out)
+}
+
+fn hex_to_vec(hex: &str) -> Result<Vec<u8>, String> {
+    // Checked before slicing by bytes, imported blocks may contain anything
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return Err(format!("{} is not a valid hex string", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("{} is not a valid hex string", hex)))
+        .collect()
 }
@@ -1,24 +1,265 @@
-use log::info;
-use std::io::{stdin, stdout, Write};
-use std::process;
-use std::sync::mpsc::Sender;
-use std::thread;
+use log::{error, info, warn};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, ExternalPrinter, Helper};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
+use crate::account::Account;
 use crate::address::Address;
-use crate::common;
-use crate::config::CONFIG;
-use crate::rpc::RpcCommand;
-use crate::unit::Raw;
+use crate::audit::verify_audit;
+use crate::common::{create_parent_dir, StorageKey};
+use crate::config::{CONFIG, DEFAULT_WALLET};
+use crate::contacts::{contacts, display_address, resolve_address};
+use crate::logger::set_console_printer;
+use crate::offline;
+use crate::registry::WalletRegistry;
+use crate::rpc::{rpc_block_count, rpc_telemetry, rpc_version, RpcCommand};
+use crate::schedule::{PresignedChain, ScheduledPayment};
+use crate::shutdown::shutting_down;
+use crate::unit::{nano_to_raw, raw_to_nano, Raw};
 use crate::wallet::Wallet;
 use crate::ws::WsSubscription;
 
-/// Start command line interface
-pub fn start_cli(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>) {
-    let seed = common::hexstring_to_bytes(&CONFIG.wallet_seed);
-    let wallet = Wallet::new(seed, rpc_tx.clone(), ws_tx.clone());
-    CliClient::start(wallet);
+/// Start command line interface, the handle finishes when the user exits
+pub fn start_cli(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>) -> Result<JoinHandle<()>, String> {
+    let wallets = WalletRegistry::from_config(rpc_tx.clone(), ws_tx)?;
+    Ok(CliClient::start(wallets, rpc_tx))
 }
 
+/// Exit codes of non-interactive commands
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+/// Run a single command line, e.g. "send_direct nano_1... 1000", and return its exit code
+pub fn run_once(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>, line: &str, json: bool) -> i32 {
+    let wallets = match WalletRegistry::from_config(rpc_tx.clone(), ws_tx) {
+        Ok(wallets) => wallets,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FAILED;
+        }
+    };
+    let mut cli = CliClient::new(wallets, rpc_tx, Output { json, prefix: "", sink: None });
+    // Sends are confirmed on the terminal, unless the command is piped from a script
+    if !io::stdin().is_terminal() {
+        cli.confirm = Confirm::Skip;
+    }
+    cli.execute_line(line)
+}
+
+/// Run the command lines of a file, skipping empty lines and # comments
+/// Stops at the first command that fails and returns its exit code
+pub fn run_batch(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>, path: &str, json: bool) -> i32 {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Failed to read batch file {}: {}", path, e);
+            return EXIT_FAILED;
+        }
+    };
+    let wallets = match WalletRegistry::from_config(rpc_tx.clone(), ws_tx) {
+        Ok(wallets) => wallets,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FAILED;
+        }
+    };
+    let mut cli = CliClient::new(wallets, rpc_tx, Output { json, prefix: "", sink: None });
+    cli.confirm = Confirm::Skip;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if shutting_down() {
+            eprintln!("Batch {} stopped before line {} to shut down", path, i + 1);
+            return EXIT_FAILED;
+        }
+        let code = cli.execute_line(line);
+        if code != EXIT_OK {
+            eprintln!("Batch {} stopped at line {}", path, i + 1);
+            return code;
+        }
+    }
+    EXIT_OK
+}
+
+/// Command line for a subcommand given as program arguments, e.g. ["nano_pool", "send-direct", "nano_1...", "1000"]
+/// Global options before the subcommand are skipped, the subcommand name may use dashes instead of underscores
+pub fn subcommand_line(args: &[String]) -> Option<String> {
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" | "--batch" => i += 2,
+            "--json" | "--dashboard" => i += 1,
+            _ => {
+                let mut line = vec![args[i].replace('-', "_")];
+                line.extend(args[i + 1..].iter().filter(|a| *a != "--json").cloned());
+                return Some(line.join(" "));
+            }
+        }
+    }
+    None
+}
+
+/// Presigned chain location of older versions, which only had the default wallet
+const LEGACY_PRESIGNED_PATH: &str = "data/presigned.bin";
+
+/// Encrypted presigned chain location for a wallet, a chain stored by an older version is moved there
+fn presigned_path(wallet: &str) -> Result<String, String> {
+    let path = format!("data/presigned_{}.bin", wallet);
+    if wallet == DEFAULT_WALLET {
+        migrate_file(LEGACY_PRESIGNED_PATH, &path)?;
+    }
+    Ok(path)
+}
+
+/// Move a file to its new location, unless a file is stored there already
+fn migrate_file(old: &str, new: &str) -> Result<(), String> {
+    if !Path::new(old).exists() {
+        return Ok(());
+    }
+    if Path::new(new).exists() {
+        warn!("CLI ignoring {}, {} is used instead", old, new);
+        return Ok(());
+    }
+    fs::rename(old, new).map_err(|e| format!("Could not move {} to {}: {}", old, new, e))?;
+    info!("CLI moved {} to {}", old, new);
+    Ok(())
+}
+
+/// Command history, kept across sessions
+const HISTORY_PATH: &str = "data/cli_history.txt";
+
+/// Command names for tab completion
+const COMMANDS: &[&str] = &[
+    "send_direct",
+    "send_payment",
+    "receive_payment",
+    "presign",
+    "resume_presigned",
+    "history",
+    "prepare_send",
+    "publish_signed",
+    "wallets",
+    "balance",
+    "address",
+    "pending",
+    "pool",
+    "account",
+    "node",
+    "derive",
+    "contact",
+    "verify_audit",
+    "exit",
+    "help",
+];
+
+/// Commands with a passphrase, they are not written to the history
+const SECRET_COMMANDS: &[&str] = &["presign", "resume_presigned"];
+
+/// Number of recently used addresses offered for tab completion
+const RECENT_ADDRESSES: usize = 100;
+
+/// Largest --timeout in seconds, about 49 days, so it also fits in milliseconds as u32
+const MAX_TIMEOUT: u32 = u32::MAX / 1000;
+
+/// Subcommands of the commands that have them, for tab completion
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("pool", &["list", "info"]),
+    ("node", &["status"]),
+    ("contact", &["add", "remove", "list"]),
+];
+
+/// Tab completion of command names, wallet names, account indices, contact labels and recently used addresses
+struct CliHelper {
+    wallets: Vec<String>,
+    /// Pool account indices of the default wallet
+    indices: Vec<u32>,
+    /// Address book labels, completed after @
+    labels: Vec<String>,
+    /// Recently used addresses, newest last
+    addresses: VecDeque<Address>,
+}
+
+impl CliHelper {
+    fn new(wallets: Vec<String>) -> Self {
+        Self {
+            wallets,
+            indices: vec![],
+            labels: vec![],
+            addresses: VecDeque::new(),
+        }
+    }
+
+    /// Remember the addresses used in a command line
+    fn remember(&mut self, line: &str) {
+        for word in line.split_whitespace() {
+            if word.starts_with("nano_") || word.starts_with("xrb_") {
+                self.addresses.retain(|a| a != word);
+                if self.addresses.len() == RECENT_ADDRESSES {
+                    self.addresses.pop_front();
+                }
+                self.addresses.push_back(word.to_owned());
+            }
+        }
+    }
+
+    /// Start of the word at the cursor and the candidates that complete it
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let before: Vec<&str> = line[..start].split_whitespace().collect();
+        let subcommands = SUBCOMMANDS.iter().find(|(c, _)| before.len() == 1 && before[0] == *c);
+        let candidates: Vec<String> = if before.is_empty() {
+            COMMANDS.iter().map(|c| c.to_string()).collect()
+        } else if before.last() == Some(&"--wallet") {
+            self.wallets.clone()
+        } else if let Some((_, subcommands)) = subcommands {
+            subcommands.iter().map(|c| c.to_string()).collect()
+        } else if before == ["pool", "info"] || before == ["account"] {
+            self.indices.iter().map(|i| i.to_string()).collect()
+        } else if word.starts_with('@') {
+            self.labels.iter().map(|l| format!("@{}", l)).collect()
+        } else {
+            self.addresses.iter().rev().cloned().collect()
+        };
+        let matches = candidates.into_iter().filter(|c| c.starts_with(word)).collect();
+        (start, matches)
+    }
+}
+
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
 /// CLI commands
 #[derive(Debug, PartialEq)]
 enum Command {
@@ -28,50 +269,340 @@ enum Command {
     SendPayment(Address, Raw),
     /// Receive payment via wallet account pool
     ReceivePayment(Raw),
+    /// Presign scheduled payments from a csv file, encrypted with a passphrase
+    Presign(String, String),
+    /// Resume publishing stored presigned payments
+    ResumePresigned(String),
+    /// List wallet names
+    Wallets,
+    /// Show the most recent main account blocks
+    History(usize),
+    /// Export an unsigned send block from the wallet account for offline signing
+    PrepareSend(Address, Raw, String),
+    /// Publish a block that was signed offline
+    PublishSigned(String),
     /// Exit program
     Exit,
+    /// Show the main account balance
+    Balance,
+    /// Show the main account address
+    Address,
+    /// Show the blocks ready to be received by the main account, largest first
+    Pending(usize),
+    /// List the pool accounts with their balance and status
+    PoolList,
+    /// Show the details of a pool account
+    PoolInfo(u32),
+    /// Show the details of an account by index, 0 is the main account
+    Account(u32),
+    /// Show the version, block counts and peers of the connected node
+    NodeStatus,
+    /// Derive the addresses of a range of account indices, end exclusive
+    Derive(u32, u32),
+    /// Add an address book entry with a label, address and note
+    ContactAdd(String, Address, String),
+    /// Remove an address book entry by label
+    ContactRemove(String),
+    /// List the address book
+    ContactList,
+    /// Check the audit log chain and its blocks against the ledger
+    VerifyAudit,
     /// Display help
     Help,
     /// Undefined command
     Undefined,
 }
 
+/// Prints command results as text, or as one json value per result for scripting
+#[derive(Clone)]
+struct Output {
+    json: bool,
+    /// Prefix of text lines, "<" in the interactive prompt
+    prefix: &'static str,
+    /// Receives the text lines instead of stdout, e.g. for the dashboard
+    sink: Option<Sender<String>>,
+}
+
+impl Output {
+    fn print(&self, text: &str, value: Value) {
+        if let Some(sink) = &self.sink {
+            for line in text.lines() {
+                let _ = sink.send(line.to_owned());
+            }
+        } else if self.json {
+            println!("{}", value);
+        } else if !text.is_empty() {
+            println!("{}{}", self.prefix, text.replace('\n', &format!("\n{}", self.prefix)));
+        }
+    }
+
+    fn error(&self, e: &str) {
+        if let Some(sink) = &self.sink {
+            let _ = sink.send(e.to_owned());
+        } else if self.json {
+            println!("{}", json!({ "error": e }));
+        } else if self.prefix.is_empty() {
+            eprintln!("{}", e);
+        } else {
+            println!("{}{}", self.prefix, e);
+        }
+    }
+}
+
+/// How sends are confirmed before they are published
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Confirm {
+    /// Ask on the terminal, unless --yes is given
+    Ask,
+    /// Only send with --yes, where there is no terminal to ask on
+    RequireYes,
+    /// Scripts confirm by running the command
+    Skip,
+}
+
 pub struct CliClient {
-    wallet: Wallet,
+    wallets: WalletRegistry,
+    rpc_tx: Sender<RpcCommand>,
+    /// Wallet the current command applies to
+    selected: String,
+    /// Timeout in seconds for the current command, the configured transaction timeout when not given
+    timeout: Option<u32>,
+    /// Build the send block of the current command without publishing it
+    dry_run: bool,
+    /// The current command is confirmed with --yes
+    yes: bool,
+    /// Send even when the current command exceeds the spending limits
+    override_limits: bool,
+    confirm: Confirm,
+    out: Output,
 }
 
 impl CliClient {
-    pub fn start(wallet: Wallet) {
-        let mut cli = Self { wallet };
+    fn new(wallets: WalletRegistry, rpc_tx: Sender<RpcCommand>, out: Output) -> Self {
+        Self {
+            wallets,
+            rpc_tx,
+            selected: DEFAULT_WALLET.to_owned(),
+            timeout: None,
+            dry_run: false,
+            yes: false,
+            override_limits: false,
+            confirm: Confirm::Ask,
+            out,
+        }
+    }
+
+    pub fn start(wallets: WalletRegistry, rpc_tx: Sender<RpcCommand>) -> JoinHandle<()> {
+        let mut cli = Self::new(wallets, rpc_tx, Output { json: false, prefix: "<", sink: None });
 
         thread::Builder::new()
             .name("cli".to_owned())
             .spawn(move || {
                 cli.run();
             })
-            .unwrap();
+            .unwrap()
+    }
+
+    /// Execute the command lines received on a worker thread until the sender is dropped, e.g. for the dashboard
+    /// The output is sent as text lines
+    pub fn start_worker(
+        wallets: WalletRegistry,
+        rpc_tx: Sender<RpcCommand>,
+        lines: Receiver<String>,
+        output: Sender<String>,
+    ) -> JoinHandle<()> {
+        let mut cli = Self::new(wallets, rpc_tx, Output { json: false, prefix: "", sink: Some(output) });
+        cli.confirm = Confirm::RequireYes;
+
+        thread::Builder::new()
+            .name("cli worker".to_owned())
+            .spawn(move || {
+                for line in lines {
+                    cli.execute_line(&line);
+                }
+            })
+            .unwrap()
     }
 
+    /// Read and execute commands until exit, Ctrl-D or end of input
+    /// Up/down recall earlier commands, Ctrl-R searches them and tab completes the word at the cursor
     fn run(&mut self) {
+        let mut editor: Editor<CliHelper, DefaultHistory> = Editor::new().expect("Failed to start line editor");
+        if let Err(e) = editor.load_history(HISTORY_PATH) {
+            info!("CLI no history loaded from {}: {}", HISTORY_PATH, e);
+        }
+        let mut helper = CliHelper::new(self.wallets.names());
+        helper.indices = self.pool_indices();
+        helper.labels = contacts().labels();
+        for line in editor.history().iter() {
+            helper.remember(line);
+        }
+        editor.set_helper(Some(helper));
+        // Log lines are printed above the prompt instead of through the line being typed
+        if let Ok(mut printer) = editor.create_external_printer() {
+            set_console_printer(Some(Box::new(move |line| {
+                let _ = printer.print(line);
+            })));
+        }
+
         loop {
-            let mut buf = String::new();
-            print!(">");
-            stdout().flush().unwrap();
-            stdin().read_line(&mut buf).unwrap();
-            let cmd = CliClient::process_input(&buf);
-            match self.execute_command(cmd) {
+            let buf = match editor.readline(">") {
+                Ok(buf) => buf,
+                Err(ReadlineError::Interrupted) => {
+                    println!("<Type exit or press Ctrl-D to quit");
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
                 Err(e) => {
-                    println!("<{}", e);
+                    error!("CLI read failed: {}", e);
+                    break;
                 }
-                Ok(_) => {}
+            };
+            if buf.trim().is_empty() {
+                continue;
+            }
+            let name = buf.split_whitespace().next().unwrap_or_default().to_lowercase();
+            if !SECRET_COMMANDS.contains(&name.as_str()) {
+                let _ = editor.add_history_entry(buf.trim());
+            }
+            if let Some(helper) = editor.helper_mut() {
+                helper.remember(&buf);
+            }
+
+            if CliClient::process_input(&CliClient::take_wallet_selector(&buf).1) == Command::Exit {
+                break;
+            }
+            self.execute_line(&buf);
+            // Saved after every command, a signal exits without returning here
+            CliClient::save_history(&mut editor);
+            // Commands may have added pool accounts or contacts
+            let indices = self.pool_indices();
+            if let Some(helper) = editor.helper_mut() {
+                helper.indices = indices;
+                helper.labels = contacts().labels();
+            }
+        }
+
+        set_console_printer(None);
+        CliClient::save_history(&mut editor);
+        info!("CLI exit");
+    }
+
+    fn save_history(editor: &mut Editor<CliHelper, DefaultHistory>) {
+        let _ = create_parent_dir(HISTORY_PATH);
+        if let Err(e) = editor.save_history(HISTORY_PATH) {
+            error!("CLI could not save history to {}: {}", HISTORY_PATH, e);
+        }
+    }
+
+    /// Pool account indices of the default wallet
+    fn pool_indices(&mut self) -> Vec<u32> {
+        match self.wallets.get(DEFAULT_WALLET) {
+            Ok(wallet) => wallet.pool().indices(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Execute a command line with its options and return the exit code, errors are printed
+    fn execute_line(&mut self, line: &str) -> i32 {
+        let (wallet, line) = CliClient::take_wallet_selector(line);
+        let (timeout, line) = CliClient::take_option(&line, "--timeout");
+        let (dry_run, line) = CliClient::take_flag(&line, "--dry-run");
+        let (yes, line) = CliClient::take_flag(&line, "--yes");
+        let (override_limits, line) = CliClient::take_flag(&line, "--override-limits");
+        self.dry_run = dry_run;
+        self.yes = yes;
+        self.override_limits = override_limits;
+        self.selected = wallet.unwrap_or_else(|| DEFAULT_WALLET.to_owned());
+        let cmd = CliClient::process_input(&line);
+        if cmd == Command::Undefined {
+            self.out.error("Invalid command; type 'help' to see valid commands");
+            return EXIT_USAGE;
+        }
+        self.timeout = match CliClient::parse_timeout(timeout, &cmd) {
+            Ok(timeout) => timeout,
+            Err(e) => {
+                self.out.error(&e);
+                return EXIT_USAGE;
             }
+        };
+        match self.execute_command(cmd) {
+            Ok(_) => EXIT_OK,
+            Err(e) => {
+                self.out.error(&e);
+                EXIT_FAILED
+            }
+        }
+    }
+
+    /// Parse the "--timeout <seconds>" option, only receive_payment waits for a payment
+    fn parse_timeout(timeout: Option<String>, cmd: &Command) -> Result<Option<u32>, String> {
+        let timeout = match timeout {
+            None => return Ok(None),
+            Some(timeout) => timeout,
+        };
+        if !matches!(cmd, Command::ReceivePayment(_)) {
+            return Err("--timeout is only valid for receive_payment".to_owned());
+        }
+        match timeout.parse::<u32>() {
+            Ok(t) if t > 0 && t <= MAX_TIMEOUT => Ok(Some(t)),
+            _ => Err(format!("--timeout requires a number of seconds between 1 and {}", MAX_TIMEOUT)),
+        }
+    }
+
+    /// Split a "--wallet <name>" selector from the input, anywhere after the command name
+    fn take_wallet_selector(buf: &str) -> (Option<String>, String) {
+        CliClient::take_option(buf, "--wallet")
+    }
+
+    /// Split an option with a value from the input, anywhere after the command name
+    fn take_option(buf: &str, option: &str) -> (Option<String>, String) {
+        let mut value = None;
+        let mut rest: Vec<&str> = vec![];
+        let mut split = buf.trim().split(' ');
+        while let Some(arg) = split.next() {
+            if arg == option {
+                value = split.next().map(|v| v.to_owned());
+            } else {
+                rest.push(arg);
+            }
+        }
+        (value, rest.join(" "))
+    }
+
+    /// Parse an amount in raw, or in nano with an XNO suffix, e.g. "1.5XNO"
+    fn parse_amount(amount: &str) -> Option<Raw> {
+        match amount.to_lowercase().strip_suffix("xno") {
+            Some(nano) => nano_to_raw(nano).ok(),
+            None => amount.parse::<Raw>().ok(),
+        }
+    }
+
+    /// Split a flag without a value from the input, anywhere after the command name
+    fn take_flag(buf: &str, flag: &str) -> (bool, String) {
+        let rest: Vec<&str> = buf.trim().split(' ').filter(|arg| *arg != flag).collect();
+        (rest.len() < buf.trim().split(' ').count(), rest.join(" "))
+    }
+
+    /// Parse an index range, "1..100" excludes and "1..=100" includes the end
+    fn parse_range(range: &str) -> Option<(u32, u32)> {
+        let (start, end) = range.split_once("..")?;
+        let start = start.parse::<u32>().ok()?;
+        let end = match end.strip_prefix('=') {
+            Some(end) => end.parse::<u32>().ok()?.checked_add(1)?,
+            None => end.parse::<u32>().ok()?,
+        };
+        if start < end {
+            Some((start, end))
+        } else {
+            None
         }
     }
 
     fn process_input(buf: &str) -> Command {
         let buf = buf.trim();
         let split: Vec<&str> = buf.split(' ').collect();
-        if split.len() == 0 {
+        if split.is_empty() {
             return Command::Undefined;
         }
         let cmd: &str = &split[0].to_lowercase();
@@ -80,9 +611,9 @@ impl CliClient {
                 if split.len() < 3 {
                     Command::Undefined
                 } else {
-                    match split[2].to_owned().parse::<Raw>() {
-                        Err(_) => Command::Undefined,
-                        Ok(raw) => Command::SendDirect(split[1].to_owned(), raw),
+                    match CliClient::parse_amount(split[2]) {
+                        None => Command::Undefined,
+                        Some(raw) => Command::SendDirect(split[1].to_owned(), raw),
                     }
                 }
             }
@@ -90,9 +621,9 @@ impl CliClient {
                 if split.len() < 3 {
                     Command::Undefined
                 } else {
-                    match split[2].to_owned().parse::<Raw>() {
-                        Err(_) => Command::Undefined,
-                        Ok(raw) => Command::SendPayment(split[1].to_owned(), raw),
+                    match CliClient::parse_amount(split[2]) {
+                        None => Command::Undefined,
+                        Some(raw) => Command::SendPayment(split[1].to_owned(), raw),
                     }
                 }
             }
@@ -100,9 +631,96 @@ impl CliClient {
                 if split.len() < 2 {
                     Command::Undefined
                 } else {
-                    match split[1].to_owned().parse::<Raw>() {
+                    match CliClient::parse_amount(split[1]) {
+                        None => Command::Undefined,
+                        Some(raw) => Command::ReceivePayment(raw),
+                    }
+                }
+            }
+            "presign" => {
+                if split.len() < 3 {
+                    Command::Undefined
+                } else {
+                    Command::Presign(split[1].to_owned(), split[2].to_owned())
+                }
+            }
+            "resume_presigned" => {
+                if split.len() < 2 {
+                    Command::Undefined
+                } else {
+                    Command::ResumePresigned(split[1].to_owned())
+                }
+            }
+            "prepare_send" => {
+                if split.len() < 4 {
+                    Command::Undefined
+                } else {
+                    match CliClient::parse_amount(split[2]) {
+                        None => Command::Undefined,
+                        Some(raw) => Command::PrepareSend(split[1].to_owned(), raw, split[3].to_owned()),
+                    }
+                }
+            }
+            "publish_signed" => {
+                if split.len() < 2 {
+                    Command::Undefined
+                } else {
+                    Command::PublishSigned(split[1].to_owned())
+                }
+            }
+            "wallets" => Command::Wallets,
+            "balance" => Command::Balance,
+            "address" => Command::Address,
+            "pending" => match split.get(1) {
+                None => Command::Pending(10),
+                Some(count) => match count.parse::<usize>() {
+                    Err(_) => Command::Undefined,
+                    Ok(count) => Command::Pending(count),
+                },
+            },
+            "pool" => match (split.get(1).map(|s| s.to_lowercase()).as_deref(), split.get(2)) {
+                (Some("list"), _) => Command::PoolList,
+                (Some("info"), Some(index)) => match index.parse::<u32>() {
+                    Err(_) => Command::Undefined,
+                    Ok(index) => Command::PoolInfo(index),
+                },
+                _ => Command::Undefined,
+            },
+            "account" => match split.get(1).map(|i| i.parse::<u32>()) {
+                Some(Ok(index)) => Command::Account(index),
+                _ => Command::Undefined,
+            },
+            "contact" => match (split.get(1).map(|s| s.to_lowercase()).as_deref(), split.get(2), split.get(3)) {
+                (Some("add"), Some(label), Some(address)) => {
+                    Command::ContactAdd(label.to_string(), address.to_string(), split[4..].join(" "))
+                }
+                (Some("remove"), Some(label), _) => Command::ContactRemove(label.to_string()),
+                (Some("list"), _, _) => Command::ContactList,
+                _ => Command::Undefined,
+            },
+            "verify_audit" => Command::VerifyAudit,
+            "node" => match split.get(1).map(|s| s.to_lowercase()).as_deref() {
+                Some("status") => Command::NodeStatus,
+                _ => Command::Undefined,
+            },
+            "derive" => {
+                // The range may follow a --range flag, as in the derive subcommand
+                let range = match split.get(1) {
+                    Some(&"--range") => split.get(2),
+                    range => range,
+                };
+                match range.and_then(|r| CliClient::parse_range(r)) {
+                    Some((start, end)) => Command::Derive(start, end),
+                    None => Command::Undefined,
+                }
+            }
+            "history" => {
+                if split.len() < 2 {
+                    Command::History(10)
+                } else {
+                    match split[1].parse::<usize>() {
                         Err(_) => Command::Undefined,
-                        Ok(raw) => Command::ReceivePayment(raw),
+                        Ok(count) => Command::History(count),
                     }
                 }
             }
@@ -113,38 +731,419 @@ impl CliClient {
     }
 
     fn execute_command(&mut self, cmd: Command) -> Result<(), String> {
-        info!("CLI exec {:?}", cmd);
+        match &cmd {
+            // Don't log passphrases
+            Command::Presign(path, _) => info!("CLI exec Presign({:?}) on {}", path, self.selected),
+            Command::ResumePresigned(_) => info!("CLI exec ResumePresigned on {}", self.selected),
+            _ => info!("CLI exec {:?} on {}", cmd, self.selected),
+        }
         match cmd {
-            Command::SendDirect(address, amount) => self.send_direct(address, amount),
-            Command::SendPayment(address, amount) => self.send_payment(address, amount),
+            Command::SendDirect(address, amount) => self.send(address, amount, false),
+            Command::SendPayment(address, amount) => self.send(address, amount, true),
             Command::ReceivePayment(amount) => self.receive_payment(amount),
-            Command::Exit => process::exit(0),
-            Command::Help => CliClient::print_help(),
+            Command::Presign(path, passphrase) => self.presign(path, passphrase),
+            Command::ResumePresigned(passphrase) => self.resume_presigned(passphrase),
+            Command::Wallets => self.print_wallets(),
+            Command::History(count) => self.print_history(count),
+            Command::PrepareSend(address, amount, path) => self.prepare_send(address, amount, path),
+            Command::PublishSigned(path) => self.publish_signed(path),
+            Command::Balance => self.print_balance(),
+            Command::Address => self.print_address(),
+            Command::Pending(count) => self.print_pending(count),
+            Command::PoolList => self.print_pool(),
+            Command::PoolInfo(index) => self.print_pool_account(index),
+            Command::Account(index) => self.print_account(index),
+            Command::NodeStatus => self.print_node_status(),
+            Command::ContactAdd(label, address, note) => self.add_contact(label, address, note),
+            Command::ContactRemove(label) => self.remove_contact(label),
+            Command::ContactList => self.print_contacts(),
+            Command::VerifyAudit => self.verify_audit(),
+            Command::Derive(start, end) => self.print_derived(start, end),
+            // Handled by the input loop, which saves the history before exiting
+            Command::Exit => Ok(()),
+            Command::Help => self.print_help(),
             Command::Undefined => {
                 Err("Invalid command; type 'help' to see valid commands".to_owned())
             }
         }
     }
 
-    fn send_direct(&mut self, address: Address, amount: Raw) -> Result<(), String> {
-        self.wallet.send_direct(amount, address);
+    /// Get the wallet selected for the current command
+    fn wallet(&mut self) -> Result<&mut Wallet, String> {
+        self.wallets.get(&self.selected)
+    }
+
+    /// Send from the wallet account directly or via the pool, after the user confirmed it
+    fn send(&mut self, address: Address, amount: Raw, via_pool: bool) -> Result<(), String> {
+        let address = resolve_address(&address)?;
+        let override_limits = self.override_limits;
+        if self.dry_run {
+            let (hash, block) = self.wallet()?.dry_run_send(amount, address, via_pool, override_limits)?;
+            self.out.print(
+                &format!("{}\nDry run, block {} was not published", offline::describe_block(&block), hash),
+                json!({ "dry_run": true, "hash": hash, "block": block }),
+            );
+            return Ok(());
+        }
+
+        let wallet = self.wallet()?;
+        wallet.check_send(amount, override_limits)?;
+        let question = format!(
+            "Send {} XNO to {}{}? The wallet balance will be {} XNO",
+            raw_to_nano(amount),
+            display_address(&address),
+            if via_pool { " via the pool" } else { "" },
+            raw_to_nano(wallet.balance() - amount)
+        );
+        if !self.confirm(&question)? {
+            return Err("Send cancelled".to_owned());
+        }
+        let wallet = self.wallet()?;
+        if via_pool {
+            wallet.send_payment(amount, address.clone(), override_limits)?;
+        } else {
+            wallet.send_direct(amount, address.clone(), override_limits)?;
+        }
+        self.out.print(
+            &format!("Sent {} to {}", amount, display_address(&address)),
+            json!({ "sent": amount.to_string(), "destination": address }),
+        );
         Ok(())
     }
 
-    fn send_payment(&mut self, address: Address, amount: Raw) -> Result<(), String> {
-        self.wallet.send_payment(amount, address)
+    /// Ask the user whether to go ahead, the question goes to stderr so it doesn't mix with json output
+    fn confirm(&self, question: &str) -> Result<bool, String> {
+        if self.yes || self.confirm == Confirm::Skip {
+            return Ok(true);
+        }
+        if self.confirm == Confirm::RequireYes {
+            return Err(format!("{}\nAdd --yes to confirm", question));
+        }
+        eprint!("{}{} [y/N] ", self.out.prefix, question);
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).map_err(|e| e.to_string())?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
     }
 
     fn receive_payment(&mut self, amount: Raw) -> Result<(), String> {
-        self.wallet.receive_payment(amount)
+        let timeout = self.timeout.unwrap_or(CONFIG.timeouts.transaction);
+        let out = self.out.clone();
+        let mut reserved = String::new();
+        self.wallet()?.receive_payment_with(amount, timeout, |address| {
+            reserved = address.clone();
+            out.print(
+                &format!("Waiting {} seconds for {} on {}", timeout, amount, address),
+                json!({ "address": address, "amount": amount.to_string(), "timeout": timeout }),
+            );
+        })?;
+        self.out.print(
+            &format!("Received {} on {}", amount, reserved),
+            json!({ "received": amount.to_string(), "address": reserved }),
+        );
+        Ok(())
+    }
+
+    fn presign(&mut self, path: String, passphrase: String) -> Result<(), String> {
+        let contents = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut payments = ScheduledPayment::parse_csv(&contents)?;
+        for payment in &mut payments {
+            payment.destination = resolve_address(&payment.destination)?;
+        }
+        let key = StorageKey::new(&passphrase)?;
+        let path = presigned_path(&self.selected)?;
+        let override_limits = self.override_limits;
+        self.wallet()?.presign_payments(payments, &path, key, override_limits)
+    }
+
+    fn resume_presigned(&mut self, passphrase: String) -> Result<(), String> {
+        let path = presigned_path(&self.selected)?;
+        let key = PresignedChain::key(&path, &passphrase)?;
+        self.wallet()?.resume_presigned(&path, key)
+    }
+
+    fn prepare_send(&mut self, address: Address, amount: Raw, path: String) -> Result<(), String> {
+        let address = resolve_address(&address)?;
+        let block = self.wallet()?.prepare_send(amount, address)?;
+        offline::export_block(&block, &path)?;
+        if !self.out.json {
+            self.out.print(&offline::describe_block(&block), Value::Null);
+            self.out.print(&offline::block_qr(&block)?, Value::Null);
+        }
+        self.out.print(
+            &format!("Unsigned block written to {}", path),
+            json!({ "block": block, "path": path }),
+        );
+        Ok(())
+    }
+
+    fn publish_signed(&mut self, path: String) -> Result<(), String> {
+        let block = offline::import_block(&path)?;
+        let override_limits = self.override_limits;
+        let hash = self.wallet()?.publish_signed(block, override_limits)?;
+        self.out.print(&format!("Published {}", hash), json!({ "hash": hash }));
+        Ok(())
+    }
+
+    fn print_wallets(&mut self) -> Result<(), String> {
+        let mut lines = vec![];
+        let mut wallets = vec![];
+        for name in self.wallets.names() {
+            let watch_only = self.wallets.get(&name)?.is_watch_only();
+            if watch_only {
+                lines.push(format!("{} (watch-only)", name));
+            } else {
+                lines.push(name.clone());
+            }
+            wallets.push(json!({ "name": name, "watch_only": watch_only }));
+        }
+        self.out.print(&lines.join("\n"), json!(wallets));
+        Ok(())
+    }
+
+    fn print_history(&mut self, count: usize) -> Result<(), String> {
+        let mut lines = vec![];
+        let mut entries = vec![];
+        for entry in self.wallet()?.history(count)? {
+            lines.push(format!(
+                "{} {} {} {} {}",
+                entry.height,
+                entry.r#type,
+                entry.amount,
+                display_address(&entry.account),
+                entry.hash
+            ));
+            entries.push(json!({
+                "height": entry.height,
+                "type": entry.r#type,
+                "amount": entry.amount.to_string(),
+                "account": entry.account,
+                "label": contacts().label(&entry.account),
+                "hash": entry.hash,
+            }));
+        }
+        self.out.print(&lines.join("\n"), json!(entries));
+        Ok(())
+    }
+
+    fn print_balance(&mut self) -> Result<(), String> {
+        let balance = self.wallet()?.balance();
+        self.out.print(&balance.to_string(), json!({ "balance": balance.to_string() }));
+        Ok(())
+    }
+
+    fn add_contact(&mut self, label: String, address: Address, note: String) -> Result<(), String> {
+        contacts().add(&label, address.clone(), note)?;
+        let label = label.trim_start_matches('@').to_lowercase();
+        self.out.print(
+            &format!("Added @{} {}", label, address),
+            json!({ "label": label, "address": address }),
+        );
+        Ok(())
+    }
+
+    fn remove_contact(&mut self, label: String) -> Result<(), String> {
+        let contact = contacts().remove(&label)?;
+        let label = label.trim_start_matches('@').to_lowercase();
+        self.out.print(
+            &format!("Removed @{} {}", label, contact.address),
+            json!({ "label": label, "address": contact.address }),
+        );
+        Ok(())
+    }
+
+    fn print_contacts(&mut self) -> Result<(), String> {
+        let mut lines = vec![];
+        let mut entries = vec![];
+        for (label, contact) in contacts().list() {
+            lines.push(format!("@{} {} {}", label, contact.address, contact.note).trim_end().to_owned());
+            entries.push(json!({ "label": label, "address": contact.address, "note": contact.note }));
+        }
+        self.out.print(&lines.join("\n"), json!(entries));
+        Ok(())
+    }
+
+    fn print_address(&mut self) -> Result<(), String> {
+        let address = self.wallet()?.address();
+        self.out.print(&address, json!({ "address": address }));
+        Ok(())
+    }
+
+    fn print_pending(&mut self, count: usize) -> Result<(), String> {
+        let mut lines = vec![];
+        let mut entries = vec![];
+        for block in self.wallet()?.pending(count)? {
+            let amount = block.amount.unwrap_or_default();
+            let source = block.source.unwrap_or_default();
+            lines.push(format!("{} {} {}", amount, source, block.hash));
+            entries.push(json!({ "amount": amount.to_string(), "source": source, "hash": block.hash }));
+        }
+        self.out.print(&lines.join("\n"), json!(entries));
+        Ok(())
+    }
+
+    fn print_pool(&mut self) -> Result<(), String> {
+        let mut lines = vec![];
+        let mut entries = vec![];
+        for (account, status) in self.wallet()?.pool().accounts() {
+            let acc = account.lock().unwrap();
+            lines.push(format!("{} {} {} {}", acc.index(), acc.address(), acc.balance(), status.as_str()));
+            entries.push(json!({
+                "index": acc.index(),
+                "address": acc.address(),
+                "balance": acc.balance().to_string(),
+                "status": status.as_str(),
+            }));
+        }
+        self.out.print(&lines.join("\n"), json!(entries));
+        Ok(())
+    }
+
+    fn print_pool_account(&mut self, index: u32) -> Result<(), String> {
+        let (account, status) = self
+            .wallet()?
+            .pool()
+            .account(index)
+            .ok_or(format!("No pool account with index {}", index))?;
+        self.print_account_details(&account, status.as_str());
+        Ok(())
+    }
+
+    fn print_account(&mut self, index: u32) -> Result<(), String> {
+        let wallet = self.wallet()?;
+        let (account, status) = match index {
+            0 => (wallet.account(), "main"),
+            _ => wallet
+                .pool()
+                .account(index)
+                .map(|(account, status)| (account, status.as_str()))
+                .ok_or(format!("No account with index {}", index))?,
+        };
+        self.print_account_details(&account, status);
+        Ok(())
+    }
+
+    /// Print the local and confirmed state of an account
+    fn print_account_details(&self, account: &Arc<Mutex<Account>>, status: &str) {
+        let acc = account.lock().unwrap();
+        let text = [
+            format!("index: {}", acc.index()),
+            format!("address: {}", acc.address()),
+            format!("status: {}", status),
+            format!("balance: {}", acc.balance()),
+            format!("confirmed balance: {}", acc.confirmed_balance()),
+            format!("frontier: {}", acc.frontier()),
+            format!("confirmed frontier: {}", acc.confirmed_frontier()),
+            format!("frontier confirmed: {}", acc.frontier_confirmed()),
+            format!("confirmation height: {}", acc.confirmation_height()),
+            format!("representative: {}", acc.representative()),
+            format!("unconfirmed blocks: {}", acc.unconfirmed().len()),
+        ];
+        self.out.print(
+            &text.join("\n"),
+            json!({
+                "index": acc.index(),
+                "address": acc.address(),
+                "status": status,
+                "balance": acc.balance().to_string(),
+                "confirmed_balance": acc.confirmed_balance().to_string(),
+                "frontier": acc.frontier(),
+                "confirmed_frontier": acc.confirmed_frontier(),
+                "frontier_confirmed": acc.frontier_confirmed(),
+                "confirmation_height": acc.confirmation_height(),
+                "representative": acc.representative(),
+                "unconfirmed": acc.unconfirmed(),
+            }),
+        );
+    }
+
+    fn print_node_status(&mut self) -> Result<(), String> {
+        let version = rpc_version(self.rpc_tx.clone())?;
+        let count = rpc_block_count(self.rpc_tx.clone())?;
+        let telemetry = rpc_telemetry(self.rpc_tx.clone())?;
+        let text = [
+            format!("node: {} ({} network)", version.node_vendor, version.network),
+            format!("rpc version: {}", version.rpc_version),
+            format!("blocks: {} ({} cemented, {} unchecked)", count.count, count.cemented, count.unchecked),
+            format!("peers: {}", telemetry.peer_count),
+            format!("active difficulty: {}", telemetry.active_difficulty),
+        ];
+        self.out.print(
+            &text.join("\n"),
+            json!({
+                "node_vendor": version.node_vendor,
+                "network": version.network,
+                "rpc_version": version.rpc_version,
+                "block_count": count.count,
+                "cemented_count": count.cemented,
+                "unchecked_count": count.unchecked,
+                "peer_count": telemetry.peer_count,
+                "active_difficulty": telemetry.active_difficulty,
+            }),
+        );
+        Ok(())
+    }
+
+    fn verify_audit(&mut self) -> Result<(), String> {
+        let report = verify_audit(self.rpc_tx.clone())?;
+        let mut lines = report.problems.clone();
+        if report.problems.is_empty() {
+            lines.push(format!(
+                "Audit log has {} entries, the chain is intact and all blocks match the ledger",
+                report.entries
+            ));
+        }
+        self.out.print(
+            &lines.join("\n"),
+            json!({ "entries": report.entries, "problems": report.problems }),
+        );
+        if report.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Audit log has {} problems in {} entries", report.problems.len(), report.entries))
+        }
+    }
+
+    fn print_derived(&mut self, start: u32, end: u32) -> Result<(), String> {
+        let addresses = self.wallet()?.derive_addresses(start..end)?;
+        let lines: Vec<String> = addresses.iter().map(|(i, a)| format!("{} {}", i, a)).collect();
+        let entries: Vec<Value> = addresses
+            .iter()
+            .map(|(i, a)| json!({ "index": i, "address": a }))
+            .collect();
+        self.out.print(&lines.join("\n"), json!(entries));
+        Ok(())
     }
 
-    fn print_help() -> Result<(), String> {
-        println!("<send_direct <nano_address> <amount_in_raw> -- Send raw from the wallet account directly to a nano address");
-        println!("<send_payment <nano_address> <amount_in_raw> -- Send raw from the wallet account via the account pool");
-        println!("<receive_payment <amount_in_raw> -- Receive a specific amo8unt of raw to the wallet account via the account pool");
-        println!("<exit -- Exit the program");
-        println!("<help -- Show this help text");
+    fn print_help(&self) -> Result<(), String> {
+        let help = [
+            "send_direct <nano_address> <amount_in_raw> -- Send raw from the wallet account directly to a nano address".to_owned(),
+            "send_payment <nano_address> <amount_in_raw> -- Send raw from the wallet account via the account pool".to_owned(),
+            "receive_payment <amount_in_raw> [--timeout <seconds>] -- Receive a specific amount of raw to the wallet account via the account pool".to_owned(),
+            "presign <csv_path> <passphrase> -- Presign payments (lines of address,amount_in_raw,due_unix_timestamp) from the wallet account and publish them when due".to_owned(),
+            "resume_presigned <passphrase> -- Resume publishing stored presigned payments".to_owned(),
+            "history [count] -- Show the most recent blocks of the wallet account (default 10)".to_owned(),
+            "prepare_send <nano_address> <amount_in_raw> <file> -- Write an unsigned send block from the wallet account to a file for offline signing".to_owned(),
+            "publish_signed <file> -- Attach work to an offline signed block and publish it".to_owned(),
+            "balance -- Show the wallet account balance".to_owned(),
+            "address -- Show the wallet account address".to_owned(),
+            "pending [count] -- Show the blocks ready to be received by the wallet account, largest first (default 10)".to_owned(),
+            "pool list -- List the pool accounts with their balance and whether they are free or reserved".to_owned(),
+            "pool info <index> -- Show the details of a pool account".to_owned(),
+            "account <index> -- Show the frontier, confirmation height and representative of an account, 0 is the wallet account".to_owned(),
+            "node status -- Show the version, block counts and peers of the connected node".to_owned(),
+            "derive <start>..<end> -- Show the addresses of a range of account indices, ..= includes the end".to_owned(),
+            "contact add <label> <nano_address> [note] -- Add an address to the address book, use it as @label wherever an address is accepted".to_owned(),
+            "contact remove <label> -- Remove an address from the address book".to_owned(),
+            "contact list -- List the address book".to_owned(),
+            "verify_audit -- Check that the audit log of published blocks is unaltered and matches the ledger".to_owned(),
+            "wallets -- List all wallets".to_owned(),
+            "exit -- Exit the program".to_owned(),
+            "help -- Show this help text".to_owned(),
+            "Amounts are in raw, or in nano with an XNO suffix, e.g. 1.5XNO".to_owned(),
+            format!("Add --wallet <name> to a command to use another wallet than {}", DEFAULT_WALLET),
+            "Sends ask for confirmation, add --yes to skip it, --dry-run to build the block without publishing it, or --override-limits to send more than the configured spending limits".to_owned(),
+        ];
+        self.out.print(&help.join("\n"), json!({ "help": help }));
         Ok(())
     }
 }
@@ -152,6 +1151,7 @@ impl CliClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::test_path;
 
     #[test]
     fn cli_process_input_no_node_required() {
@@ -220,6 +1220,89 @@ mod tests {
             Command::Undefined
         );
 
+        assert_eq!(
+            CliClient::process_input("presign payments.csv hunter2"),
+            Command::Presign("payments.csv".to_owned(), "hunter2".to_owned())
+        );
+        assert_eq!(
+            CliClient::process_input("presign payments.csv"),
+            Command::Undefined
+        );
+        assert_eq!(
+            CliClient::process_input("resume_presigned hunter2"),
+            Command::ResumePresigned("hunter2".to_owned())
+        );
+        assert_eq!(
+            CliClient::process_input("resume_presigned"),
+            Command::Undefined
+        );
+
+        assert_eq!(CliClient::process_input("wallets"), Command::Wallets);
+
+        assert_eq!(
+            CliClient::process_input("prepare_send Arg1 123 block.json"),
+            Command::PrepareSend("Arg1".to_owned(), 123, "block.json".to_owned())
+        );
+        assert_eq!(
+            CliClient::process_input("prepare_send Arg1 123"),
+            Command::Undefined
+        );
+        assert_eq!(
+            CliClient::process_input("prepare_send Arg1 Arg2 block.json"),
+            Command::Undefined
+        );
+        assert_eq!(
+            CliClient::process_input("publish_signed block.json"),
+            Command::PublishSigned("block.json".to_owned())
+        );
+        assert_eq!(CliClient::process_input("publish_signed"), Command::Undefined);
+
+        assert_eq!(CliClient::process_input("history"), Command::History(10));
+        assert_eq!(CliClient::process_input("history 5"), Command::History(5));
+        assert_eq!(CliClient::process_input("history Arg1"), Command::Undefined);
+
+        assert_eq!(CliClient::process_input("balance"), Command::Balance);
+        assert_eq!(CliClient::process_input("address"), Command::Address);
+        assert_eq!(CliClient::process_input("pending"), Command::Pending(10));
+        assert_eq!(CliClient::process_input("pending 3"), Command::Pending(3));
+        assert_eq!(CliClient::process_input("pending Arg1"), Command::Undefined);
+        assert_eq!(CliClient::process_input("pool list"), Command::PoolList);
+        assert_eq!(CliClient::process_input("pool info 4"), Command::PoolInfo(4));
+        assert_eq!(CliClient::process_input("pool info"), Command::Undefined);
+        assert_eq!(CliClient::process_input("pool"), Command::Undefined);
+        assert_eq!(CliClient::process_input("account 0"), Command::Account(0));
+        assert_eq!(CliClient::process_input("account Arg1"), Command::Undefined);
+        assert_eq!(CliClient::process_input("node status"), Command::NodeStatus);
+        assert_eq!(CliClient::process_input("verify_audit"), Command::VerifyAudit);
+
+        assert_eq!(
+            CliClient::process_input("send_payment @alice 1XNO"),
+            Command::SendPayment("@alice".to_owned(), 1_000_000_000_000_000_000_000_000_000_000)
+        );
+        assert_eq!(
+            CliClient::process_input("send_direct Arg1 0.5xno"),
+            Command::SendDirect("Arg1".to_owned(), 500_000_000_000_000_000_000_000_000_000)
+        );
+        assert_eq!(CliClient::process_input("send_direct Arg1 XNO"), Command::Undefined);
+        assert_eq!(
+            CliClient::process_input("contact add alice nano_1abc monthly rent"),
+            Command::ContactAdd("alice".to_owned(), "nano_1abc".to_owned(), "monthly rent".to_owned())
+        );
+        assert_eq!(
+            CliClient::process_input("contact add alice nano_1abc"),
+            Command::ContactAdd("alice".to_owned(), "nano_1abc".to_owned(), "".to_owned())
+        );
+        assert_eq!(CliClient::process_input("contact add alice"), Command::Undefined);
+        assert_eq!(CliClient::process_input("contact remove @alice"), Command::ContactRemove("@alice".to_owned()));
+        assert_eq!(CliClient::process_input("contact list"), Command::ContactList);
+        assert_eq!(CliClient::process_input("contact"), Command::Undefined);
+        assert_eq!(CliClient::process_input("node"), Command::Undefined);
+        assert_eq!(CliClient::process_input("derive 1..100"), Command::Derive(1, 100));
+        assert_eq!(CliClient::process_input("derive --range 1..=100"), Command::Derive(1, 101));
+        assert_eq!(CliClient::process_input("derive 5..5"), Command::Undefined);
+        assert_eq!(CliClient::process_input("derive 1-100"), Command::Undefined);
+        assert_eq!(CliClient::process_input("derive"), Command::Undefined);
+
         assert_eq!(CliClient::process_input("exit"), Command::Exit);
         assert_eq!(CliClient::process_input("Exit"), Command::Exit);
         assert_eq!(
@@ -244,4 +1327,108 @@ mod tests {
             Command::Undefined
         );
     }
+
+    #[test]
+    fn cli_subcommand_line_no_node_required() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(subcommand_line(&args(&["nano_pool"])), None);
+        assert_eq!(subcommand_line(&args(&["nano_pool", "--config", "c.toml"])), None);
+        assert_eq!(
+            subcommand_line(&args(&["nano_pool", "--json", "send-direct", "nano_1abc", "10"])),
+            Some("send_direct nano_1abc 10".to_owned())
+        );
+        assert_eq!(
+            subcommand_line(&args(&["nano_pool", "--config", "c.toml", "receive-payment", "10", "--timeout", "60", "--json"])),
+            Some("receive_payment 10 --timeout 60".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_option("receive_payment 10 --timeout 60", "--timeout"),
+            (Some("60".to_owned()), "receive_payment 10".to_owned())
+        );
+        let receive = Command::ReceivePayment(10);
+        assert_eq!(CliClient::parse_timeout(None, &receive), Ok(None));
+        assert_eq!(CliClient::parse_timeout(Some("60".to_owned()), &receive), Ok(Some(60)));
+        assert_eq!(CliClient::parse_timeout(Some(MAX_TIMEOUT.to_string()), &receive), Ok(Some(MAX_TIMEOUT)));
+        for timeout in ["0", "-1", "ten", "4294968", "99999999999"] {
+            assert!(CliClient::parse_timeout(Some(timeout.to_owned()), &receive).is_err());
+        }
+        assert_eq!(
+            CliClient::parse_timeout(Some("60".to_owned()), &Command::Balance),
+            Err("--timeout is only valid for receive_payment".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_flag("send_direct --dry-run nano_1abc 10", "--dry-run"),
+            (true, "send_direct nano_1abc 10".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_flag("send_direct nano_1abc 10", "--yes"),
+            (false, "send_direct nano_1abc 10".to_owned())
+        );
+    }
+
+    #[test]
+    fn cli_migrate_file_no_node_required() {
+        let (old, new) = (&test_path("presigned.bin"), &test_path("presigned_default.bin"));
+        fs::write(old, b"old").unwrap();
+        migrate_file(old, new).unwrap();
+        assert!(!Path::new(old).exists());
+        assert_eq!(fs::read(new).unwrap(), b"old");
+
+        // A file at the new location is kept
+        fs::write(old, b"older").unwrap();
+        migrate_file(old, new).unwrap();
+        assert_eq!(fs::read(new).unwrap(), b"old");
+        fs::remove_file(old).unwrap();
+        migrate_file(old, new).unwrap();
+        assert_eq!(fs::read(new).unwrap(), b"old");
+    }
+
+    #[test]
+    fn cli_complete_no_node_required() {
+        let mut helper = CliHelper::new(vec!["default".to_owned(), "shop".to_owned()]);
+        assert_eq!(
+            helper.candidates("send_", 5),
+            (0, vec!["send_direct".to_owned(), "send_payment".to_owned()])
+        );
+        assert_eq!(helper.candidates("history --wallet s", 18), (17, vec!["shop".to_owned()]));
+
+        helper.remember("send_direct nano_1abc 10");
+        helper.remember("send_payment nano_3xyz 10 --wallet shop");
+        helper.remember("send_direct nano_1abc 20");
+        assert_eq!(
+            helper.candidates("send_direct nano_", 17),
+            (12, vec!["nano_1abc".to_owned(), "nano_3xyz".to_owned()])
+        );
+        assert_eq!(helper.candidates("send_direct nano_3", 18), (12, vec!["nano_3xyz".to_owned()]));
+        assert_eq!(helper.candidates("exit now", 8), (5, vec![]));
+
+        helper.labels = vec!["alice".to_owned(), "bob".to_owned()];
+        assert_eq!(helper.candidates("send_payment @a", 15), (13, vec!["@alice".to_owned()]));
+        assert_eq!(helper.candidates("contact r", 9), (8, vec!["remove".to_owned()]));
+
+        helper.indices = vec![1, 2, 12];
+        assert_eq!(helper.candidates("pool i", 6), (5, vec!["info".to_owned()]));
+        assert_eq!(helper.candidates("pool info 1", 11), (10, vec!["1".to_owned(), "12".to_owned()]));
+        assert_eq!(helper.candidates("account ", 8), (8, vec!["1".to_owned(), "2".to_owned(), "12".to_owned()]));
+    }
+
+    #[test]
+    fn cli_take_wallet_selector_no_node_required() {
+        assert_eq!(
+            CliClient::take_wallet_selector("send_direct Arg1 123\n"),
+            (None, "send_direct Arg1 123".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_wallet_selector("send_direct --wallet shop Arg1 123"),
+            (Some("shop".to_owned()), "send_direct Arg1 123".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_wallet_selector("receive_payment 123 --wallet shop"),
+            (Some("shop".to_owned()), "receive_payment 123".to_owned())
+        );
+        assert_eq!(
+            CliClient::take_wallet_selector("receive_payment 123 --wallet"),
+            (None, "receive_payment 123".to_owned())
+        );
+    }
 }
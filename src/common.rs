@@ -1,6 +1,12 @@
+use argon2::Argon2;
 use bitvec::prelude::*;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
 use once_cell::sync::Lazy;
 use rand::Rng;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
 
 use crate::account::Account;
 use crate::address::Address;
@@ -24,21 +30,19 @@ pub fn bytes_to_hexstring(bytes: &[u8]) -> String {
 /// Convert hex string to bytes array of size 32, where each byte contains 2 hex digits.
 pub fn hexstring_to_bytes(hexstring: &str) -> Seed {
     let mut buf: Seed = [0; 32];
-    let mut i: usize = 0;
     let mut j_a: u8 = 0;
     let mut j_b: u8 = 0;
 
-    for (a, b) in hexstring.chars().zip(hexstring.chars().skip(1)).step_by(2) {
+    for (i, (a, b)) in hexstring.chars().zip(hexstring.chars().skip(1)).step_by(2).enumerate() {
         for (j, x) in HEX.iter().enumerate() {
-            if a.to_string() == x.to_string() {
+            if x.starts_with(a) {
                 j_a = j as u8;
             }
-            if b.to_string() == x.to_string() {
+            if x.starts_with(b) {
                 j_b = j as u8;
             }
         }
-        buf[i] = j_a << 4 | j_b;
-        i += 1;
+        buf[i] = (j_a << 4) | j_b;
     }
     buf
 }
@@ -64,15 +68,159 @@ pub fn encode_nano_base_32(bits: &BitSlice<Msb0, u8>) -> String {
     s
 }
 
+/// Decode a nano base 32 string into bytes, the number of characters times 5 must be divisible by 8
+pub fn decode_nano_base_32(s: &str) -> Result<Vec<u8>, String> {
+    if !(s.len() * ENCODING_BITS).is_multiple_of(8) {
+        return Err("has an invalid encoded length".to_owned());
+    }
+    let mut bytes = Vec::with_capacity(s.len() * ENCODING_BITS / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for c in s.chars() {
+        let value = match ALPHABET.find(c) {
+            Some(v) => v as u16,
+            None => return Err(format!("contains invalid character {}", c)),
+        };
+        buffer = (buffer << ENCODING_BITS) | value;
+        bits += ENCODING_BITS;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
 /// Generate a random seed and address (index 0)
 pub fn generate_random_seed_address() -> (Seed, Address) {
     let mut rng = rand::thread_rng();
     let mut seed: Seed = [0; 32];
-    for i in 0..32 {
-        seed[i] = rng.gen_range(0..16) << 4 | rng.gen_range(0..16);
+    for byte in seed.iter_mut() {
+        *byte = (rng.gen_range(0..16) << 4) | rng.gen_range(0..16);
     }
     let private_key = Account::derive_private_key(seed, 0);
     let public_key = Account::derive_public_key(private_key);
     let address = Account::derive_address(public_key);
     (seed, address)
 }
+
+/// Create the folder of a file when it does not exist yet
+pub fn create_parent_dir(path: &str) -> Result<(), String> {
+    match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() && !folder.exists() => {
+            fs::create_dir_all(folder).map_err(|e| format!("Could not create {}: {}", folder.display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replace a file with data, creating its folder when needed
+/// The data is written to a temporary file next to it and renamed over it once synced, so a crash leaves
+/// either the old or the new contents and never a partial file
+pub fn write_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    create_parent_dir(path)?;
+    let filepath = Path::new(path);
+    let tmp_path = format!("{}.tmp", path);
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, filepath)
+    };
+    write().map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        e.to_string()
+    })
+}
+
+/// Magic bytes and version of data encrypted with a storage key
+const STORAGE_MAGIC: &[u8; 4] = b"NPS1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = STORAGE_MAGIC.len() + SALT_LENGTH;
+
+/// Key for encrypting data stored on disk, derived from a passphrase with a random salt
+/// The salt is stored in front of the encrypted data, so the key can be derived again to decrypt it
+pub struct StorageKey {
+    salt: [u8; SALT_LENGTH],
+    key: [u8; 32],
+}
+
+impl StorageKey {
+    /// Derive a key for new data from a passphrase with a fresh salt
+    pub fn new(passphrase: &str) -> Result<Self, String> {
+        let mut salt = [0u8; SALT_LENGTH];
+        rand::thread_rng().fill(&mut salt);
+        Self::derive(passphrase, salt)
+    }
+
+    /// Derive the key data was encrypted with from a passphrase, using the salt stored with it
+    pub fn for_data(passphrase: &str, data: &[u8]) -> Result<Self, String> {
+        let salt = storage_header(data)?;
+        Self::derive(passphrase, salt)
+    }
+
+    /// Argon2id with its default cost, so guessing passphrases of stolen files is slow
+    fn derive(passphrase: &str, salt: [u8; SALT_LENGTH]) -> Result<Self, String> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Could not derive storage key: {}", e))?;
+        Ok(Self { salt, key })
+    }
+}
+
+/// Get the salt from the header of encrypted data
+fn storage_header(data: &[u8]) -> Result<[u8; SALT_LENGTH], String> {
+    if data.len() < HEADER_LENGTH + NONCE_LENGTH {
+        return Err("Encrypted data is too short".to_owned());
+    }
+    if &data[..STORAGE_MAGIC.len()] != STORAGE_MAGIC {
+        return Err("Encrypted data has an unknown format, it may have been stored by an older version".to_owned());
+    }
+    let mut salt = [0u8; SALT_LENGTH];
+    salt.copy_from_slice(&data[STORAGE_MAGIC.len()..HEADER_LENGTH]);
+    Ok(salt)
+}
+
+/// Encrypt bytes with ChaCha20-Poly1305
+/// Output layout is magic | salt | nonce | ciphertext with tag, the header is authenticated as well
+pub fn encrypt_bytes(key: &StorageKey, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill(&mut nonce);
+
+    let mut out = Vec::with_capacity(HEADER_LENGTH + NONCE_LENGTH + plaintext.len() + 16);
+    out.extend_from_slice(STORAGE_MAGIC);
+    out.extend_from_slice(&key.salt);
+    let ciphertext = ChaCha20Poly1305::new(&key.key.into())
+        .encrypt(&nonce.into(), Payload { msg: plaintext, aad: &out })
+        .map_err(|_| "Could not encrypt data".to_owned())?;
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Verify and decrypt bytes created by encrypt_bytes
+pub fn decrypt_bytes(key: &StorageKey, data: &[u8]) -> Result<Vec<u8>, String> {
+    let failed = || "Encrypted data failed authentication (wrong passphrase or tampered data)".to_owned();
+    if storage_header(data)? != key.salt {
+        return Err(failed());
+    }
+    let (header, body) = data.split_at(HEADER_LENGTH);
+    let (nonce, ciphertext) = body.split_at(NONCE_LENGTH);
+    ChaCha20Poly1305::new(&key.key.into())
+        .decrypt(nonce.into(), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| failed())
+}
+
+/// Path of a file in test_output for a test, anything a previous run stored there is removed
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> String {
+    let path = format!("test_output/{}", name);
+    for file in [path.clone(), format!("{}.tmp", path), format!("{}.head", path)] {
+        let _ = fs::remove_file(file);
+    }
+    fs::create_dir_all("test_output").unwrap();
+    path
+}
@@ -1,41 +1,1062 @@
-use serde::Deserialize;
-use std::{fs, env::VarError};
-use toml;
 use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::time::Duration;
+use toml::Value;
 
-use crate::address::Address;
+use crate::address::{validate_address, Address};
+use crate::unit::{nano_to_raw, Raw};
 
 lazy_static! {
     pub static ref CONFIG: Config = Config::new();
 }
 
+static CONFIG_PATH: OnceCell<String> = OnceCell::new();
 static TEST_CONFIG_PATH: &str = "config/config_test.toml";
 static PROD_CONFIG_PATH: &str = "config/config.toml";
+static ENV_PREFIX: &str = "NANO_POOL_";
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+/// Name of the wallet defined in the [wallet] and [pool] sections
+pub static DEFAULT_WALLET: &str = "default";
 
-#[derive(Deserialize)]
+/// Use another config file than the default one, has to be called before CONFIG is used
+pub fn set_config_path(path: &str) -> Result<(), String> {
+    CONFIG_PATH
+        .set(path.to_owned())
+        .map_err(|_| "Config path is already set".to_owned())
+}
+
+/// Load the config and check that the node ports are reachable, exit with all errors otherwise
+pub fn check_config() {
+    lazy_static::initialize(&CONFIG);
+    let errors = CONFIG.check_node_reachable();
+    if !errors.is_empty() {
+        Config::exit_with_errors(&Config::path(), &errors);
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
 pub struct Config {
-    pub wallet_seed: Address,
-    pub node_address: String,
-    pub node_rpc_port: u16,
-    pub node_ws_port: u16,
+    pub node: NodeConfig,
+    pub wallet: WalletConfig,
+    pub pool: PoolConfig,
+    pub timeouts: TimeoutConfig,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub api: ApiConfig,
+    /// additional wallets, each with their own seed and pool, keyed by name
+    pub wallets: BTreeMap<String, NamedWalletConfig>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// node IP address
+    pub address: String,
+    /// node rpc port
+    pub rpc_port: u16,
+    /// node websocket port
+    pub ws_port: u16,
+    /// number of concurrent rpc connections
+    pub rpc_workers: usize,
+    /// fallback rpc nodes as host:port or url, used when the node at address:rpc_port fails
+    pub rpc_fallbacks: Vec<String>,
+    /// number of times a request is retried when a node can not be reached
+    pub rpc_retries: u32,
+    /// rpc url scheme, http or https
+    pub rpc_scheme: String,
+    /// rpc url path, e.g. /proxy for hosted rpc providers
+    pub rpc_path: String,
+    /// bearer token sent in the Authorization header
+    pub rpc_token: String,
+    /// additional http headers, formatted as "Name: value"
+    pub rpc_headers: Vec<String>,
+    /// verify the tls certificate of https rpc nodes
+    pub rpc_tls_verify: bool,
+    /// rpc actions the node does not offer, detected automatically when left out
+    pub rpc_disabled_actions: Vec<String>,
+    /// external work server url, used when the node does not offer work_generate
+    pub work_url: String,
+    /// number of watched accounts above which all confirmations are subscribed and filtered locally, 0 never does
+    pub ws_accounts_limit: usize,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_owned(),
+            rpc_port: 7076,
+            ws_port: 7078,
+            rpc_workers: 4,
+            rpc_fallbacks: vec![],
+            rpc_retries: 3,
+            rpc_scheme: "http".to_owned(),
+            rpc_path: "".to_owned(),
+            rpc_token: "".to_owned(),
+            rpc_headers: vec![],
+            rpc_tls_verify: true,
+            rpc_disabled_actions: vec![],
+            work_url: "".to_owned(),
+            ws_accounts_limit: 5000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct WalletConfig {
+    /// wallet seed (required)
+    pub seed: String,
+    /// representative for all wallet accounts (required)
+    pub representative: Address,
+    /// number of pending blocks fetched at once when receiving
+    pub receive_batch_size: usize,
+    /// main account address of a watch-only wallet, instead of the seed
+    pub address: Address,
+    /// pool account addresses of a watch-only wallet
+    pub pool_addresses: Vec<Address>,
+}
+
+impl Default for WalletConfig {
+    fn default() -> Self {
+        Self {
+            seed: "".to_owned(),
+            representative: "".to_owned(),
+            receive_batch_size: 100,
+            address: "".to_owned(),
+            pool_addresses: vec![],
+        }
+    }
+}
+
+/// An additional wallet defined in a [wallets.<name>] section
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamedWalletConfig {
+    /// wallet seed (required)
+    pub seed: String,
+    /// representative for all accounts of this wallet (required)
     pub representative: Address,
-    pub transaction_timeout: u32,
+    /// index of the first pool account of this wallet
+    pub start_index: u32,
+    /// main account address of a watch-only wallet, instead of the seed
+    pub address: Address,
+    /// pool account addresses of a watch-only wallet
+    pub pool_addresses: Vec<Address>,
+}
+
+impl Default for NamedWalletConfig {
+    fn default() -> Self {
+        Self {
+            seed: "".to_owned(),
+            representative: "".to_owned(),
+            start_index: 1,
+            address: "".to_owned(),
+            pool_addresses: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// index of the first pool account, index 0 is the main wallet account
+    pub start_index: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self { start_index: 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// transaction timeout in seconds
+    pub transaction: u32,
+    /// interval in milliseconds for polling balances and confirmations
+    pub poll_interval: u32,
+    /// interval in milliseconds for polling the websocket connection
+    pub ws_poll_interval: u32,
+    /// rpc request timeout in seconds
+    pub rpc: u32,
+    /// rpc work_generate timeout in seconds
+    pub rpc_work: u32,
+    /// wait in milliseconds before retrying a failed rpc request, doubled for every retry
+    pub rpc_backoff: u32,
+    /// interval in seconds for checking the health of all rpc nodes
+    pub rpc_health_interval: u32,
+    /// wait in milliseconds before reconnecting the websocket, doubled for every failed attempt
+    pub ws_reconnect: u32,
+    /// maximum wait in milliseconds between websocket reconnect attempts
+    pub ws_reconnect_max: u32,
+    /// interval in seconds for pinging a quiet websocket connection
+    pub ws_ping_interval: u32,
+    /// timeout in milliseconds for the node to ack a websocket subscribe, update or unsubscribe
+    pub ws_ack: u32,
+    /// interval in seconds for checking that the node still has our unconfirmed blocks
+    pub unconfirmed_check: u32,
+    /// wait in seconds on shutdown for payments in progress to reach a safe point
+    pub shutdown: u32,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            transaction: 300,
+            poll_interval: 500,
+            ws_poll_interval: 100,
+            rpc: 30,
+            rpc_work: 120,
+            rpc_backoff: 500,
+            rpc_health_interval: 30,
+            ws_reconnect: 1000,
+            ws_reconnect_max: 60000,
+            ws_ping_interval: 30,
+            ws_ack: 5000,
+            unconfirmed_check: 60,
+            shutdown: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// maximum amount in nano of a single send from a wallet, 0 is no limit
+    pub transaction: String,
+    /// maximum amount in nano a wallet sends in any 24 hours, 0 is no limit
+    pub daily: String,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            transaction: "0".to_owned(),
+            daily: "0".to_owned(),
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// Transaction limit in raw, 0 is no limit
+    pub fn transaction_raw(&self) -> Raw {
+        nano_to_raw(&self.transaction).unwrap_or_default()
+    }
+
+    /// Daily limit in raw, 0 is no limit
+    pub fn daily_raw(&self) -> Raw {
+        nano_to_raw(&self.daily).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// directory for log files
+    pub directory: String,
+    /// maximum log level (error, warn, info, debug, trace)
+    pub level: String,
+    /// maximum log level of single modules, overriding level, e.g. rpc = "warn"
+    pub modules: BTreeMap<String, String>,
+    /// line format of the console and log files (text, json)
+    pub format: String,
+    /// where log lines are printed besides the log files (stdout, stderr, none)
+    pub console: String,
+    /// size in MB after which a log file is rotated, 0 is no limit
+    pub rotate_size: u64,
+    /// age in hours after which a log file is rotated, 0 is no limit
+    pub rotate_hours: u32,
+    /// number of rotated files kept for each log file
+    pub rotate_keep: u32,
+    /// log files inside the directory, each with its level and modules
+    pub files: Vec<LogFileConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogFileConfig {
+    pub path: String,
+    /// maximum log level written to the file
+    pub level: String,
+    /// modules written to the file, all modules when empty
+    #[serde(default)]
+    pub modules: Vec<String>,
+}
+
+impl LogFileConfig {
+    fn new(path: &str, level: &str, modules: &[&str]) -> Self {
+        Self {
+            path: path.to_owned(),
+            level: level.to_owned(),
+            modules: modules.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            directory: "log".to_owned(),
+            level: "info".to_owned(),
+            modules: BTreeMap::new(),
+            format: "text".to_owned(),
+            console: "stdout".to_owned(),
+            rotate_size: 10,
+            rotate_hours: 0,
+            rotate_keep: 5,
+            files: vec![
+                LogFileConfig::new("rpc.log", "info", &["rpc"]),
+                LogFileConfig::new("ws.log", "info", &["ws"]),
+                LogFileConfig::new("wallet.log", "info", &["wallet", "pool", "account"]),
+                LogFileConfig::new("info.log", "info", &[]),
+                LogFileConfig::new("error.log", "error", &[]),
+                LogFileConfig::new("cli.log", "info", &["cli"]),
+            ],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// serve prometheus metrics over http at /metrics
+    pub enabled: bool,
+    /// address the metrics endpoint listens on
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_owned(),
+            port: 9097,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_owned(),
+            port: 7090,
+        }
+    }
 }
 
 impl Config {
     fn new() -> Self {
-        let path;
+        let path = Config::path();
+        match Config::load(&path) {
+            Ok(config) => config,
+            Err(errors) => Config::exit_with_errors(&path, &errors),
+        }
+    }
+
+    /// Path set with set_config_path, otherwise chosen by RUST_ENV
+    fn path() -> String {
+        if let Some(path) = CONFIG_PATH.get() {
+            return path.clone();
+        }
         match env::var("RUST_ENV") {
-            Ok(v) => {
-                match v.as_str() {
-                    "TEST" => path = TEST_CONFIG_PATH,
-                    _ => path = PROD_CONFIG_PATH
+            Ok(v) if v == "TEST" => TEST_CONFIG_PATH.to_owned(),
+            _ => PROD_CONFIG_PATH.to_owned(),
+        }
+    }
+
+    fn exit_with_errors(path: &str, errors: &[String]) -> ! {
+        eprintln!("Invalid config {}:", path);
+        for e in errors {
+            eprintln!("  {}", e);
+        }
+        process::exit(1);
+    }
+
+    /// Read a config file, apply NANO_POOL_* environment overrides, and validate it
+    pub fn load(path: &str) -> Result<Self, Vec<String>> {
+        let contents = fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+        Config::parse(&contents, path, env::vars())
+    }
+
+    /// Parse config file contents, returning every problem found instead of only the first one
+    pub fn parse(
+        contents: &str,
+        path: &str,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self, Vec<String>> {
+        let mut value: Value = match toml::from_str(contents) {
+            Ok(v) => v,
+            Err(e) => {
+                let location = match e.line_col() {
+                    Some((line, _)) => format!("{}:{}", path, line + 1),
+                    None => path.to_owned(),
+                };
+                return Err(vec![format!("{}: {}", location, e)]);
+            }
+        };
+        let defaults = Value::try_from(Config::default()).unwrap();
+        let mut errors = vec![];
+        // Where each key was set, for error messages
+        let mut sources: HashMap<String, String> = HashMap::new();
+
+        // Sections with the table of default values their keys are checked against
+        let mut sections: Vec<(String, Value)> = vec![];
+        for (section, table) in defaults.as_table().unwrap() {
+            if section != "wallets" {
+                sections.push((section.clone(), table.clone()));
+            }
+        }
+        let wallet_defaults = Value::try_from(NamedWalletConfig::default()).unwrap();
+
+        // Unknown sections and keys, or keys with the wrong type
+        for (section, table) in value.as_table().unwrap() {
+            let location = Config::location(contents, path, section, None);
+            if defaults.get(section).is_none() {
+                if table.is_table() {
+                    errors.push(format!("{}: unknown section [{}]", location, section));
+                } else {
+                    errors.push(format!(
+                        "{}: unknown key {}, keys have to be placed in a section (see config/config_sample.toml)",
+                        location, section
+                    ));
+                }
+                continue;
+            }
+            let table = match table.as_table() {
+                Some(t) => t,
+                None => {
+                    errors.push(format!("{}: {} has to be a section", location, section));
+                    continue;
+                }
+            };
+            if section != "wallets" {
+                Config::check_keys(contents, path, section, table, &defaults[section.as_str()], &mut errors, &mut sources);
+                continue;
+            }
+            for (name, wallet) in table {
+                let section = format!("wallets.{}", name);
+                let location = Config::location(contents, path, &section, None);
+                sources.insert(section.clone(), location.clone());
+                match wallet.as_table() {
+                    Some(wallet) => {
+                        Config::check_keys(contents, path, &section, wallet, &wallet_defaults, &mut errors, &mut sources);
+                        sections.push((section, wallet_defaults.clone()));
+                    }
+                    None => errors.push(format!("{}: {} has to be a section", location, section)),
                 }
-            },
-            Err(_) => path = PROD_CONFIG_PATH
+            }
+        }
+
+        // Environment overrides, formatted as NANO_POOL_<SECTION>_<KEY>
+        for (name, raw) in vars {
+            let suffix = match name.strip_prefix(ENV_PREFIX) {
+                Some(s) => s.to_lowercase(),
+                None => continue,
+            };
+            let mut found = false;
+            for (section, table) in &sections {
+                for (key, default) in table.as_table().unwrap() {
+                    if suffix != format!("{}_{}", section.replace('.', "_"), key) {
+                        continue;
+                    }
+                    found = true;
+                    let parsed = match default {
+                        Value::Integer(_) => raw.parse::<i64>().map(Value::Integer).ok(),
+                        Value::Boolean(_) => raw.parse::<bool>().map(Value::Boolean).ok(),
+                        // Arrays are comma separated
+                        Value::Array(_) => Some(Value::Array(
+                            raw.split(',')
+                                .map(|s| s.trim())
+                                .filter(|s| !s.is_empty())
+                                .map(|s| Value::String(s.to_owned()))
+                                .collect(),
+                        )),
+                        _ => Some(Value::String(raw.clone())),
+                    };
+                    match parsed {
+                        Some(v) => {
+                            let mut table = value.as_table_mut().unwrap();
+                            for part in section.split('.') {
+                                table = table
+                                    .entry(part.to_owned())
+                                    .or_insert_with(|| Value::Table(Default::default()))
+                                    .as_table_mut()
+                                    .unwrap();
+                            }
+                            table.insert(key.clone(), v);
+                            sources.insert(format!("{}.{}", section, key), name.clone());
+                        }
+                        None => errors.push(format!(
+                            "{}: {}.{} has to be of type {}",
+                            name,
+                            section,
+                            key,
+                            default.type_str()
+                        )),
+                    }
+                }
+            }
+            if !found {
+                errors.push(format!("{}: unknown environment override", name));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let config: Config = match value.try_into() {
+            Ok(c) => c,
+            Err(e) => return Err(vec![format!("{}: {}", path, e)]),
+        };
+        let errors = config.validate(|key| match sources.get(key) {
+            Some(source) => source.clone(),
+            None => format!("{} (default)", path),
+        });
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check a section for unknown keys and keys with a different type than their default value
+    fn check_keys(
+        contents: &str,
+        path: &str,
+        section: &str,
+        table: &toml::value::Table,
+        defaults: &Value,
+        errors: &mut Vec<String>,
+        sources: &mut HashMap<String, String>,
+    ) {
+        for (key, v) in table {
+            let location = Config::location(contents, path, section, Some(key));
+            sources.insert(format!("{}.{}", section, key), location.clone());
+            match defaults.get(key) {
+                None => errors.push(format!("{}: unknown key {}.{}", location, section, key)),
+                Some(d) if d.type_str() != v.type_str() => errors.push(format!(
+                    "{}: {}.{} has to be of type {}, found {}",
+                    location,
+                    section,
+                    key,
+                    d.type_str(),
+                    v.type_str()
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    /// Check values, location returns where a key was set
+    fn validate(&self, location: impl Fn(&str) -> String) -> Vec<String> {
+        let mut errors = vec![];
+        let mut check = |ok: bool, key: &str, message: &str| {
+            if !ok {
+                errors.push(format!("{}: {} {}", location(key), key, message));
+            }
+        };
+
+        check(!self.node.address.is_empty(), "node.address", "is required");
+        check(self.node.rpc_port != 0, "node.rpc_port", "must not be 0");
+        check(self.node.ws_port != 0, "node.ws_port", "must not be 0");
+        check(
+            self.node.rpc_port != self.node.ws_port,
+            "node.ws_port",
+            "must differ from node.rpc_port",
+        );
+        check(self.node.rpc_workers != 0, "node.rpc_workers", "must not be 0");
+        for fallback in &self.node.rpc_fallbacks {
+            check(
+                fallback.starts_with("http://")
+                    || fallback.starts_with("https://")
+                    || matches!(fallback.rsplit_once(':'), Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok()),
+                "node.rpc_fallbacks",
+                &format!("entry {} has to be formatted as host:port or be an http(s) url", fallback),
+            );
+        }
+        check(
+            self.node.rpc_scheme == "http" || self.node.rpc_scheme == "https",
+            "node.rpc_scheme",
+            "has to be http or https",
+        );
+        check(
+            self.node.rpc_path.is_empty() || self.node.rpc_path.starts_with('/'),
+            "node.rpc_path",
+            "has to start with /",
+        );
+        for header in &self.node.rpc_headers {
+            check(
+                matches!(header.split_once(':'), Some((name, _)) if !name.trim().is_empty()),
+                "node.rpc_headers",
+                &format!("entry {} has to be formatted as \"Name: value\"", header),
+            );
         }
-        let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
-        toml::from_str(&contents).unwrap()
+        check(
+            self.node.work_url.is_empty()
+                || self.node.work_url.starts_with("http://")
+                || self.node.work_url.starts_with("https://"),
+            "node.work_url",
+            "has to be an http(s) url",
+        );
+
+        let mut wallets = vec![(
+            "wallet".to_owned(),
+            &self.wallet.seed,
+            &self.wallet.representative,
+            self.pool.start_index,
+            &self.wallet.address,
+            &self.wallet.pool_addresses,
+        )];
+        for (name, wallet) in &self.wallets {
+            check(
+                name != DEFAULT_WALLET
+                    && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'),
+                &format!("wallets.{}", name),
+                &format!("name has to be lowercase alphanumeric and not {}", DEFAULT_WALLET),
+            );
+            wallets.push((
+                format!("wallets.{}", name),
+                &wallet.seed,
+                &wallet.representative,
+                wallet.start_index,
+                &wallet.address,
+                &wallet.pool_addresses,
+            ));
+        }
+        for (section, seed, representative, start_index, address, pool_addresses) in wallets {
+            // Watch-only wallets have an address instead of a seed
+            if seed.is_empty() && !address.is_empty() {
+                if let Err(e) = validate_address(address) {
+                    check(false, &format!("{}.address", section), &format!("is invalid: {}", e));
+                }
+                for pool_address in pool_addresses {
+                    if let Err(e) = validate_address(pool_address) {
+                        check(false, &format!("{}.pool_addresses", section), &format!("is invalid: {}", e));
+                    }
+                }
+                continue;
+            }
+            check(
+                address.is_empty() && pool_addresses.is_empty(),
+                &format!("{}.address", section),
+                "is only used by watch-only wallets, remove either the seed or the addresses",
+            );
+            check(
+                seed.len() == 64 && seed.chars().all(|c| c.is_ascii_hexdigit()),
+                &format!("{}.seed", section),
+                "has to be 64 hexadecimal characters",
+            );
+            if let Err(e) = validate_address(representative) {
+                check(false, &format!("{}.representative", section), &format!("is invalid: {}", e));
+            }
+            if section != "wallet" {
+                check(
+                    start_index != 0,
+                    &format!("{}.start_index", section),
+                    "must not be 0, index 0 is the main account",
+                );
+            }
+        }
+        check(self.wallet.receive_batch_size != 0, "wallet.receive_batch_size", "must not be 0");
+
+        check(self.pool.start_index != 0, "pool.start_index", "must not be 0, index 0 is the main account");
+
+        check(self.timeouts.transaction != 0, "timeouts.transaction", "must not be 0");
+        check(self.timeouts.poll_interval != 0, "timeouts.poll_interval", "must not be 0");
+        check(self.timeouts.ws_poll_interval != 0, "timeouts.ws_poll_interval", "must not be 0");
+        check(self.timeouts.rpc != 0, "timeouts.rpc", "must not be 0");
+        check(self.timeouts.rpc_work != 0, "timeouts.rpc_work", "must not be 0");
+        check(self.timeouts.rpc_health_interval != 0, "timeouts.rpc_health_interval", "must not be 0");
+        check(self.timeouts.ws_reconnect != 0, "timeouts.ws_reconnect", "must not be 0");
+        check(
+            self.timeouts.ws_reconnect_max >= self.timeouts.ws_reconnect,
+            "timeouts.ws_reconnect_max",
+            "must not be less than timeouts.ws_reconnect",
+        );
+        check(self.timeouts.ws_ping_interval != 0, "timeouts.ws_ping_interval", "must not be 0");
+        check(self.timeouts.ws_ack != 0, "timeouts.ws_ack", "must not be 0");
+        check(self.timeouts.unconfirmed_check != 0, "timeouts.unconfirmed_check", "must not be 0");
+        check(self.timeouts.shutdown != 0, "timeouts.shutdown", "must not be 0");
+
+        for (key, limit) in [("limits.transaction", &self.limits.transaction), ("limits.daily", &self.limits.daily)] {
+            if let Err(e) = nano_to_raw(limit) {
+                check(false, key, &format!("has to be an amount in nano: {}", e));
+            }
+        }
+
+        check(
+            LOG_LEVELS.contains(&self.logging.level.as_str()),
+            "logging.level",
+            &format!("has to be one of {}", LOG_LEVELS.join(", ")),
+        );
+        check(!self.logging.directory.is_empty(), "logging.directory", "is required");
+        for (module, level) in &self.logging.modules {
+            check(
+                LOG_LEVELS.contains(&level.as_str()),
+                "logging.modules",
+                &format!("level {} of {} has to be one of {}", level, module, LOG_LEVELS.join(", ")),
+            );
+        }
+        check(
+            self.logging.format == "text" || self.logging.format == "json",
+            "logging.format",
+            "has to be text or json",
+        );
+        check(
+            ["stdout", "stderr", "none"].contains(&self.logging.console.as_str()),
+            "logging.console",
+            "has to be stdout, stderr or none",
+        );
+        for file in &self.logging.files {
+            check(!file.path.is_empty(), "logging.files", "entry path is required");
+            check(
+                LOG_LEVELS.contains(&file.level.as_str()),
+                "logging.files",
+                &format!("level {} of {} has to be one of {}", file.level, file.path, LOG_LEVELS.join(", ")),
+            );
+        }
+
+        check(!self.metrics.enabled || self.metrics.port != 0, "metrics.port", "must not be 0");
+        check(!self.api.enabled || self.api.port != 0, "api.port", "must not be 0");
+        errors
+    }
+
+    /// Rpc node urls, the configured node first and then the fallbacks
+    pub fn rpc_endpoints(&self) -> Vec<String> {
+        let mut endpoints = vec![format!(
+            "{}://{}:{}{}",
+            self.node.rpc_scheme, self.node.address, self.node.rpc_port, self.node.rpc_path
+        )];
+        for fallback in &self.node.rpc_fallbacks {
+            if fallback.contains("://") {
+                endpoints.push(fallback.clone());
+            } else {
+                endpoints.push(format!("{}://{}{}", self.node.rpc_scheme, fallback, self.node.rpc_path));
+            }
+        }
+        endpoints
+    }
+
+    /// Http headers sent with every rpc request, including the bearer token
+    pub fn rpc_headers(&self) -> Vec<String> {
+        let mut headers = self.node.rpc_headers.clone();
+        if !self.node.rpc_token.is_empty() {
+            headers.push(format!("Authorization: Bearer {}", self.node.rpc_token));
+        }
+        headers
+    }
+
+    /// Try to connect to the node rpc and websocket ports
+    /// The rpc check passes when at least one of the rpc endpoints is reachable
+    pub fn check_node_reachable(&self) -> Vec<String> {
+        let reachable = |endpoint: &str| match endpoint.to_socket_addrs() {
+            Ok(mut addrs) => addrs.any(|a| TcpStream::connect_timeout(&a, Duration::from_secs(2)).is_ok()),
+            Err(_) => false,
+        };
+        let mut errors = vec![];
+        let rpc_endpoints = self.rpc_endpoints();
+        if !rpc_endpoints.iter().any(|e| reachable(&Config::host_port(e))) {
+            errors.push(format!("node.rpc_port {} is unreachable", rpc_endpoints.join(", ")));
+        }
+        let ws_endpoint = format!("{}:{}", self.node.address, self.node.ws_port);
+        if !reachable(&ws_endpoint) {
+            errors.push(format!("node.ws_port {} is unreachable", ws_endpoint));
+        }
+        errors
+    }
+
+    /// Host and port of a url, with the default port of the scheme if it has none
+    fn host_port(url: &str) -> String {
+        let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+        let authority = rest.split('/').next().unwrap_or_default();
+        match authority.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => authority.to_owned(),
+            _ if scheme == "https" => format!("{}:443", authority),
+            _ => format!("{}:80", authority),
+        }
+    }
+
+    /// Find the line a section or key is defined on, formatted as path:line
+    fn location(contents: &str, path: &str, section: &str, key: Option<&str>) -> String {
+        let mut current = "";
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with('[') && line.ends_with(']') {
+                current = line[1..line.len() - 1].trim();
+                if key.is_none() && current == section {
+                    return format!("{}:{}", path, i + 1);
+                }
+                continue;
+            }
+            let name = match line.split('=').next() {
+                Some(n) if line.contains('=') => n.trim(),
+                _ => continue,
+            };
+            match key {
+                Some(key) if current == section && name == key => return format!("{}:{}", path, i + 1),
+                None if current.is_empty() && name == section => return format!("{}:{}", path, i + 1),
+                _ => {}
+            }
+        }
+        path.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"
+[node]
+address = "127.0.0.1"
+rpc_port = 17076
+
+[wallet]
+seed = "907933879BCBA2F7C92E3A03A59255F3BC3B72C4AD49E7A57B48B7DF5F4ADD20"
+representative = "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit"
+"#;
+
+    fn no_vars() -> std::vec::IntoIter<(String, String)> {
+        vec![].into_iter()
+    }
+
+    #[test]
+    fn config_parse_no_node_required() {
+        let config = Config::parse(VALID, "test.toml", no_vars()).unwrap();
+        assert_eq!(config.node.rpc_port, 17076);
+        // defaults
+        assert_eq!(config.node.ws_port, 7078);
+        assert_eq!(config.timeouts.transaction, 300);
+        assert_eq!(config.timeouts.shutdown, 60);
+        assert_eq!(config.node.rpc_workers, 4);
+        assert_eq!(config.rpc_endpoints(), vec!["http://127.0.0.1:17076".to_owned()]);
+        assert!(config.rpc_headers().is_empty());
+        assert_eq!(config.pool.start_index, 1);
+        assert_eq!(config.logging.level, "info");
+        assert_eq!(config.logging.files.len(), 6);
+        assert!(!config.api.enabled);
+        assert!(!config.metrics.enabled);
+        assert_eq!(config.limits.transaction_raw(), 0);
+
+        let contents = format!("{}[limits]\ntransaction = \"1.5\"\n", VALID);
+        let config = Config::parse(&contents, "test.toml", no_vars()).unwrap();
+        assert_eq!(config.limits.transaction_raw(), 1_500_000_000_000_000_000_000_000_000_000);
+        let contents = format!("{}[limits]\ndaily = \"ten\"\n", VALID);
+        assert_eq!(
+            Config::parse(&contents, "test.toml", no_vars()).err().unwrap(),
+            vec!["test.toml:10: limits.daily has to be an amount in nano: Invalid nano amount ten".to_owned()]
+        );
+
+        let contents = format!(
+            "{}[logging]\nformat = \"json\"\nmodules = {{ rpc = \"warn\" }}\n[[logging.files]]\npath = \"all.log\"\nlevel = \"debug\"\n",
+            VALID
+        );
+        let config = Config::parse(&contents, "test.toml", no_vars()).unwrap();
+        assert_eq!(config.logging.format, "json");
+        assert_eq!(config.logging.modules["rpc"], "warn");
+        assert_eq!(config.logging.files.len(), 1);
+        assert!(config.logging.files[0].modules.is_empty());
+        let contents = format!("{}[logging]\nconsole = \"file\"\nmodules = {{ ws = \"loud\" }}\n", VALID);
+        assert_eq!(
+            Config::parse(&contents, "test.toml", no_vars()).err().unwrap(),
+            vec![
+                "test.toml:11: logging.modules level loud of ws has to be one of error, warn, info, debug, trace".to_owned(),
+                "test.toml:10: logging.console has to be stdout, stderr or none".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_env_overrides_no_node_required() {
+        let vars = vec![
+            ("NANO_POOL_NODE_WS_PORT".to_owned(), "17078".to_owned()),
+            ("NANO_POOL_TIMEOUTS_TRANSACTION".to_owned(), "3".to_owned()),
+            ("NANO_POOL_API_ENABLED".to_owned(), "true".to_owned()),
+            ("UNRELATED".to_owned(), "1".to_owned()),
+        ];
+        let config = Config::parse(VALID, "test.toml", vars.into_iter()).unwrap();
+        assert_eq!(config.node.ws_port, 17078);
+        assert_eq!(config.timeouts.transaction, 3);
+        assert!(config.api.enabled);
+
+        let vars = vec![
+            ("NANO_POOL_NODE_WS_PORT".to_owned(), "abc".to_owned()),
+            ("NANO_POOL_NOT_A_KEY".to_owned(), "1".to_owned()),
+        ];
+        let errors = Config::parse(VALID, "test.toml", vars.into_iter()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "NANO_POOL_NODE_WS_PORT: node.ws_port has to be of type integer".to_owned(),
+                "NANO_POOL_NOT_A_KEY: unknown environment override".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_errors_no_node_required() {
+        // Syntax errors include the line
+        let errors = Config::parse("[node]\nrpc_port = = 1", "test.toml", no_vars()).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("test.toml:2: "));
+
+        // All unknown and mistyped keys are reported
+        let contents = "wallet_seed = \"00\"\n[node]\nrpc_port = \"x\"\nport = 1\n[nodes]\n";
+        let errors = Config::parse(contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "test.toml:4: unknown key node.port".to_owned(),
+                "test.toml:3: node.rpc_port has to be of type integer, found string".to_owned(),
+                "test.toml:5: unknown section [nodes]".to_owned(),
+                "test.toml:1: unknown key wallet_seed, keys have to be placed in a section (see config/config_sample.toml)".to_owned(),
+            ]
+        );
+
+        // All invalid values are reported
+        let contents = "[node]\nrpc_port = 0\n[wallet]\nseed = \"123\"\nrepresentative = \"nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjis\"\n[timeouts]\ntransaction = 0\n";
+        let errors = Config::parse(contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "test.toml:2: node.rpc_port must not be 0".to_owned(),
+                "test.toml:4: wallet.seed has to be 64 hexadecimal characters".to_owned(),
+                "test.toml:5: wallet.representative is invalid: Address nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjis has an invalid checksum".to_owned(),
+                "test.toml:7: timeouts.transaction must not be 0".to_owned(),
+            ]
+        );
+
+        let contents = VALID.replace("rpc_port = 17076\n", "rpc_port = 17076\nrpc_fallbacks = [\"10.0.0.2:7076\", \"10.0.0.3\"]\n");
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["test.toml:5: node.rpc_fallbacks entry 10.0.0.3 has to be formatted as host:port or be an http(s) url".to_owned()]
+        );
+    }
+
+    #[test]
+    fn config_rpc_provider_no_node_required() {
+        let contents = VALID.replace(
+            "rpc_port = 17076\n",
+            "rpc_port = 443\nrpc_scheme = \"https\"\nrpc_path = \"/proxy\"\nrpc_token = \"secret\"\nrpc_headers = [\"X-Api-Key: abc\"]\nrpc_fallbacks = [\"10.0.0.2:7076\", \"https://rpc.example.com/api\"]\n",
+        );
+        let config = Config::parse(&contents, "test.toml", no_vars()).unwrap();
+        assert_eq!(
+            config.rpc_endpoints(),
+            vec![
+                "https://127.0.0.1:443/proxy".to_owned(),
+                "https://10.0.0.2:7076/proxy".to_owned(),
+                "https://rpc.example.com/api".to_owned(),
+            ]
+        );
+        assert_eq!(
+            config.rpc_headers(),
+            vec!["X-Api-Key: abc".to_owned(), "Authorization: Bearer secret".to_owned()]
+        );
+        assert_eq!(Config::host_port("https://rpc.example.com/api"), "rpc.example.com:443");
+        assert_eq!(Config::host_port("http://10.0.0.2:7076/proxy"), "10.0.0.2:7076");
+
+        let contents = VALID.replace("rpc_port = 17076\n", "rpc_port = 17076\nrpc_scheme = \"ftp\"\nrpc_headers = [\"abc\"]\n");
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "test.toml:5: node.rpc_scheme has to be http or https".to_owned(),
+                "test.toml:6: node.rpc_headers entry abc has to be formatted as \"Name: value\"".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn config_wallets_no_node_required() {
+        let contents = format!(
+            "{}\n[wallets.shop]\nseed = \"{}\"\nrepresentative = \"{}\"\n\n[wallets.Bad]\nseed = \"1\"\nrepresentative = \"nano_1\"\nstart_index = 0\nfoo = 1\n",
+            VALID,
+            "0".repeat(64),
+            "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit"
+        );
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(errors, vec!["test.toml:18: unknown key wallets.Bad.foo".to_owned()]);
+
+        let contents = contents.replace("foo = 1\n", "");
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(errors.len(), 4);
+        assert_eq!(
+            errors[0],
+            "test.toml:14: wallets.Bad name has to be lowercase alphanumeric and not default"
+        );
+        assert_eq!(errors[1], "test.toml:15: wallets.Bad.seed has to be 64 hexadecimal characters");
+        assert_eq!(errors[3], "test.toml:17: wallets.Bad.start_index must not be 0, index 0 is the main account");
+
+        let contents = contents.replace("[wallets.Bad]", "[wallets.bad]");
+        let vars = vec![
+            ("NANO_POOL_WALLETS_BAD_SEED".to_owned(), "1".repeat(64)),
+            ("NANO_POOL_WALLETS_BAD_REPRESENTATIVE".to_owned(), "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit".to_owned()),
+            ("NANO_POOL_WALLETS_BAD_START_INDEX".to_owned(), "5".to_owned()),
+        ];
+        let config = Config::parse(&contents, "test.toml", vars.into_iter()).unwrap();
+        assert_eq!(config.wallets.len(), 2);
+        assert_eq!(config.wallets["shop"].start_index, 1);
+        assert_eq!(config.wallets["bad"].seed, "1".repeat(64));
+        assert_eq!(config.wallets["bad"].start_index, 5);
+    }
+
+    #[test]
+    fn config_watch_only_no_node_required() {
+        let address = "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit";
+        let contents = format!(
+            "[wallet]\naddress = \"{}\"\npool_addresses = [\"{}\"]\n",
+            address, address
+        );
+        let config = Config::parse(&contents, "test.toml", no_vars()).unwrap();
+        assert!(config.wallet.seed.is_empty());
+        assert_eq!(config.wallet.pool_addresses, vec![address.to_owned()]);
+
+        let vars = vec![(
+            "NANO_POOL_WALLET_POOL_ADDRESSES".to_owned(),
+            format!("{}, {}", address, address),
+        )];
+        let config = Config::parse(&contents, "test.toml", vars.into_iter()).unwrap();
+        assert_eq!(config.wallet.pool_addresses.len(), 2);
+
+        let contents = format!(
+            "[wallet]\naddress = \"{}\"\npool_addresses = [\"nano_1\"]\n",
+            address
+        );
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["test.toml:3: wallet.pool_addresses is invalid: Address nano_1 has an invalid length".to_owned()]
+        );
+        // A typo with a multi-byte character is an error, not a panic
+        let typo = format!("nano_{}", "é".repeat(30));
+        assert_eq!(validate_address(&typo), Err(format!("Address {} has an invalid length", typo)));
+
+        // Seed and address can't both be set
+        let contents = format!("{}address = \"{}\"\n", VALID, address);
+        let errors = Config::parse(&contents, "test.toml", no_vars()).err().unwrap();
+        assert_eq!(
+            errors,
+            vec!["test.toml:9: wallet.address is only used by watch-only wallets, remove either the seed or the addresses".to_owned()]
+        );
     }
 }
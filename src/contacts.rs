@@ -0,0 +1,180 @@
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::address::{validate_address, Address};
+use crate::common::write_atomic;
+
+/// Address book location, shared by all wallets
+const CONTACTS_PATH: &str = "data/contacts.json";
+
+static CONTACTS: Lazy<Mutex<AddressBook>> = Lazy::new(|| Mutex::new(AddressBook::load(CONTACTS_PATH)));
+
+/// The address book, loaded on first use
+pub fn contacts() -> MutexGuard<'static, AddressBook> {
+    CONTACTS.lock().unwrap()
+}
+
+/// An address with its label for logs and output, e.g. "@alice nano_1...", or only the address without a contact
+pub fn display_address(address: &str) -> String {
+    contacts().display(address)
+}
+
+/// Resolve an @label to its address, other input is returned as it is
+pub fn resolve_address(input: &str) -> Result<Address, String> {
+    contacts().resolve(input)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Contact {
+    pub address: Address,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+}
+
+/// Destination addresses by label, so they can be given as @label instead of the full address
+pub struct AddressBook {
+    path: String,
+    contacts: BTreeMap<String, Contact>,
+    /// Why the stored address book could not be read, it is not overwritten then
+    error: Option<String>,
+}
+
+impl AddressBook {
+    /// Load the address book stored at path, a missing file is an empty address book
+    pub fn load(path: &str) -> Self {
+        let (contacts, error) = match fs::read(path) {
+            Err(_) => (BTreeMap::new(), None),
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(contacts) => (contacts, None),
+                Err(e) => {
+                    let e = format!("Address book {} could not be read: {}", path, e);
+                    error!("CONTACTS {}", e);
+                    (BTreeMap::new(), Some(e))
+                }
+            },
+        };
+        Self {
+            path: path.to_owned(),
+            contacts,
+            error,
+        }
+    }
+
+    /// Add a contact, labels are lowercase alphanumeric and may be prefixed with @
+    pub fn add(&mut self, label: &str, address: Address, note: String) -> Result<(), String> {
+        let label = label.strip_prefix('@').unwrap_or(label).to_lowercase();
+        if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid label {}, use letters, digits, _ and -", label));
+        }
+        validate_address(&address).map_err(|e| format!("Invalid address {}: {}", address, e))?;
+        if let Some(contact) = self.contacts.get(&label) {
+            return Err(format!("Contact @{} already exists for {}, remove it first", label, contact.address));
+        }
+        info!("CONTACTS add @{} {}", label, address);
+        self.contacts.insert(label, Contact { address, note });
+        self.save()
+    }
+
+    /// Remove a contact by label
+    pub fn remove(&mut self, label: &str) -> Result<Contact, String> {
+        let label = label.strip_prefix('@').unwrap_or(label).to_lowercase();
+        let contact = self
+            .contacts
+            .remove(&label)
+            .ok_or(format!("No contact @{}", label))?;
+        info!("CONTACTS remove @{} {}", label, contact.address);
+        self.save()?;
+        Ok(contact)
+    }
+
+    /// All contacts ordered by label
+    pub fn list(&self) -> Vec<(String, Contact)> {
+        self.contacts.iter().map(|(l, c)| (l.clone(), c.clone())).collect()
+    }
+
+    /// All labels, for tab completion
+    pub fn labels(&self) -> Vec<String> {
+        self.contacts.keys().cloned().collect()
+    }
+
+    /// Resolve an @label to its address, other input is returned as it is
+    pub fn resolve(&self, input: &str) -> Result<Address, String> {
+        match input.strip_prefix('@') {
+            Some(label) => self
+                .contacts
+                .get(&label.to_lowercase())
+                .map(|c| c.address.clone())
+                .ok_or(format!("No contact @{}, see contact list", label)),
+            None => Ok(input.to_owned()),
+        }
+    }
+
+    /// Label of an address, if it is in the address book
+    pub fn label(&self, address: &str) -> Option<String> {
+        self.contacts
+            .iter()
+            .find(|(_, c)| c.address == address)
+            .map(|(l, _)| l.clone())
+    }
+
+    /// An address with its label, e.g. "@alice nano_1...", or only the address without a contact
+    pub fn display(&self, address: &str) -> String {
+        match self.label(address) {
+            Some(label) => format!("@{} {}", label, address),
+            None => address.to_owned(),
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
+        let json = serde_json::to_vec_pretty(&self.contacts).map_err(|e| e.to_string())?;
+        write_atomic(&self.path, &json).map_err(|e| format!("Could not store address book {}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+
+    const ALICE: &str = "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit";
+
+    #[test]
+    fn contacts_no_node_required() {
+        let path = &test_path("contacts.json");
+        let mut book = AddressBook::load(path);
+        book.add("@Alice", ALICE.to_owned(), "rent".to_owned()).unwrap();
+        assert!(book.add("alice", ALICE.to_owned(), "".to_owned()).is_err());
+        assert!(book.add("bob", "nano_1invalid".to_owned(), "".to_owned()).is_err());
+        assert!(book.add("bob smith", ALICE.to_owned(), "".to_owned()).is_err());
+
+        assert_eq!(book.resolve("@alice"), Ok(ALICE.to_owned()));
+        assert_eq!(book.resolve("@ALICE"), Ok(ALICE.to_owned()));
+        assert_eq!(book.resolve("nano_1abc"), Ok("nano_1abc".to_owned()));
+        assert!(book.resolve("@bob").is_err());
+        assert_eq!(book.display(ALICE), format!("@alice {}", ALICE));
+        assert_eq!(book.display("nano_1abc"), "nano_1abc");
+
+        // Contacts are kept across restarts
+        let mut book = AddressBook::load(path);
+        assert_eq!(
+            book.list(),
+            vec![("alice".to_owned(), Contact { address: ALICE.to_owned(), note: "rent".to_owned() })]
+        );
+        book.remove("@alice").unwrap();
+        assert!(book.remove("alice").is_err());
+        assert!(AddressBook::load(path).list().is_empty());
+
+        // A damaged address book is not overwritten
+        fs::write(path, "{").unwrap();
+        let mut book = AddressBook::load(path);
+        assert!(book.add("alice", ALICE.to_owned(), "".to_owned()).is_err());
+        assert_eq!(fs::read_to_string(path).unwrap(), "{");
+    }
+}
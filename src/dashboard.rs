@@ -0,0 +1,416 @@
+use crossterm::cursor::Show;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use log::{error, info};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Stdout};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::account::Account;
+use crate::address::Address;
+use crate::cli::CliClient;
+use crate::config::{CONFIG, DEFAULT_WALLET};
+use crate::contacts::display_address;
+use crate::pool::{PoolStatus, PoolView};
+use crate::registry::WalletRegistry;
+use crate::rpc::{rpc_block_count, RpcCommand};
+use crate::shutdown::{at_exit, shutting_down};
+use crate::unit::Raw;
+use crate::ws::{ws_connected, ws_subscribe, WsHandler, WsMessage, WsSubscription, WsTopic};
+
+/// Time between redraws, also the longest a key press waits
+const TICK: Duration = Duration::from_millis(250);
+
+/// Time between rpc health checks
+const RPC_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of confirmations and output lines kept for display
+const FEED_LENGTH: usize = 100;
+
+/// Run the full-screen dashboard of the default wallet until the user quits
+/// Commands typed at the bottom run like in the command line interface, their output is shown above the input
+pub fn start_dashboard(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>) -> Result<(), String> {
+    let mut wallets = WalletRegistry::from_config(rpc_tx.clone(), ws_tx.clone())?;
+    let wallet = wallets.get(DEFAULT_WALLET)?;
+    let account = wallet.account();
+    let pool = wallet.pool().view();
+
+    let addresses = Arc::new(Mutex::new(HashSet::new()));
+    let feed = Arc::new(Mutex::new(VecDeque::new()));
+    ws_subscribe(&ws_tx, WsTopic::Confirmation, None, confirmation_handler(addresses.clone(), feed.clone()))?;
+    let rpc = start_rpc_check(rpc_tx.clone());
+
+    let (command_tx, command_rx) = mpsc::channel::<String>();
+    let (output_tx, output_rx) = mpsc::channel::<String>();
+    CliClient::start_worker(wallets, rpc_tx, command_rx, output_tx);
+
+    let mut dashboard = Dashboard {
+        account,
+        pool,
+        addresses,
+        feed,
+        rpc,
+        rows: HashMap::new(),
+        input: String::new(),
+        output: VecDeque::new(),
+        output_rx,
+        command_tx,
+    };
+
+    let mut terminal = enter_terminal().map_err(|e| format!("DASHBOARD could not start terminal: {}", e))?;
+    // Restore the terminal when a signal shuts down the program before the dashboard quits
+    at_exit(Box::new(|| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+    }));
+    info!("DASHBOARD started");
+    let result = dashboard.run(&mut terminal);
+    if let Err(e) = leave_terminal(&mut terminal) {
+        error!("DASHBOARD could not restore terminal: {}", e);
+    }
+    info!("DASHBOARD exit");
+    result.map_err(|e| format!("DASHBOARD terminal error: {}", e))
+}
+
+fn enter_terminal() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(stdout))
+}
+
+fn leave_terminal(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+/// A confirmed block of the wallet or one of its pool accounts
+struct FeedEntry {
+    time: String,
+    account: Address,
+    subtype: String,
+    amount: Raw,
+    hash: String,
+}
+
+/// Add the confirmations of the wallet and pool accounts to the feed, newest first
+/// Other confirmations arrive when the websocket client subscribed to all confirmations
+fn confirmation_handler(addresses: Arc<Mutex<HashSet<Address>>>, feed: Arc<Mutex<VecDeque<FeedEntry>>>) -> WsHandler {
+    Box::new(move |message| {
+        if let WsMessage::Confirmation(confirmation) = message {
+            let addresses = addresses.lock().unwrap();
+            let account = if addresses.contains(&confirmation.account) {
+                confirmation.account.clone()
+            } else if addresses.contains(&confirmation.block.link_as_account) {
+                confirmation.block.link_as_account.clone()
+            } else {
+                return;
+            };
+            drop(addresses);
+            let mut feed = feed.lock().unwrap();
+            if feed.len() == FEED_LENGTH {
+                feed.pop_back();
+            }
+            feed.push_front(FeedEntry {
+                time: time::strftime("%H:%M:%S", &time::now()).unwrap(),
+                account,
+                subtype: confirmation.block.subtype.clone().unwrap_or_default(),
+                amount: confirmation.amount,
+                hash: confirmation.hash.clone(),
+            });
+        }
+    })
+}
+
+/// Result of the last rpc check, the round trip time or the error
+type RpcHealth = Arc<Mutex<Option<Result<Duration, String>>>>;
+
+/// Periodically check that the node answers rpc requests, on its own thread so a slow node doesn't block drawing
+fn start_rpc_check(rpc_tx: Sender<RpcCommand>) -> RpcHealth {
+    let health: RpcHealth = Arc::new(Mutex::new(None));
+    let result = health.clone();
+    thread::Builder::new()
+        .name("dashboard rpc".to_owned())
+        .spawn(move || loop {
+            let start = Instant::now();
+            let check = rpc_block_count(rpc_tx.clone())
+                .map(|_| start.elapsed())
+                .map_err(|e| e.to_string());
+            *result.lock().unwrap() = Some(check);
+            thread::sleep(RPC_CHECK_INTERVAL);
+        })
+        .unwrap();
+    health
+}
+
+/// Time left of a reservation until the transaction times out, as minutes and seconds
+fn countdown(reserved: Duration, timeout: Duration) -> String {
+    match timeout.checked_sub(reserved) {
+        Some(left) if !left.is_zero() => format!("{}:{:02}", left.as_secs() / 60, left.as_secs() % 60),
+        _ => "timed out".to_owned(),
+    }
+}
+
+/// The last known state of an account
+#[derive(Clone)]
+struct AccountRow {
+    account: Arc<Mutex<Account>>,
+    index: u32,
+    address: Address,
+    balance: Raw,
+    status: PoolStatus,
+    /// Locked by a transaction, the balance may be outdated
+    busy: bool,
+}
+
+struct Dashboard {
+    account: Arc<Mutex<Account>>,
+    pool: PoolView,
+    /// Addresses of the wallet and pool accounts, shared with the confirmation handler
+    addresses: Arc<Mutex<HashSet<Address>>>,
+    feed: Arc<Mutex<VecDeque<FeedEntry>>>,
+    rpc: RpcHealth,
+    /// Last known state of each account by index, 0 is the wallet account
+    rows: HashMap<u32, AccountRow>,
+    input: String,
+    /// Command output, newest last
+    output: VecDeque<String>,
+    output_rx: Receiver<String>,
+    command_tx: Sender<String>,
+}
+
+impl Dashboard {
+    fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> io::Result<()> {
+        while !shutting_down() {
+            self.refresh();
+            terminal.draw(|f| self.draw(f))?;
+            if !event::poll(TICK)? {
+                continue;
+            }
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                KeyCode::Esc => self.input.clear(),
+                KeyCode::Enter => {
+                    let line = self.input.trim().to_owned();
+                    self.input.clear();
+                    if line == "exit" {
+                        return Ok(());
+                    }
+                    if !line.is_empty() {
+                        self.push_output(format!(">{}", line));
+                        let _ = self.command_tx.send(line);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn push_output(&mut self, line: String) {
+        if self.output.len() == FEED_LENGTH {
+            self.output.pop_front();
+        }
+        self.output.push_back(line);
+    }
+
+    /// Read the accounts that are not locked by a transaction and collect the command output
+    fn refresh(&mut self) {
+        let mut accounts = vec![(self.account.clone(), None)];
+        accounts.extend(self.pool.accounts().into_iter().map(|(a, s)| (a, Some(s))));
+        let mut addresses = HashSet::new();
+        for (account, status) in accounts {
+            let status = status.unwrap_or(PoolStatus::Free);
+            match account.try_lock() {
+                Ok(acc) => {
+                    addresses.insert(acc.address());
+                    self.rows.insert(
+                        acc.index(),
+                        AccountRow {
+                            account: account.clone(),
+                            index: acc.index(),
+                            address: acc.address(),
+                            balance: acc.balance(),
+                            status,
+                            busy: false,
+                        },
+                    );
+                }
+                // Accounts are locked while they publish, keep their last known state meanwhile
+                Err(_) => {
+                    if let Some(row) = self.rows.values_mut().find(|r| Arc::ptr_eq(&r.account, &account)) {
+                        row.status = status;
+                        row.busy = true;
+                    }
+                }
+            }
+        }
+        for row in self.rows.values() {
+            addresses.insert(row.address.clone());
+        }
+        *self.addresses.lock().unwrap() = addresses;
+
+        while let Ok(line) = self.output_rx.try_recv() {
+            self.push_output(line);
+        }
+    }
+
+    fn draw(&self, f: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Percentage(40),
+                Constraint::Min(5),
+                Constraint::Length(8),
+                Constraint::Length(3),
+            ])
+            .split(f.size());
+        let middle = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(rows[1]);
+
+        self.draw_status(f, rows[0]);
+        self.draw_pool(f, middle[0]);
+        self.draw_reservations(f, middle[1]);
+        self.draw_feed(f, rows[2]);
+        self.draw_output(f, rows[3]);
+        self.draw_input(f, rows[4]);
+    }
+
+    fn draw_status(&self, f: &mut Frame, area: Rect) {
+        let balance = match self.rows.get(&0) {
+            Some(row) => row.balance.to_string(),
+            None => "-".to_owned(),
+        };
+        let rpc = match &*self.rpc.lock().unwrap() {
+            None => Span::raw("checking"),
+            Some(Ok(rtt)) => Span::styled(format!("ok {} ms", rtt.as_millis()), Style::default().fg(Color::Green)),
+            Some(Err(e)) => Span::styled(e.clone(), Style::default().fg(Color::Red)),
+        };
+        let ws = if ws_connected() {
+            Span::styled("connected", Style::default().fg(Color::Green))
+        } else {
+            Span::styled("reconnecting", Style::default().fg(Color::Red))
+        };
+        let line = Line::from(vec![
+            Span::styled("Balance ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(format!("{} raw   ", balance)),
+            Span::styled("RPC ", Style::default().add_modifier(Modifier::BOLD)),
+            rpc,
+            Span::raw("   "),
+            Span::styled("WS ", Style::default().add_modifier(Modifier::BOLD)),
+            ws,
+        ]);
+        let block = Block::default().borders(Borders::ALL).title(format!(" {} ", DEFAULT_WALLET));
+        f.render_widget(Paragraph::new(line).block(block), area);
+    }
+
+    /// Pool account rows ordered by index
+    fn pool_rows(&self) -> Vec<&AccountRow> {
+        let mut rows: Vec<&AccountRow> = self.rows.values().filter(|r| r.index != 0).collect();
+        rows.sort_by_key(|r| r.index);
+        rows
+    }
+
+    fn draw_pool(&self, f: &mut Frame, area: Rect) {
+        let rows = self.pool_rows().into_iter().map(|row| {
+            let status = if row.busy { "busy" } else { row.status.as_str() };
+            Row::new(vec![
+                Cell::from(row.index.to_string()),
+                Cell::from(row.address.clone()),
+                Cell::from(row.balance.to_string()),
+                Cell::from(status),
+            ])
+        });
+        let widths = [Constraint::Length(6), Constraint::Min(20), Constraint::Length(40), Constraint::Length(9)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["Index", "Address", "Balance", "Status"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title(" Pool "));
+        f.render_widget(table, area);
+    }
+
+    fn draw_reservations(&self, f: &mut Frame, area: Rect) {
+        let timeout = Duration::from_secs(CONFIG.timeouts.transaction as u64);
+        let rows = self.pool_rows().into_iter().filter_map(|row| match row.status {
+            PoolStatus::Reserved(since) => Some(Row::new(vec![
+                Cell::from(row.index.to_string()),
+                Cell::from(row.address.clone()),
+                Cell::from(countdown(since.elapsed(), timeout)),
+            ])),
+            PoolStatus::Free => None,
+        });
+        let widths = [Constraint::Length(6), Constraint::Min(20), Constraint::Length(9)];
+        let table = Table::new(rows, widths)
+            .header(Row::new(vec!["Index", "Address", "Left"]).style(Style::default().add_modifier(Modifier::BOLD)))
+            .block(Block::default().borders(Borders::ALL).title(" Reservations "));
+        f.render_widget(table, area);
+    }
+
+    fn draw_feed(&self, f: &mut Frame, area: Rect) {
+        let feed = self.feed.lock().unwrap();
+        let items: Vec<ListItem> = feed
+            .iter()
+            .map(|e| {
+                let account = display_address(&e.account);
+                ListItem::new(format!("{} {:<7} {} {} {}", e.time, e.subtype, e.amount, account, e.hash))
+            })
+            .collect();
+        let list = List::new(items).block(Block::default().borders(Borders::ALL).title(" Confirmations "));
+        f.render_widget(list, area);
+    }
+
+    fn draw_output(&self, f: &mut Frame, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .output
+            .iter()
+            .skip(self.output.len().saturating_sub(height))
+            .map(|l| Line::from(l.as_str()))
+            .collect();
+        let output = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Output "));
+        f.render_widget(output, area);
+    }
+
+    fn draw_input(&self, f: &mut Frame, area: Rect) {
+        let input = Paragraph::new(format!(">{}", self.input))
+            .block(Block::default().borders(Borders::ALL).title(" Command (exit or Ctrl-C to quit) "));
+        f.render_widget(input, area);
+        f.set_cursor(area.x + 2 + self.input.chars().count() as u16, area.y + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dashboard_countdown_no_node_required() {
+        let timeout = Duration::from_secs(300);
+        assert_eq!(countdown(Duration::from_secs(0), timeout), "5:00");
+        assert_eq!(countdown(Duration::from_millis(61_500), timeout), "3:58");
+        assert_eq!(countdown(Duration::from_secs(295), timeout), "0:05");
+        assert_eq!(countdown(Duration::from_secs(300), timeout), "timed out");
+        assert_eq!(countdown(Duration::from_secs(400), timeout), "timed out");
+    }
+}
@@ -0,0 +1,165 @@
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, File, TryLockError};
+use std::io::ErrorKind;
+
+use crate::address::Address;
+use crate::common::{create_parent_dir, write_atomic};
+use crate::unit::Raw;
+
+/// Lock file of the data directory, held by the process that resumes interrupted payments
+#[cfg(not(test))]
+const LOCK_PATH: &str = "data/.lock";
+#[cfg(test)]
+const LOCK_PATH: &str = "test_output/.lock";
+
+/// The data directory lock, taken on first use and held until the process exits
+static DATA_LOCK: Lazy<Option<File>> = Lazy::new(|| match lock_file(LOCK_PATH) {
+    Ok(file) => Some(file),
+    Err(e) => {
+        warn!("JOURNAL {}", e);
+        None
+    }
+});
+
+fn lock_file(path: &str) -> Result<File, String> {
+    create_parent_dir(path)?;
+    let file = File::create(path).map_err(|e| format!("Could not open lock file {}: {}", path, e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!("{} is held by another process", path)),
+        Err(TryLockError::Error(e)) => Err(format!("Could not lock {}: {}", path, e)),
+    }
+}
+
+/// Check that this process holds the exclusive lock on the data directory
+/// Pool account indices and payment journals are shared by all processes using it, so only the holder resumes
+/// interrupted payments, e.g. a one-off command next to a running wallet leaves them to the wallet
+pub fn owns_data_dir() -> bool {
+    DATA_LOCK.is_some()
+}
+
+/// The pool payment a pool account was reserved for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentKind {
+    PoolSend,
+    PoolReceive,
+}
+
+/// A pool payment in progress, recorded from reserving its pool account until the account is returned
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    /// payment id, as logged
+    pub id: String,
+    pub kind: PaymentKind,
+    /// pool account index
+    pub index: u32,
+    pub amount: Raw,
+    /// destination of a send, empty for a receive
+    #[serde(default)]
+    pub destination: Address,
+}
+
+/// Pool payments of a wallet that have not finished yet, stored so they can be resumed after a shutdown or crash
+pub struct PaymentJournal {
+    path: String,
+    entries: Vec<JournalEntry>,
+}
+
+impl PaymentJournal {
+    /// Load the payments stored at path, a missing file means nothing is in progress
+    pub fn load(path: &str) -> Result<Self, String> {
+        let entries = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("Invalid payment journal {}: {}", path, e))?,
+            Err(_) => vec![],
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            entries,
+        })
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.entries.clone()
+    }
+
+    /// Record a payment before any of its blocks is published
+    pub fn add(&mut self, entry: JournalEntry) -> Result<(), String> {
+        info!("JOURNAL {:?} {} of {} on pool account {}", entry.kind, entry.id, entry.amount, entry.index);
+        self.entries.push(entry);
+        self.save()
+    }
+
+    /// Remove a payment once its pool account is returned
+    pub fn remove(&mut self, id: &str) -> Result<(), String> {
+        self.entries.retain(|e| e.id != id);
+        self.save()
+    }
+
+    /// Store the entries, or remove the file once nothing is in progress
+    fn save(&self) -> Result<(), String> {
+        if self.entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(format!("Could not remove payment journal {}: {}", self.path, e))
+                }
+                _ => Ok(()),
+            };
+        }
+        let json = serde_json::to_vec(&self.entries).map_err(|e| e.to_string())?;
+        write_atomic(&self.path, &json).map_err(|e| format!("Could not store payment journal {}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+    use std::path::Path;
+
+    #[test]
+    fn journal_no_node_required() {
+        let path = &test_path("payments.json");
+        let mut journal = PaymentJournal::load(path).unwrap();
+        assert!(journal.entries().is_empty());
+
+        let send = JournalEntry {
+            id: "ab12".to_owned(),
+            kind: PaymentKind::PoolSend,
+            index: 3,
+            amount: 5,
+            destination: "nano_a".to_owned(),
+        };
+        let receive = JournalEntry {
+            id: "cd34".to_owned(),
+            kind: PaymentKind::PoolReceive,
+            index: 4,
+            amount: 0,
+            destination: "".to_owned(),
+        };
+        journal.add(send.clone()).unwrap();
+        journal.add(receive.clone()).unwrap();
+        assert!(fs::read_to_string(path).unwrap().contains("\"kind\":\"pool_send\""));
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        // Payments in progress survive a restart
+        let mut journal = PaymentJournal::load(path).unwrap();
+        assert_eq!(journal.entries(), vec![send, receive.clone()]);
+        journal.remove("ab12").unwrap();
+        assert_eq!(PaymentJournal::load(path).unwrap().entries(), vec![receive]);
+        journal.remove("cd34").unwrap();
+        assert!(!Path::new(path).exists());
+    }
+
+    #[test]
+    fn journal_data_lock_no_node_required() {
+        assert!(owns_data_dir());
+        // A second process can't take the lock while it is held
+        assert_eq!(
+            lock_file(LOCK_PATH).err(),
+            Some(format!("{} is held by another process", LOCK_PATH))
+        );
+    }
+}
@@ -2,14 +2,25 @@ mod macros;
 
 pub mod account;
 pub mod address;
+pub mod audit;
 pub mod block;
 pub mod cli;
 pub mod common;
 pub mod config;
+pub mod contacts;
+pub mod dashboard;
+pub mod journal;
+pub mod limits;
 pub mod logger;
+pub mod metrics;
+pub mod offline;
 pub mod pool;
+pub mod registry;
 pub mod rpc;
+pub mod schedule;
 pub mod seed;
+pub mod shutdown;
 pub mod unit;
 pub mod wallet;
+pub mod work;
 pub mod ws;
@@ -0,0 +1,144 @@
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::write_atomic;
+use crate::unit::{raw_to_nano, Raw};
+
+/// Length of the rolling window of the daily limit in seconds
+const DAY: u64 = 24 * 60 * 60;
+
+/// An amount sent from a wallet at a unix timestamp
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Spent {
+    time: u64,
+    amount: Raw,
+}
+
+/// Maximum amounts a wallet may send in a single transaction and in any 24 hours, 0 means no limit
+/// The sends of the last 24 hours are stored, so the daily limit also holds across restarts
+pub struct SpendingLimits {
+    path: String,
+    transaction: Raw,
+    daily: Raw,
+    sent: Vec<Spent>,
+}
+
+impl SpendingLimits {
+    /// Load the recent sends stored at path, a missing file means nothing was sent yet
+    pub fn load(path: &str, transaction: Raw, daily: Raw) -> Result<Self, String> {
+        let sent = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| format!("Invalid spending record {}: {}", path, e))?,
+            Err(_) => vec![],
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            transaction,
+            daily,
+            sent,
+        })
+    }
+
+    /// Current unix timestamp in seconds
+    pub fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    /// Amount sent in the 24 hours before now
+    pub fn spent(&self, now: u64) -> Raw {
+        self.sent
+            .iter()
+            .filter(|s| s.time + DAY > now)
+            .map(|s| s.amount)
+            .sum()
+    }
+
+    /// Fail when sending amount now would exceed the transaction or daily limit
+    pub fn check(&self, amount: Raw, now: u64) -> Result<(), String> {
+        self.check_all(&[amount], now)
+    }
+
+    /// Fail when any of the amounts exceeds the transaction limit, or sending all of them now would exceed the
+    /// daily limit, e.g. for a file of payments that are signed at once
+    pub fn check_all(&self, amounts: &[Raw], now: u64) -> Result<(), String> {
+        for amount in amounts {
+            if self.transaction != 0 && *amount > self.transaction {
+                return Err(format!(
+                    "Sending {} XNO exceeds the transaction limit of {} XNO",
+                    raw_to_nano(*amount),
+                    raw_to_nano(self.transaction)
+                ));
+            }
+        }
+        let amount = amounts.iter().fold(0, |total: Raw, a| total.saturating_add(*a));
+        let spent = self.spent(now);
+        if self.daily != 0 && spent.saturating_add(amount) > self.daily {
+            return Err(format!(
+                "Sending {} XNO exceeds the daily limit of {} XNO, {} XNO was sent in the last 24 hours",
+                raw_to_nano(amount),
+                raw_to_nano(self.daily),
+                raw_to_nano(spent)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Record a send and store the sends of the last 24 hours
+    pub fn record(&mut self, amount: Raw, now: u64) -> Result<(), String> {
+        self.sent.retain(|s| s.time + DAY > now);
+        self.sent.push(Spent { time: now, amount });
+        info!("LIMITS {} XNO sent in the last 24 hours", raw_to_nano(self.spent(now)));
+        let json = serde_json::to_vec(&self.sent).map_err(|e| e.to_string())?;
+        write_atomic(&self.path, &json).map_err(|e| format!("Could not store spending record {}: {}", self.path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+    use crate::unit::RAW_PER_NANO;
+
+    #[test]
+    fn limits_no_node_required() {
+        let path = &test_path("limits.json");
+        let mut limits = SpendingLimits::load(path, 5 * RAW_PER_NANO, 8 * RAW_PER_NANO).unwrap();
+        let now = 1_700_000_000;
+
+        assert!(limits.check(5 * RAW_PER_NANO, now).is_ok());
+        assert_eq!(
+            limits.check(6 * RAW_PER_NANO, now),
+            Err("Sending 6 XNO exceeds the transaction limit of 5 XNO".to_owned())
+        );
+
+        limits.record(5 * RAW_PER_NANO, now).unwrap();
+        limits.record(2 * RAW_PER_NANO, now + 3600).unwrap();
+        assert_eq!(limits.spent(now + 3600), 7 * RAW_PER_NANO);
+        assert!(limits.check(RAW_PER_NANO, now + 3600).is_ok());
+        assert_eq!(
+            limits.check(2 * RAW_PER_NANO, now + 3600),
+            Err("Sending 2 XNO exceeds the daily limit of 8 XNO, 7 XNO was sent in the last 24 hours".to_owned())
+        );
+
+        // The record is kept across restarts, and sends older than 24 hours no longer count
+        let limits = SpendingLimits::load(path, 5 * RAW_PER_NANO, 8 * RAW_PER_NANO).unwrap();
+        assert_eq!(limits.spent(now + 3600), 7 * RAW_PER_NANO);
+        assert_eq!(limits.spent(now + DAY), 2 * RAW_PER_NANO);
+        assert!(limits.check(5 * RAW_PER_NANO, now + DAY).is_ok());
+
+        // Payments that are signed at once are checked one by one and in total
+        assert!(limits.check_all(&[2 * RAW_PER_NANO, 4 * RAW_PER_NANO], now + DAY).is_ok());
+        assert_eq!(
+            limits.check_all(&[2 * RAW_PER_NANO, 6 * RAW_PER_NANO], now + DAY),
+            Err("Sending 6 XNO exceeds the transaction limit of 5 XNO".to_owned())
+        );
+        assert_eq!(
+            limits.check_all(&[4 * RAW_PER_NANO, 3 * RAW_PER_NANO], now + DAY),
+            Err("Sending 7 XNO exceeds the daily limit of 8 XNO, 2 XNO was sent in the last 24 hours".to_owned())
+        );
+
+        let unlimited = SpendingLimits::load(&test_path("limits_none.json"), 0, 0).unwrap();
+        assert!(unlimited.check(1000 * RAW_PER_NANO, now).is_ok());
+    }
+}
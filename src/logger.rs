@@ -1,128 +1,339 @@
-use log::{Level, LevelFilter, Log, Metadata, Record};
+use log::kv::{Error as KvError, Key, Value as KvValue, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 use time;
 
-static LOGGER: Logger = Logger;
-
-const LOGFILE_COUNT: usize = 6;
-const LOGFILES: [LogFile; LOGFILE_COUNT] = [
-    LogFile {
-        path: "log/rpc.log",
-        level: Level::Info,
-        targets: ["nano_pool::rpc", "", ""],
-    },
-    LogFile {
-        path: "log/ws.log",
-        level: Level::Info,
-        targets: ["nano_pool::ws", "", ""],
-    },
-    LogFile {
-        path: "log/wallet.log",
-        level: Level::Info,
-        targets: ["nano_pool::wallet", "nano_pool::pool", "nano_pool::account"],
-    },
-    LogFile {
-        path: "log/info.log",
-        level: Level::Info,
-        targets: ["", "", ""],
-    },
-    LogFile {
-        path: "log/error.log",
-        level: Level::Error,
-        targets: ["", "", ""],
-    },
-    LogFile {
-        path: "log/cli.log",
-        level: Level::Info,
-        targets: ["nano_pool::cli", "", ""],
-    },
-];
+use crate::config::{LogFileConfig, CONFIG};
+
+static LOGGER: Lazy<Logger> = Lazy::new(Logger::from_config);
+
+/// Cleared for non-interactive commands and the dashboard, the log files are always written
+static LOG_TO_CONSOLE: AtomicBool = AtomicBool::new(true);
+
+/// Prints a console log line, e.g. above the interactive prompt instead of through it
+pub type ConsolePrinter = Box<dyn FnMut(String) + Send>;
+
+static CONSOLE_PRINTER: Lazy<Mutex<Option<ConsolePrinter>>> = Lazy::new(|| Mutex::new(None));
+
+/// JSON fields whose values are never logged, e.g. the key sent with block_create
+const SECRET_FIELDS: [&str; 4] = ["key", "seed", "private_key", "prv"];
+const REDACTED: &str = "<redacted>";
+
+thread_local! {
+    /// Payment the current thread is working on, added to its log records
+    static PAYMENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
 
 /// Start logger
 pub fn start_logger() {
-    // Create and empty log files for session
-    for log in LOGFILES {
-        log.init();
+    let _ = log::set_logger(&*LOGGER).map(|()| log::set_max_level(LOGGER.max_level()));
+}
+
+/// Enable or disable logging to the console, the log files are always written
+pub fn log_to_console(enabled: bool) {
+    LOG_TO_CONSOLE.store(enabled, Ordering::Relaxed);
+}
+
+/// Print console log lines with printer, e.g. above a line editor prompt, None prints them directly again
+pub fn set_console_printer(printer: Option<ConsolePrinter>) {
+    *CONSOLE_PRINTER.lock().unwrap() = printer;
+}
+
+/// Add the payment id as a field to everything logged on this thread until the guard is dropped
+pub fn log_payment(payment: &str) -> PaymentGuard {
+    PaymentGuard(PAYMENT.with(|p| p.replace(Some(payment.to_owned()))))
+}
+
+/// Restores the payment logged before, see log_payment
+pub struct PaymentGuard(Option<String>);
+
+impl Drop for PaymentGuard {
+    fn drop(&mut self) {
+        PAYMENT.with(|p| *p.borrow_mut() = self.0.take());
+    }
+}
+
+/// Replace the values of secret JSON fields and any of the given secrets in a log message
+pub fn redact(message: &str, secrets: &[String]) -> String {
+    let mut message = message.to_owned();
+    for field in SECRET_FIELDS {
+        let pattern = format!("\"{}\":", field);
+        let mut from = 0;
+        while let Some(i) = message[from..].find(&pattern) {
+            let start = from + i + pattern.len();
+            let rest = &message[start..];
+            let value_start = start + rest.len() - rest.trim_start().len();
+            from = start;
+            if !message[value_start..].starts_with('"') {
+                continue;
+            }
+            if let Some(end) = message[value_start + 1..].find('"') {
+                message.replace_range(value_start + 1..value_start + 1 + end, REDACTED);
+                from = value_start + 1 + REDACTED.len();
+            }
+        }
+    }
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        let secret = secret.to_ascii_lowercase();
+        while let Some(i) = message.to_ascii_lowercase().find(&secret) {
+            message.replace_range(i..i + secret.len(), REDACTED);
+        }
+    }
+    message
+}
+
+/// Module of a log target, e.g. wallet for nano_pool::wallet, or the crate name for other crates
+fn module(target: &str) -> &str {
+    let target = target.strip_prefix("nano_pool::").unwrap_or(target);
+    target.split("::").next().unwrap_or(target)
+}
+
+/// Collects the key-value fields of a record, e.g. account and hash
+#[derive(Default)]
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), KvError> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
     }
+}
 
-    // Set up logger
-   let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Info));
+struct OpenFile {
+    file: File,
+    size: u64,
+    opened: SystemTime,
 }
 
+/// A log file receiving the records of some modules up to a level, rotated by size or age
 struct LogFile {
-    path: &'static str,
-    level: Level,
-    targets: [&'static str; 3],
+    path: PathBuf,
+    level: LevelFilter,
+    /// All modules when empty
+    modules: Vec<String>,
+    open: Mutex<Option<OpenFile>>,
 }
 
 impl LogFile {
-    fn init(&self) {
-        let filepath = Path::new(self.path);
-        // Create log dir
-        let folderpath = filepath.parent();
-        match folderpath {
-            Some(path) if !path.exists() => {
-                fs::create_dir(path).unwrap();
+    fn new(config: &LogFileConfig) -> Self {
+        Self {
+            path: Path::new(&CONFIG.logging.directory).join(&config.path),
+            level: config.level.parse().unwrap_or(LevelFilter::Info),
+            modules: config.modules.clone(),
+            open: Mutex::new(None),
+        }
+    }
+
+    fn accepts(&self, module: &str, level: LevelFilter) -> bool {
+        level <= self.level && (self.modules.is_empty() || self.modules.iter().any(|m| m == module))
+    }
+
+    /// Append a line, the file is kept open and rotated first once it is too large or too old
+    fn write(&self, line: &str) {
+        let mut open = self.open.lock().unwrap();
+        let config = &CONFIG.logging;
+        let rotate = match &*open {
+            Some(f) => {
+                (config.rotate_size != 0 && f.size >= config.rotate_size * 1024 * 1024)
+                    || (config.rotate_hours != 0
+                        && f.opened.elapsed().unwrap_or_default() >= Duration::from_secs(config.rotate_hours as u64 * 3600))
+            }
+            None => false,
+        };
+        if rotate {
+            *open = None;
+            self.rotate(config.rotate_keep);
+        }
+        if open.is_none() {
+            *open = self.open_file();
+        }
+        if let Some(f) = open.as_mut() {
+            if writeln!(f.file, "{}", line).is_ok() {
+                f.size += line.len() as u64 + 1;
             }
-            _ => {}
         }
+    }
 
-        // Create or empty log file
-        if filepath.exists() {
-            fs::remove_file(filepath).unwrap();
+    /// Open for appending, an existing file is continued instead of emptied
+    fn open_file(&self) -> Option<OpenFile> {
+        if let Some(folder) = self.path.parent() {
+            if !folder.exists() {
+                fs::create_dir_all(folder).ok()?;
+            }
         }
-        File::create(filepath).unwrap();
+        let file = fs::OpenOptions::new().create(true).append(true).open(&self.path).ok()?;
+        let metadata = file.metadata().ok()?;
+        let opened = match metadata.len() {
+            0 => SystemTime::now(),
+            _ => metadata.created().unwrap_or_else(|_| SystemTime::now()),
+        };
+        Some(OpenFile {
+            file,
+            size: metadata.len(),
+            opened,
+        })
     }
 
-    fn write(&self, log: &String) {
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(self.path)
-            .unwrap();
-        write!(file, "{}", log).unwrap();
+    /// Shift path.1 .. path.<keep> up by one and move the current file to path.1, the oldest is removed
+    fn rotate(&self, keep: u32) {
+        if keep == 0 {
+            let _ = fs::remove_file(&self.path);
+            return;
+        }
+        let rotated = |i: u32| PathBuf::from(format!("{}.{}", self.path.display(), i));
+        let _ = fs::remove_file(rotated(keep));
+        for i in (1..keep).rev() {
+            let _ = fs::rename(rotated(i), rotated(i + 1));
+        }
+        let _ = fs::rename(&self.path, rotated(1));
     }
 }
 
-pub struct Logger;
+pub struct Logger {
+    level: LevelFilter,
+    /// Levels of single modules, overriding level
+    modules: BTreeMap<String, LevelFilter>,
+    json: bool,
+    console: String,
+    files: Vec<LogFile>,
+    /// Seeds of the configured wallets, redacted wherever they would appear
+    secrets: Vec<String>,
+}
+
+impl Logger {
+    fn from_config() -> Self {
+        let config = &CONFIG.logging;
+        let mut secrets = vec![CONFIG.wallet.seed.clone()];
+        secrets.extend(CONFIG.wallets.values().map(|w| w.seed.clone()));
+        Self {
+            level: config.level.parse().unwrap_or(LevelFilter::Info),
+            modules: config
+                .modules
+                .iter()
+                .map(|(m, l)| (m.clone(), l.parse().unwrap_or(LevelFilter::Info)))
+                .collect(),
+            json: config.format == "json",
+            console: config.console.clone(),
+            files: config.files.iter().map(LogFile::new).collect(),
+            secrets,
+        }
+    }
+
+    fn module_level(&self, module: &str) -> LevelFilter {
+        *self.modules.get(module).unwrap_or(&self.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules.values().copied().fold(self.level, Ord::max)
+    }
+
+    /// Format a record as a text or JSON line, with its fields and without secrets
+    fn format(&self, record: &Record) -> String {
+        let time = time::strftime("%Y/%m/%d %H:%M:%S.%f", &time::now()).unwrap();
+        let message = redact(&record.args().to_string(), &self.secrets);
+        let mut fields = Fields::default();
+        let _ = record.key_values().visit(&mut fields);
+        if let Some(payment) = PAYMENT.with(|p| p.borrow().clone()) {
+            fields.0.push(("payment".to_owned(), payment));
+        }
+        if self.json {
+            let mut line = Map::new();
+            line.insert("time".to_owned(), json!(time));
+            line.insert("level".to_owned(), json!(record.level().as_str()));
+            line.insert("module".to_owned(), json!(module(record.target())));
+            line.insert("message".to_owned(), json!(message));
+            for (key, value) in fields.0 {
+                line.insert(key, json!(redact(&value, &self.secrets)));
+            }
+            Value::Object(line).to_string()
+        } else {
+            let mut line = format!("{} {} {}", time, record.level(), message);
+            for (key, value) in fields.0 {
+                line.push_str(&format!(" {}={}", key, redact(&value, &self.secrets)));
+            }
+            line
+        }
+    }
+
+    fn print(&self, line: String) {
+        if !LOG_TO_CONSOLE.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Some(printer) = CONSOLE_PRINTER.lock().unwrap().as_mut() {
+            printer(line);
+            return;
+        }
+        match self.console.as_str() {
+            "stdout" => println!("{}", line),
+            "stderr" => eprintln!("{}", line),
+            _ => {}
+        }
+    }
+}
 
 impl Log for Logger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
-    }
-
-    /// Always print to stdout, and log to the following files:
-    /// rpc
-    /// ws
-    /// wallet
-    /// TRACE
-    /// DEBUG
-    /// INFO
-    /// WARN
-    /// ERROR
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.module_level(module(metadata.target()))
+    }
+
+    /// Print to the console unless disabled, and write to the log files accepting the module and level
     fn log(&self, record: &Record) {
-        let message = format!(
-            "{} {} {}\n",
-            time::strftime("%Y/%m/%d %H:%M:%S.%f", &time::now()).unwrap(),
-            record.level(),
-            record.args()
-        );
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let module = module(record.target());
+        let level = record.level().to_level_filter();
+        let line = self.format(record);
+        for file in self.files.iter().filter(|f| f.accepts(module, level)) {
+            file.write(&line);
+        }
+        if self.console != "none" {
+            self.print(line);
+        }
+    }
 
-        // Log to files
-        let level = record.level();
-        let target = record.target();
-        for log in LOGFILES {
-            if log.level >= level && (log.targets.contains(&target) || log.targets[0] == "") {
-                log.write(&message);
+    fn flush(&self) {
+        for file in &self.files {
+            if let Some(f) = file.open.lock().unwrap().as_mut() {
+                let _ = f.file.flush();
             }
         }
-
-        // Log to stdout
-        println!("{}", message);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn flush(&self) {}
+    #[test]
+    fn logger_redact_no_node_required() {
+        let message = r#"RPC send http://[::1]:7076 {"action":"block_create","key":"1A2B3C","seed": "4D5E","type":"state"}"#;
+        assert_eq!(
+            redact(message, &[]),
+            r#"RPC send http://[::1]:7076 {"action":"block_create","key":"<redacted>","seed": "<redacted>","type":"state"}"#
+        );
+        let seed = "907933879BCBA2F7C92E3A03A59255F3BC3B72C4AD49E7A57B48B7DF5F4ADD20".to_owned();
+        assert_eq!(
+            redact(&format!("seed {} and {}", seed, seed.to_lowercase()), &[seed.clone(), "".to_owned()]),
+            "seed <redacted> and <redacted>"
+        );
+        assert_eq!(redact(r#"{"keys":1,"key":2}"#, &[]), r#"{"keys":1,"key":2}"#);
+
+        assert_eq!(module("nano_pool::wallet"), "wallet");
+        assert_eq!(module("websocket::client"), "websocket");
+        let payment = log_payment("ab12");
+        assert_eq!(PAYMENT.with(|p| p.borrow().clone()), Some("ab12".to_owned()));
+        drop(payment);
+        assert_eq!(PAYMENT.with(|p| p.borrow().clone()), None);
+    }
 }
@@ -1,12 +1,121 @@
-use nano_pool::cli::start_cli;
-use nano_pool::logger::start_logger;
+use std::env;
+use std::process;
+
+use nano_pool::cli::{run_batch, run_once, start_cli, subcommand_line, EXIT_FAILED, EXIT_OK, EXIT_USAGE};
+use nano_pool::config::{check_config, set_config_path, CONFIG};
+use nano_pool::dashboard::start_dashboard;
+use nano_pool::logger::{log_to_console, start_logger};
+use nano_pool::metrics::start_metrics;
+use nano_pool::offline::{block_qr, describe_block, export_block, import_block, sign_with_config};
 use nano_pool::rpc::start_rpc;
+use nano_pool::shutdown::{shutdown, start_signal_handler};
 use nano_pool::ws::start_ws;
 
+/// Sign an exported block with a key from the config and write it to output
+fn sign_offline(input: &str, output: &str) -> Result<(), String> {
+    let mut block = import_block(input)?;
+    println!("{}", describe_block(&block));
+    sign_with_config(&mut block)?;
+    export_block(&block, output)?;
+    println!("{}", block_qr(&block)?);
+    println!("Signed block written to {}", output);
+    Ok(())
+}
+
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--config") {
+        match args.get(i + 1) {
+            Some(path) => set_config_path(path).unwrap(),
+            None => {
+                eprintln!("--config requires a path");
+                process::exit(1);
+            }
+        }
+    }
+
+    // Offline signing mode, doesn't require a node
+    if let Some(i) = args.iter().position(|a| a == "--sign") {
+        match (args.get(i + 1), args.get(i + 2)) {
+            (Some(input), Some(output)) => match sign_offline(input, output) {
+                Ok(_) => process::exit(0),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            },
+            _ => {
+                eprintln!("--sign requires an unsigned block file and an output file");
+                process::exit(1);
+            }
+        }
+    }
+    let json = args.iter().any(|a| a == "--json");
+    let batch = match args.iter().position(|a| a == "--batch") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => Some(path.clone()),
+            None => {
+                eprintln!("--batch requires a file with commands");
+                process::exit(EXIT_USAGE);
+            }
+        },
+        None => None,
+    };
+    let dashboard = args.iter().any(|a| a == "--dashboard");
+    let subcommand = subcommand_line(&args);
+    check_config();
+
     start_logger();
+    // Only the command output goes to stdout when running non-interactively, logs may still go to stderr,
+    // and nothing but the dashboard is drawn on the terminal
+    let interactive = batch.is_none() && subcommand.is_none();
+    log_to_console(!dashboard && (interactive || CONFIG.logging.console == "stderr"));
+    // Only long running sessions serve metrics, so one-off commands don't take the port of a running wallet
+    if interactive {
+        if let Err(e) = start_metrics() {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILED);
+        }
+    }
     let rpc_tx = start_rpc();
-    let ws_tx = start_ws();
-    start_cli(rpc_tx, ws_tx);
-    loop {}
+    let ws_tx = match start_ws() {
+        Ok(ws_tx) => ws_tx,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_FAILED);
+        }
+    };
+    if let Err(e) = start_signal_handler(ws_tx.clone()) {
+        eprintln!("{}", e);
+        process::exit(EXIT_FAILED);
+    }
+    let code = if let Some(path) = batch {
+        run_batch(rpc_tx, ws_tx.clone(), &path, json)
+    } else if let Some(line) = subcommand {
+        run_once(rpc_tx, ws_tx.clone(), &line, json)
+    } else if dashboard {
+        match start_dashboard(rpc_tx, ws_tx.clone()) {
+            Ok(()) => EXIT_OK,
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_FAILED
+            }
+        }
+    } else {
+        match start_cli(rpc_tx, ws_tx.clone()) {
+            Ok(cli) => {
+                cli.join().unwrap();
+                EXIT_OK
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_FAILED
+            }
+        }
+    };
+    // Scheduled payments and commands still running in the dashboard are waited for
+    if !shutdown(&ws_tx) && code == EXIT_OK {
+        process::exit(EXIT_FAILED);
+    }
+    process::exit(code);
 }
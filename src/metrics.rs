@@ -0,0 +1,263 @@
+use log::{error, info};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::account::Account;
+use crate::config::CONFIG;
+use crate::pool::{PoolStatus, PoolView};
+use crate::unit::{raw_to_nano, Raw};
+
+static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+/// Wallets whose pool and balance are read when the metrics are scraped
+static WALLETS: Lazy<Mutex<Vec<WatchedWallet>>> = Lazy::new(|| Mutex::new(vec![]));
+
+/// Upper bounds in seconds of the histogram buckets
+const BUCKETS: [f64; 12] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Name, type and help of every exported metric, in the order they are rendered
+const DESCRIPTIONS: [(&str, &str, &str); 12] = [
+    ("nano_pool_rpc_requests_total", "counter", "Rpc requests sent by action"),
+    ("nano_pool_rpc_errors_total", "counter", "Rpc requests that failed or returned an error by action"),
+    ("nano_pool_rpc_request_seconds", "histogram", "Rpc request latency by action"),
+    ("nano_pool_ws_reconnects_total", "counter", "Websocket reconnects to the node"),
+    ("nano_pool_ws_messages_total", "counter", "Websocket messages received by topic"),
+    ("nano_pool_pool_accounts", "gauge", "Pool accounts by wallet and status"),
+    ("nano_pool_payments_total", "counter", "Payments through the pool or directly from the main account by wallet, kind and result"),
+    ("nano_pool_sweeps_total", "counter", "Pool account balances swept to the main account"),
+    ("nano_pool_swept_nano_total", "counter", "Amount in nano swept from pool accounts to the main account"),
+    ("nano_pool_balance_nano", "gauge", "Main account balance in nano by wallet"),
+    ("nano_pool_work_seconds", "histogram", "Work generation time by source"),
+    ("nano_pool_up", "gauge", "Whether the wallet is running"),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default, Clone)]
+struct Histogram {
+    /// Observations per bucket of BUCKETS, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Counters, gauges and histograms by name and labels
+#[derive(Default)]
+pub struct Metrics {
+    values: BTreeMap<(&'static str, Labels), f64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+#[derive(Clone)]
+struct WatchedWallet {
+    name: String,
+    account: Arc<Mutex<Account>>,
+    pool: PoolView,
+}
+
+/// Add to a counter
+pub fn count(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    METRICS.lock().unwrap().add(name, labels, value);
+}
+
+/// Set a gauge
+pub fn gauge(name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+    METRICS.lock().unwrap().set(name, labels, value);
+}
+
+/// Record a duration in a histogram
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], duration: Duration) {
+    METRICS.lock().unwrap().observe(name, labels, duration.as_secs_f64());
+}
+
+/// Export the pool status and main account balance of a wallet
+pub fn watch_wallet(name: &str, account: Arc<Mutex<Account>>, pool: PoolView) {
+    let mut wallets = WALLETS.lock().unwrap();
+    wallets.retain(|w| w.name != name);
+    wallets.push(WatchedWallet {
+        name: name.to_owned(),
+        account,
+        pool,
+    });
+}
+
+/// Amount in nano as a metric value
+pub fn nano(raw: Raw) -> f64 {
+    raw_to_nano(raw).parse().unwrap_or_default()
+}
+
+/// Serve the metrics on http://<metrics.address>:<metrics.port>/metrics when enabled
+pub fn start_metrics() -> Result<(), String> {
+    if !CONFIG.metrics.enabled {
+        return Ok(());
+    }
+    let address = format!("{}:{}", CONFIG.metrics.address, CONFIG.metrics.port);
+    let listener = TcpListener::bind(&address).map_err(|e| format!("Metrics could not listen on {}: {}", address, e))?;
+    info!("METRICS listening on http://{}/metrics", address);
+    gauge("nano_pool_up", &[], 1.0);
+    thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = respond(stream) {
+                            error!("METRICS request failed: {}", e);
+                        }
+                    }
+                    Err(e) => error!("METRICS connection failed: {}", e),
+                }
+            }
+        })
+        .unwrap();
+    Ok(())
+}
+
+/// Answer GET /metrics with the metrics in the prometheus text format, anything else with 404
+fn respond(stream: TcpStream) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request).map_err(|e| e.to_string())?;
+    // Skip the headers, the request has no body
+    let mut header = String::new();
+    while reader.read_line(&mut header).map_err(|e| e.to_string())? > 2 {
+        header.clear();
+    }
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", render()),
+        _ => ("404 Not Found", "Not found, metrics are at /metrics\n".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    reader
+        .get_mut()
+        .write_all(response.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+/// Read the watched wallets and render all metrics
+fn render() -> String {
+    let wallets = WALLETS.lock().unwrap().clone();
+    for wallet in wallets {
+        let accounts = wallet.pool.accounts();
+        let reserved = accounts.iter().filter(|(_, s)| *s != PoolStatus::Free).count();
+        for (status, value) in [("free", accounts.len() - reserved), ("reserved", reserved)] {
+            gauge(
+                "nano_pool_pool_accounts",
+                &[("wallet", &wallet.name), ("status", status)],
+                value as f64,
+            );
+        }
+        // The account is locked while it publishes, the last balance is kept then
+        if let Ok(account) = wallet.account.try_lock() {
+            gauge("nano_pool_balance_nano", &[("wallet", &wallet.name)], nano(account.balance()));
+        }
+    }
+    METRICS.lock().unwrap().render()
+}
+
+impl Metrics {
+    fn key(name: &'static str, labels: &[(&'static str, &str)]) -> (&'static str, Labels) {
+        (name, labels.iter().map(|(k, v)| (*k, v.to_string())).collect())
+    }
+
+    pub fn add(&mut self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        *self.values.entry(Metrics::key(name, labels)).or_default() += value;
+    }
+
+    pub fn set(&mut self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.values.insert(Metrics::key(name, labels), value);
+    }
+
+    pub fn observe(&mut self, name: &'static str, labels: &[(&'static str, &str)], seconds: f64) {
+        let histogram = self.histograms.entry(Metrics::key(name, labels)).or_default();
+        if let Some(i) = BUCKETS.iter().position(|b| seconds <= *b) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// All metrics in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help) in DESCRIPTIONS {
+            out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+            for ((_, labels), value) in self.values.range((name, vec![])..).take_while(|((n, _), _)| *n == name) {
+                out.push_str(&format!("{}{} {}\n", name, format_labels(labels, None), value));
+            }
+            for ((_, labels), histogram) in self.histograms.range((name, vec![])..).take_while(|((n, _), _)| *n == name) {
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(histogram.buckets.iter()) {
+                    cumulative += count;
+                    let le = bound.to_string();
+                    out.push_str(&format!("{}_bucket{} {}\n", name, format_labels(labels, Some(&le)), cumulative));
+                }
+                out.push_str(&format!("{}_bucket{} {}\n", name, format_labels(labels, Some("+Inf")), histogram.count));
+                out.push_str(&format!("{}_sum{} {}\n", name, format_labels(labels, None), histogram.sum));
+                out.push_str(&format!("{}_count{} {}\n", name, format_labels(labels, None), histogram.count));
+            }
+        }
+        out
+    }
+}
+
+/// Labels formatted as {key="value",...}, with the le label of a histogram bucket last
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{}\"", le));
+    }
+    if parts.is_empty() {
+        "".to_owned()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_render_no_node_required() {
+        let mut metrics = Metrics::default();
+        metrics.add("nano_pool_rpc_requests_total", &[("action", "process")], 1.0);
+        metrics.add("nano_pool_rpc_requests_total", &[("action", "process")], 1.0);
+        metrics.add("nano_pool_ws_reconnects_total", &[], 1.0);
+        metrics.set("nano_pool_balance_nano", &[("wallet", "shop \"1\"")], 1.5);
+        metrics.observe("nano_pool_work_seconds", &[("source", "local")], 0.3);
+        metrics.observe("nano_pool_work_seconds", &[("source", "local")], 200.0);
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE nano_pool_rpc_requests_total counter\nnano_pool_rpc_requests_total{action=\"process\"} 2\n"));
+        assert!(text.contains("\nnano_pool_ws_reconnects_total 1\n"));
+        assert!(text.contains("\nnano_pool_balance_nano{wallet=\"shop \\\"1\\\"\"} 1.5\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_bucket{source=\"local\",le=\"0.25\"} 0\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_bucket{source=\"local\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_bucket{source=\"local\",le=\"120\"} 1\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_bucket{source=\"local\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_sum{source=\"local\"} 200.3\n"));
+        assert!(text.contains("\nnano_pool_work_seconds_count{source=\"local\"} 2\n"));
+        assert!(text.contains("# HELP nano_pool_payments_total "));
+        assert_eq!(nano(1_500_000_000_000_000_000_000_000_000_000), 1.5);
+    }
+}
@@ -0,0 +1,238 @@
+use log::info;
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use std::fs;
+use std::sync::mpsc::Sender;
+
+use crate::account::Account;
+use crate::address::{address_to_public_key, Address};
+use crate::audit::{audit, AuditEntry, AuditReason};
+use crate::block::Block;
+use crate::common::{bytes_to_hexstring, hexstring_to_bytes, write_atomic};
+use crate::config::CONFIG;
+use crate::rpc::{rpc_account_info, rpc_process, RpcCommand, SUBTYPE};
+use crate::unit::Raw;
+use crate::work::{generate_work, receive_difficulty, send_difficulty};
+
+/// Build an unsigned send block on top of the current account frontier, for signing on an offline machine
+pub fn prepare_send(
+    rpc_tx: Sender<RpcCommand>,
+    account: &Address,
+    destination: &Address,
+    amount: Raw,
+) -> Result<Block, String> {
+    let link = bytes_to_hexstring(&address_to_public_key(destination)?);
+    let info = rpc_account_info(rpc_tx, account, Some(true))?;
+    if info.balance < amount {
+        return Err(format!(
+            "Account {} insufficient balance ({}) to send {}",
+            account, info.balance, amount
+        ));
+    }
+    let representative = info
+        .representative
+        .ok_or(format!("Account {} has no representative", account))?;
+    let block = Block::new(
+        account.clone(),
+        info.frontier,
+        representative,
+        info.balance - amount,
+        link,
+        destination.clone(),
+        None,
+        None,
+        Some("send".to_owned()),
+    );
+    info!(
+        "OFFLINE prepared unsigned send {} of {} from {} to {}",
+        block.hash()?,
+        amount,
+        account,
+        destination
+    );
+    Ok(block)
+}
+
+/// Sign a block with the main account key of the configured wallet it belongs to, without a node
+pub fn sign_with_config(block: &mut Block) -> Result<(), String> {
+    let mut seeds = vec![&CONFIG.wallet.seed];
+    for wallet in CONFIG.wallets.values() {
+        seeds.push(&wallet.seed);
+    }
+    for seed in seeds.into_iter().filter(|s| !s.is_empty()) {
+        let private_key = Account::derive_private_key(hexstring_to_bytes(seed), 0);
+        let public_key = Account::derive_public_key(private_key);
+        if Account::derive_address(public_key) == block.account {
+            block.sign(private_key.as_bytes())?;
+            info!("OFFLINE signed {} for {}", block.hash()?, block.account);
+            return Ok(());
+        }
+    }
+    Err(format!("No configured wallet holds the key for {}", block.account))
+}
+
+/// Check a signed block, attach work and publish it
+pub fn publish_signed(rpc_tx: Sender<RpcCommand>, mut block: Block) -> Result<String, String> {
+    block.verify_signature()?;
+    let info = rpc_account_info(rpc_tx.clone(), &block.account, None);
+    let (frontier, previous_balance) = match info {
+        Ok(info) => (info.frontier, info.balance),
+        Err(_) => ("0".repeat(64), 0),
+    };
+    if block.previous != frontier && !(block.previous == "0" && frontier == "0".repeat(64)) {
+        return Err(format!(
+            "Block previous {} is not the current frontier {} of {}",
+            block.previous, frontier, block.account
+        ));
+    }
+
+    let subtype = match block.subtype.as_deref() {
+        Some("send") => SUBTYPE::SEND,
+        Some("receive") | Some("open") => SUBTYPE::RECEIVE,
+        Some("change") => SUBTYPE::CHANGE,
+        s => return Err(format!("Unsupported block subtype {:?}", s)),
+    };
+    if block.work.is_none() {
+        let difficulty = match subtype {
+            SUBTYPE::RECEIVE => receive_difficulty(),
+            _ => send_difficulty(),
+        };
+        block.work = Some(generate_work(rpc_tx.clone(), block.work_root()?, &difficulty)?);
+    }
+    let expected = block.hash()?;
+    let balance = block.balance.parse::<Raw>().map_err(|e| format!("Invalid block balance: {}", e))?;
+    let (amount, link) = match subtype {
+        SUBTYPE::SEND => (previous_balance.saturating_sub(balance), block.link_as_account.clone()),
+        _ => (balance.saturating_sub(previous_balance), block.link.clone()),
+    };
+    let account = block.account.clone();
+    let hash = rpc_process(rpc_tx, subtype, block)?;
+    if hash != expected {
+        return Err(format!("Published block hash {} does not match {}", hash, expected));
+    }
+    info!("OFFLINE published signed block {}", hash);
+    audit(AuditEntry::new(&hash, &account, None, subtype, amount, &link, AuditReason::Offline));
+    Ok(hash)
+}
+
+/// Write a block as json
+pub fn export_block(block: &Block, path: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(block).map_err(|e| e.to_string())?;
+    write_atomic(path, json.as_bytes())
+}
+
+/// Read a block from json
+pub fn import_block(path: &str) -> Result<Block, String> {
+    let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&json).map_err(|e| format!("Invalid block json in {}: {}", path, e))
+}
+
+/// Render a block as a QR code of its json, to transfer it by camera
+pub fn block_qr(block: &Block) -> Result<String, String> {
+    let json = serde_json::to_string(block).map_err(|e| e.to_string())?;
+    let code = QrCode::new(json.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Describe a block for review before signing or publishing
+pub fn describe_block(block: &Block) -> String {
+    format!(
+        "{} block {}\naccount: {}\nprevious: {}\nrepresentative: {}\nbalance: {}\nlink: {}\nsigned: {}",
+        block.subtype.as_deref().unwrap_or("state"),
+        block.hash().unwrap_or_else(|e| e),
+        block.account,
+        block.previous,
+        block.representative,
+        block.balance,
+        block.link_as_account,
+        block.signature.is_some()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+
+    fn unsigned_send() -> Block {
+        let (_, account) = key_and_address(0);
+        let (_, destination) = key_and_address(1);
+        Block::new(
+            account,
+            "991CF190094C00F0B68E2E5F75F6BEE95A2E0BD93CEAA4A6734DB9F19B728948".to_owned(),
+            "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit".to_owned(),
+            1000,
+            bytes_to_hexstring(&address_to_public_key(&destination).unwrap()),
+            destination,
+            None,
+            None,
+            Some("send".to_owned()),
+        )
+    }
+
+    fn key_and_address(index: u32) -> (blake2b_simd::Hash, Address) {
+        let seed = hexstring_to_bytes("0000000000000000000000000000000000000000000000000000000000000000");
+        let private_key = Account::derive_private_key(seed, index);
+        let address = Account::derive_address(Account::derive_public_key(private_key));
+        (private_key, address)
+    }
+
+    #[test]
+    fn offline_sign_verify_no_node_required() {
+        let mut block = unsigned_send();
+        let hash = block.hash().unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(block.work_root().unwrap(), block.previous);
+        assert!(block.verify_signature().is_err());
+
+        // Only the account key can sign
+        let (other_key, _) = key_and_address(1);
+        assert!(block.sign(other_key.as_bytes()).is_err());
+
+        let (private_key, _) = key_and_address(0);
+        block.sign(private_key.as_bytes()).unwrap();
+        assert_eq!(block.signature.as_ref().unwrap().len(), 128);
+        assert!(block.verify_signature().is_ok());
+        assert_eq!(block.hash().unwrap(), hash);
+
+        // Changing any field invalidates the signature
+        let mut tampered = block.clone();
+        tampered.balance = "999".to_owned();
+        assert!(tampered.verify_signature().is_err());
+    }
+
+    #[test]
+    fn offline_export_import_no_node_required() {
+        let path = &test_path("offline_block.json");
+        let block = unsigned_send();
+        export_block(&block, path).unwrap();
+        assert_eq!(import_block(path).unwrap(), block);
+
+        // Hex fields with multi-byte characters are rejected, not sliced
+        let mut invalid = block.clone();
+        invalid.previous = "aé".repeat(22);
+        invalid.signature = Some("aé".repeat(44));
+        export_block(&invalid, path).unwrap();
+        let imported = import_block(path).unwrap();
+        assert_eq!(imported.hash().err(), Some(format!("{} is not a valid hex string", invalid.previous)));
+        assert!(imported.verify_signature().unwrap_err().contains("is not a valid hex string"));
+        fs::remove_file(path).unwrap();
+
+        assert!(!block_qr(&block).unwrap().is_empty());
+        assert!(describe_block(&block).starts_with(&format!("send block {}", block.hash().unwrap())));
+    }
+
+    #[test]
+    fn offline_open_block_work_root_no_node_required() {
+        let mut block = unsigned_send();
+        block.previous = "0".to_owned();
+        assert_eq!(
+            block.work_root().unwrap(),
+            bytes_to_hexstring(&address_to_public_key(&block.account).unwrap())
+        );
+    }
+}
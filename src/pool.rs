@@ -1,17 +1,103 @@
-use std::collections::VecDeque;
+use log::error;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use crate::account::Account;
 use crate::address::Address;
+use crate::audit::AuditReason;
+use crate::metrics;
 use crate::rpc::RpcCommand;
 use crate::seed::Seed;
-use crate::ws::WsSubscription;
+use crate::unit::Raw;
+use crate::ws::{ws_unwatch, ws_watch, WsSubscription};
+
+/// Whether a pool account is free or reserved for a transaction, and since when
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolStatus {
+    Free,
+    Reserved(Instant),
+}
+
+impl PoolStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoolStatus::Free => "free",
+            PoolStatus::Reserved(_) => "reserved",
+        }
+    }
+}
+
+#[derive(Default)]
+struct PoolAccounts {
+    /// All pool accounts created so far by index
+    all: BTreeMap<u32, Arc<Mutex<Account>>>,
+    /// Reserved account indices with the time they were reserved
+    reserved: BTreeMap<u32, Instant>,
+}
+
+/// The pool accounts and their status, readable from other threads while the pool is in use, e.g. by the dashboard
+/// The accounts are locked by transactions for as long as they take, so readers should not block on them
+#[derive(Clone, Default)]
+pub struct PoolView {
+    accounts: Arc<Mutex<PoolAccounts>>,
+}
+
+impl PoolView {
+    /// Get all pool accounts created so far with their status, ordered by index
+    pub fn accounts(&self) -> Vec<(Arc<Mutex<Account>>, PoolStatus)> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts
+            .all
+            .iter()
+            .map(|(index, account)| (account.clone(), accounts.status(*index)))
+            .collect()
+    }
+
+    /// Get a pool account by index with its status
+    pub fn account(&self, index: u32) -> Option<(Arc<Mutex<Account>>, PoolStatus)> {
+        let accounts = self.accounts.lock().unwrap();
+        accounts.all.get(&index).map(|account| (account.clone(), accounts.status(index)))
+    }
+
+    /// Get the indices of all pool accounts created so far
+    pub fn indices(&self) -> Vec<u32> {
+        self.accounts.lock().unwrap().all.keys().cloned().collect()
+    }
+
+    fn contains(&self, index: u32) -> bool {
+        self.accounts.lock().unwrap().all.contains_key(&index)
+    }
+
+    fn insert(&self, index: u32, account: Arc<Mutex<Account>>) {
+        self.accounts.lock().unwrap().all.insert(index, account);
+    }
+
+    fn reserve(&self, index: u32) {
+        self.accounts.lock().unwrap().reserved.insert(index, Instant::now());
+    }
+
+    fn release(&self, index: u32) {
+        self.accounts.lock().unwrap().reserved.remove(&index);
+    }
+}
+
+impl PoolAccounts {
+    fn status(&self, index: u32) -> PoolStatus {
+        match self.reserved.get(&index) {
+            Some(since) => PoolStatus::Reserved(*since),
+            None => PoolStatus::Free,
+        }
+    }
+}
 
 pub struct Pool {
+    view: PoolView,
     free: VecDeque<Arc<Mutex<Account>>>,
     index: u32,
-    seed: Seed,
+    seed: Option<Seed>,
+    representative: Address,
     rpc_tx: Sender<RpcCommand>,
     ws_tx: Sender<WsSubscription>,
     wallet_address: Address,
@@ -20,14 +106,41 @@ pub struct Pool {
 impl Pool {
     pub fn new(
         seed: Seed,
+        start_index: u32,
+        representative: Address,
         rpc_tx: Sender<RpcCommand>,
         ws_tx: Sender<WsSubscription>,
         wallet_address: Address,
     ) -> Pool {
         Pool {
-            free: VecDeque::with_capacity(2 ^ 32 - 1),
-            index: 1,  // Index starts at once, so the wallet address can use index 0 if desired
-            seed,
+            view: PoolView::default(),
+            free: VecDeque::new(),
+            index: start_index, // Index 0 is reserved for the wallet address
+            seed: Some(seed),
+            representative,
+            rpc_tx,
+            ws_tx,
+            wallet_address,
+        }
+    }
+
+    /// Create a watch-only pool from existing accounts, it can't derive new accounts
+    pub fn watch(
+        accounts: Vec<Arc<Mutex<Account>>>,
+        rpc_tx: Sender<RpcCommand>,
+        ws_tx: Sender<WsSubscription>,
+        wallet_address: Address,
+    ) -> Pool {
+        let view = PoolView::default();
+        for account in &accounts {
+            view.insert(account.lock().unwrap().index(), account.clone());
+        }
+        Pool {
+            index: accounts.len() as u32 + 1,
+            view,
+            free: accounts.into_iter().collect(),
+            seed: None,
+            representative: "".to_owned(),
             rpc_tx,
             ws_tx,
             wallet_address,
@@ -36,61 +149,166 @@ impl Pool {
 
     /// Get a free account to use for a transaction
     /// If there is any balance remaining on it sweep it to the main wallet account
-    pub fn get_account(&mut self) -> Arc<Mutex<Account>> {
+    /// A watch-only pool can't sign, so its accounts are never reserved
+    pub fn get_account(&mut self) -> Result<Arc<Mutex<Account>>, String> {
+        let seed = self.seed.ok_or("A watch-only pool can't make payments")?;
         match self.free.pop_front() {
-            Some(account) => account,
+            Some(account) => {
+                // Free accounts are not watched, so watch it again and catch up on what it missed meanwhile
+                if let Err(e) = ws_watch(&self.ws_tx, account.clone()) {
+                    self.free.push_front(account);
+                    return Err(e);
+                }
+                self.view.reserve(account.lock().unwrap().index());
+                let mut acc = account.lock().unwrap();
+                acc.update_info();
+                if let Err(e) = acc.receive_all() {
+                    error!("POOL account {} could not receive its pending blocks: {}", acc.address(), e);
+                }
+                drop(acc);
+                Ok(account)
+            }
             None => {
+                // Accounts recovered for interrupted payments are already in use
+                while self.view.contains(self.index) {
+                    self.index += 1;
+                }
                 let account = Account::new(
-                    self.seed,
+                    seed,
                     self.index,
+                    self.representative.clone(),
                     self.rpc_tx.clone(),
                     self.ws_tx.clone(),
-                );
+                )?;
+                self.view.insert(self.index, account.clone());
+                self.view.reserve(self.index);
                 self.index += 1;
                 let mut acc = account.lock().unwrap();
                 let balance = acc.balance();
                 if balance > 0 {
-                    acc.send(balance, self.wallet_address.clone()).unwrap();
+                    acc.send(balance, self.wallet_address.clone(), AuditReason::Sweep).unwrap();
+                    Pool::count_sweep(balance);
                 }
                 drop(acc);
-                account
+                Ok(account)
             }
         }
     }
 
+    /// Open the pool account of an interrupted payment by index and reserve it, it is swept when it is returned
+    pub fn recover_account(&mut self, index: u32) -> Result<Arc<Mutex<Account>>, String> {
+        let seed = self.seed.ok_or("A watch-only pool can't recover accounts")?;
+        if self.view.contains(index) {
+            return Err(format!("Pool account {} is already in use", index));
+        }
+        let account = Account::new(
+            seed,
+            index,
+            self.representative.clone(),
+            self.rpc_tx.clone(),
+            self.ws_tx.clone(),
+        )?;
+        self.view.insert(index, account.clone());
+        self.view.reserve(index);
+        Ok(account)
+    }
+
+    /// Get all pool accounts created so far with their status, ordered by index
+    pub fn accounts(&self) -> Vec<(Arc<Mutex<Account>>, PoolStatus)> {
+        self.view.accounts()
+    }
+
+    /// Get a pool account by index with its status
+    pub fn account(&self, index: u32) -> Option<(Arc<Mutex<Account>>, PoolStatus)> {
+        self.view.account(index)
+    }
+
+    /// Get the indices of all pool accounts created so far
+    pub fn indices(&self) -> Vec<u32> {
+        self.view.indices()
+    }
+
+    /// Address of the account the next transaction would get, without reserving it
+    pub fn next_address(&self) -> Result<Address, String> {
+        if let Some(account) = self.free.front() {
+            return Ok(account.lock().unwrap().address());
+        }
+        let seed = self.seed.ok_or("A watch-only pool can't derive new accounts")?;
+        let public_key = Account::derive_public_key(Account::derive_private_key(seed, self.index));
+        Ok(Account::derive_address(public_key))
+    }
+
+    /// Get a handle to the pool accounts and their status for other threads
+    pub fn view(&self) -> PoolView {
+        self.view.clone()
+    }
+
     /// Return a used account to the free pool after a transaction
     /// If there is any balance remaining on it sweep it to the main wallet account
+    /// It is not watched until it is used again, except in watch-only pools which track all their accounts
     pub fn return_account(&mut self, account: Arc<Mutex<Account>>) {
         let mut acc = account.lock().unwrap();
         let balance = acc.balance();
-        if balance > 0 {
-            acc.send(balance, self.wallet_address.clone()).unwrap();
+        if balance > 0 && self.seed.is_some() {
+            // It is swept again when it is returned after its next use
+            match acc.send(balance, self.wallet_address.clone(), AuditReason::Sweep) {
+                Ok(()) => Pool::count_sweep(balance),
+                Err(e) => error!("POOL could not sweep {} from {}: {}", balance, acc.address(), e),
+            }
         }
+        let address = acc.address();
+        let index = acc.index();
         drop(acc);
+        if self.seed.is_some() {
+            if let Err(e) = ws_unwatch(&self.ws_tx, account.clone()) {
+                error!("POOL could not unwatch {}: {}", address, e);
+            }
+        }
+        self.view.release(index);
         self.free.push_back(account)
     }
+
+    fn count_sweep(amount: Raw) {
+        metrics::count("nano_pool_sweeps_total", &[], 1.0);
+        metrics::count("nano_pool_swept_nano_total", &[], metrics::nano(amount));
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::CONFIG;
-    use crate::logger::start_logger;
-    use crate::rpc::start_rpc;
-    use crate::ws::start_ws;
+    use crate::rpc::mock_node;
+    use crate::ws::mock_ws;
     use crate::common::generate_random_seed_address;
+    use serde_json::json;
+
+    /// A node on which every pool account is unopened and has nothing pending
+    fn empty_node() -> Sender<RpcCommand> {
+        mock_node(|cmd| match cmd["action"].as_str().unwrap() {
+            "account_balance" => Some(json!({ "balance": "0", "pending": "0", "receivable": "0" })),
+            "accounts_pending" => Some(json!({ "blocks": "" })),
+            _ => None,
+        })
+    }
 
     #[test]
     fn pool_no_node_required() {
-        start_logger();
-        let rpc_tx = start_rpc();
-        let ws_tx = start_ws();
+        let rpc_tx = empty_node();
+        let ws_tx = mock_ws();
         let (seed, address) = generate_random_seed_address();
-        let mut pool = Pool::new(seed, rpc_tx, ws_tx, address);
+        let mut pool = Pool::new(
+            seed,
+            CONFIG.pool.start_index,
+            CONFIG.wallet.representative.clone(),
+            rpc_tx,
+            ws_tx,
+            address,
+        );
 
-        let a1 = pool.get_account();
-        let a2 = pool.get_account();
-        let a3 = pool.get_account();
+        let a1 = pool.get_account().unwrap();
+        let a2 = pool.get_account().unwrap();
+        let a3 = pool.get_account().unwrap();
 
         assert_eq!(a1.lock().unwrap().index(), 1);
         assert_eq!(a2.lock().unwrap().index(), 2);
@@ -100,9 +318,9 @@ mod tests {
         pool.return_account(a1);
         assert_eq!(pool.free.len(), 2);
 
-        let a2 = pool.get_account();
-        let a1 = pool.get_account();
-        let a4 = pool.get_account();
+        let a2 = pool.get_account().unwrap();
+        let a1 = pool.get_account().unwrap();
+        let a4 = pool.get_account().unwrap();
         assert_eq!(pool.free.len(), 0);
 
         assert_eq!(a1.lock().unwrap().index(), 1);
@@ -115,4 +333,29 @@ mod tests {
         pool.return_account(a4);
         assert_eq!(pool.free.len(), 4);
     }
+
+    #[test]
+    fn pool_watch_only_no_node_required() {
+        let rpc_tx = mock_node(|cmd| match cmd["action"].as_str().unwrap() {
+            "account_balance" => Some(json!({ "balance": "0", "pending": "0", "receivable": "0" })),
+            "account_info" => Some(serde_json::from_str(include_str!("../tests/fixtures/rpc/account_info.json")).unwrap()),
+            _ => None,
+        });
+        let ws_tx = mock_ws();
+        let (_, address) = generate_random_seed_address();
+        let account = Account::watch(&address, 1, rpc_tx.clone(), ws_tx.clone()).unwrap();
+        assert!(account.lock().unwrap().balance() > 0);
+        let mut pool = Pool::watch(vec![account.clone()], rpc_tx, ws_tx, address);
+
+        // Its accounts can't sign, so they are neither reserved nor swept
+        assert_eq!(pool.get_account().err(), Some("A watch-only pool can't make payments".to_owned()));
+        assert_eq!(pool.free.len(), 1);
+        pool.free.clear();
+        assert!(pool.get_account().is_err());
+
+        pool.return_account(account.clone());
+        assert_eq!(pool.free.len(), 1);
+        assert_eq!(pool.account(1).unwrap().1, PoolStatus::Free);
+        assert!(account.lock().unwrap().unconfirmed().is_empty());
+    }
 }
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::Sender;
+
+use crate::common::hexstring_to_bytes;
+use crate::config::{CONFIG, DEFAULT_WALLET};
+use crate::metrics::watch_wallet;
+use crate::rpc::RpcCommand;
+use crate::wallet::Wallet;
+use crate::ws::WsSubscription;
+
+/// Wallets keyed by name, all sharing the same rpc and websocket clients
+pub struct WalletRegistry {
+    wallets: BTreeMap<String, Wallet>,
+}
+
+impl WalletRegistry {
+    pub fn new() -> Self {
+        Self {
+            wallets: BTreeMap::new(),
+        }
+    }
+
+    /// Create the default wallet and every wallet in the [wallets.<name>] config sections
+    /// Wallets without a seed are watch-only, fails when a wallet can't be opened or its accounts can't be watched
+    pub fn from_config(rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>) -> Result<Self, String> {
+        let mut registry = Self::new();
+        let mut wallets = vec![(
+            DEFAULT_WALLET.to_owned(),
+            &CONFIG.wallet.seed,
+            &CONFIG.wallet.representative,
+            CONFIG.pool.start_index,
+            &CONFIG.wallet.address,
+            &CONFIG.wallet.pool_addresses,
+        )];
+        for (name, wallet) in &CONFIG.wallets {
+            wallets.push((
+                name.clone(),
+                &wallet.seed,
+                &wallet.representative,
+                wallet.start_index,
+                &wallet.address,
+                &wallet.pool_addresses,
+            ));
+        }
+        for (name, seed, representative, start_index, address, pool_addresses) in wallets {
+            let wallet = if seed.is_empty() {
+                Wallet::watch(name.clone(), address, pool_addresses, rpc_tx.clone(), ws_tx.clone())
+                    .map_err(|e| format!("Wallet {} could not be started: {}", name, e))?
+            } else {
+                Wallet::new(
+                    name.clone(),
+                    hexstring_to_bytes(seed),
+                    representative.clone(),
+                    start_index,
+                    rpc_tx.clone(),
+                    ws_tx.clone(),
+                )
+                .map_err(|e| format!("Wallet {} could not be started: {}", name, e))?
+            };
+            watch_wallet(&wallet.name(), wallet.account(), wallet.pool().view());
+            registry.insert(wallet);
+        }
+        Ok(registry)
+    }
+
+    /// Add a wallet, replacing any wallet with the same name
+    pub fn insert(&mut self, wallet: Wallet) {
+        self.wallets.insert(wallet.name(), wallet);
+    }
+
+    /// Get a wallet by name
+    pub fn get(&mut self, name: &str) -> Result<&mut Wallet, String> {
+        self.wallets
+            .get_mut(name)
+            .ok_or(format!("Unknown wallet {}; type 'wallets' to see all wallets", name))
+    }
+
+    /// Get all wallet names
+    pub fn names(&self) -> Vec<String> {
+        self.wallets.keys().cloned().collect()
+    }
+}
+
+impl Default for WalletRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
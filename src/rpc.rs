@@ -1,100 +1,594 @@
-use curl::easy::Easy;
-use log::info;
+use curl::easy::{Easy, List};
+use log::{error, info, warn};
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::ser;
 use serde_aux::prelude::*;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::{Map, Value};
-use std::collections::HashMap;
-use std::io::Read;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::address::Address;
 use crate::block::Block;
 use crate::config::CONFIG;
-use crate::skip_fail;
+use crate::metrics;
 use crate::unit::Raw;
 
 // start remote procedure call interface
 pub fn start_rpc() -> Sender<RpcCommand> {
-    let url = format!("{}:{}", CONFIG.node_address, CONFIG.node_rpc_port);
+    let config = RpcClientConfig {
+        urls: CONFIG.rpc_endpoints(),
+        workers: CONFIG.node.rpc_workers,
+        timeout: Duration::from_secs(CONFIG.timeouts.rpc as u64),
+        work_timeout: Duration::from_secs(CONFIG.timeouts.rpc_work as u64),
+        retries: CONFIG.node.rpc_retries,
+        backoff: Duration::from_millis(CONFIG.timeouts.rpc_backoff as u64),
+        health_interval: Duration::from_secs(CONFIG.timeouts.rpc_health_interval as u64),
+        headers: CONFIG.rpc_headers(),
+        tls_verify: CONFIG.node.rpc_tls_verify,
+        disabled_actions: CONFIG.node.rpc_disabled_actions.clone(),
+    };
     let (rpc_tx, rpc_rx) = mpsc::channel::<RpcCommand>();
-    RpcClient::start(url, rpc_rx);
+    RpcClient::start(config, rpc_rx);
     rpc_tx
 }
 
+/// Requests with a higher priority are sent to the node first when all workers are busy
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RpcPriority {
+    /// Publishing and creating blocks for payments
+    High,
+    Normal,
+    /// Background scans over many accounts or blocks
+    Low,
+}
+
+impl RpcPriority {
+    pub fn of(action: &str) -> Self {
+        match action {
+            "process" | "block_create" | "work_generate" => RpcPriority::High,
+            "accounts_pending" | "receivable" | "account_history" | "accounts_balances" | "accounts_frontiers"
+            | "blocks_info" | "delegators" | "representatives_online" | "telemetry" => RpcPriority::Low,
+            _ => RpcPriority::Normal,
+        }
+    }
+}
+
 pub struct RpcCommand {
     cmd: Value,
-    tx_response: Sender<Value>,
+    tx_response: Sender<Result<Value, RpcError>>,
+    priority: RpcPriority,
+    timeout: Option<Duration>,
+    url: Option<String>,
 }
 
 impl RpcCommand {
-    pub fn new(cmd: Value, tx_response: Sender<Value>) -> Self {
-        Self { cmd, tx_response }
+    pub fn new(cmd: Value, tx_response: Sender<Result<Value, RpcError>>) -> Self {
+        let priority = RpcPriority::of(cmd["action"].as_str().unwrap_or_default());
+        Self {
+            cmd,
+            tx_response,
+            priority,
+            timeout: None,
+            url: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: RpcPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Override the configured timeout for this request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send this request to another server than the configured nodes, e.g. an external work server
+    /// The configured headers are not sent along, and the request is not retried
+    pub fn with_url(mut self, url: String) -> Self {
+        self.url = Some(url);
+        self
     }
 
     pub fn json(&self) -> Value {
         self.cmd.clone()
     }
 
-    pub fn respond(&self, json: Value) -> Result<(), String> {
-        Ok(self.tx_response.send(json).unwrap())
+    pub fn action(&self) -> &str {
+        self.cmd["action"].as_str().unwrap_or_default()
+    }
+
+    pub fn respond(&self, response: Result<Value, RpcError>) -> Result<(), String> {
+        self.tx_response
+            .send(response)
+            .map_err(|_| format!("RPC {} requester is gone", self.action()))
+    }
+}
+
+/// Commands waiting for a free worker, one queue per priority
+#[derive(Default)]
+struct RpcQueue {
+    queues: [VecDeque<RpcCommand>; 3],
+}
+
+impl RpcQueue {
+    fn push(&mut self, cmd: RpcCommand) {
+        self.queues[cmd.priority as usize].push_back(cmd);
+    }
+
+    fn pop(&mut self) -> Option<RpcCommand> {
+        self.queues.iter_mut().find_map(|q| q.pop_front())
+    }
+}
+
+/// Node endpoints in order of preference, with their last known health
+struct RpcEndpoints {
+    urls: Vec<String>,
+    healthy: Vec<bool>,
+    current: usize,
+}
+
+impl RpcEndpoints {
+    fn new(urls: Vec<String>) -> Self {
+        let healthy = vec![true; urls.len()];
+        Self {
+            urls,
+            healthy,
+            current: 0,
+        }
+    }
+
+    fn current(&self) -> (usize, String) {
+        (self.current, self.urls[self.current].clone())
+    }
+
+    /// Switch to the next healthy endpoint after a failed request
+    fn fail(&mut self, index: usize) {
+        self.healthy[index] = false;
+        if index != self.current {
+            return;
+        }
+        let n = self.urls.len();
+        // Without any healthy endpoint left, keep rotating through all of them
+        self.current = (1..=n)
+            .map(|i| (index + i) % n)
+            .find(|i| self.healthy[*i])
+            .unwrap_or((index + 1) % n);
+    }
+
+    /// Record a health check result, going back to a more preferred endpoint once it recovers
+    fn set_health(&mut self, index: usize, healthy: bool) {
+        self.healthy[index] = healthy;
+        if healthy && (index < self.current || !self.healthy[self.current]) {
+            self.current = index;
+        }
     }
 }
 
+/// Actions the wallet has a fallback for when the node does not offer them, only these are detected as disabled
+const FALLBACK_ACTIONS: [&str; 2] = ["block_create", "work_generate"];
+
+/// Time an action detected as disabled is not sent to the nodes, after that they are asked again
+const DISABLED_EXPIRY: Duration = Duration::from_secs(600);
+
+/// Hosted rpc providers answer actions they don't offer with an error like these
+/// Any other action keeps failing with the node's error, e.g. a "forbidden" for one account must not stop all sends
+fn is_disabled_error(action: &str, error: &str) -> bool {
+    let error = error.to_lowercase();
+    FALLBACK_ACTIONS.contains(&action)
+        && ["disabled", "not allowed", "unknown command", "not supported", "forbidden", "not permitted"]
+            .iter()
+            .any(|e| error.contains(e))
+}
+
+/// Actions that are not sent to the nodes, the configured ones for good and detected ones until their mark expires
+struct DisabledActions {
+    configured: HashSet<String>,
+    /// detected actions with the time their mark expires
+    detected: HashMap<String, Instant>,
+}
+
+impl DisabledActions {
+    fn new(configured: &[String]) -> Self {
+        Self {
+            configured: configured.iter().cloned().collect(),
+            detected: HashMap::new(),
+        }
+    }
+
+    fn contains(&mut self, action: &str, now: Instant) -> bool {
+        if self.configured.contains(action) {
+            return true;
+        }
+        match self.detected.get(action) {
+            Some(expiry) if *expiry > now => true,
+            Some(_) => {
+                info!("RPC trying action {} on the node again", action);
+                self.detected.remove(action);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn detect(&mut self, action: &str, now: Instant) {
+        self.detected.insert(action.to_owned(), now + DISABLED_EXPIRY);
+    }
+}
+
+/// Actions that change node or wallet state and can not be sent twice
+fn is_idempotent(action: &str) -> bool {
+    !matches!(
+        action,
+        "process" | "send" | "receive" | "account_create" | "accounts_create" | "wallet_create" | "wallet_add"
+    )
+}
+
+pub struct RpcClientConfig {
+    /// node endpoints as host:port, the first one is preferred
+    pub urls: Vec<String>,
+    pub workers: usize,
+    pub timeout: Duration,
+    pub work_timeout: Duration,
+    /// attempts after the first one for requests that failed to reach a node
+    pub retries: u32,
+    /// wait before the first retry, doubled for every next one
+    pub backoff: Duration,
+    pub health_interval: Duration,
+    /// http headers sent with every request, formatted as "Name: value"
+    pub headers: Vec<String>,
+    pub tls_verify: bool,
+    /// actions known to be unavailable on the nodes
+    pub disabled_actions: Vec<String>,
+}
+
 pub struct RpcClient {
-    url: String,
-    rx: Receiver<RpcCommand>,
+    config: Arc<RpcClientConfig>,
+    endpoints: Arc<Mutex<RpcEndpoints>>,
+    queue: Arc<(Mutex<RpcQueue>, Condvar)>,
+    disabled: Arc<Mutex<DisabledActions>>,
 }
 
 impl RpcClient {
-    pub fn start(url: String, rx: Receiver<RpcCommand>) {
-        let rpc = Self { url, rx };
+    /// Start a dispatcher thread that queues incoming commands by priority,
+    /// worker threads that send them to the nodes concurrently, and a health check thread
+    pub fn start(config: RpcClientConfig, rx: Receiver<RpcCommand>) {
+        let endpoints = Arc::new(Mutex::new(RpcEndpoints::new(config.urls.clone())));
+        let disabled = Arc::new(Mutex::new(DisabledActions::new(&config.disabled_actions)));
+        let config = Arc::new(config);
+        let queue: Arc<(Mutex<RpcQueue>, Condvar)> = Arc::new((Mutex::new(RpcQueue::default()), Condvar::new()));
+
+        for i in 0..config.workers {
+            let worker = Self {
+                config: config.clone(),
+                endpoints: endpoints.clone(),
+                queue: queue.clone(),
+                disabled: disabled.clone(),
+            };
+            thread::Builder::new()
+                .name(format!("rpc-{}", i))
+                .spawn(move || {
+                    worker.run();
+                })
+                .unwrap();
+        }
+
+        let health = Self {
+            config,
+            endpoints,
+            queue: queue.clone(),
+            disabled,
+        };
+        thread::Builder::new()
+            .name("rpc-health".to_owned())
+            .spawn(move || {
+                health.run_health_checks();
+            })
+            .unwrap();
 
         thread::Builder::new()
             .name("rpc".to_owned())
             .spawn(move || {
-                rpc.run();
+                for cmd in rx {
+                    let (lock, cvar) = &*queue;
+                    lock.lock().unwrap().push(cmd);
+                    cvar.notify_one();
+                }
             })
             .unwrap();
     }
 
-    pub fn run(&self) {
+    fn run(&self) {
+        // Each worker keeps its own handle, so the connection to the node is kept alive between requests
+        let mut easy = Easy::new();
         loop {
-            let cmd = skip_fail!(self.rx.recv());
-
-            let json = cmd.json();
-
-            info!("RPC send {}", json);
-            let data = json.to_string();
-            let mut data = data.as_bytes();
-            let mut easy = Easy::new();
-            easy.url(&self.url.clone()).unwrap();
-            easy.post(true).unwrap();
-            easy.post_field_size(data.len() as u64).unwrap();
-            let mut dst = Vec::new();
-
-            let mut transfer = easy.transfer();
-            transfer
-                .read_function(|buf| Ok(data.read(buf).unwrap_or(0)))
-                .unwrap();
-            transfer
-                .write_function(|data| {
-                    dst.extend_from_slice(data);
-                    Ok(data.len())
-                })
-                .unwrap();
+            let cmd = {
+                let (lock, cvar) = &*self.queue;
+                let mut queue = lock.lock().unwrap();
+                loop {
+                    match queue.pop() {
+                        Some(cmd) => break cmd,
+                        None => queue = cvar.wait(queue).unwrap(),
+                    }
+                }
+            };
+            let started = Instant::now();
+            let response = self.send(&mut easy, &cmd);
+            let action = [("action", cmd.action())];
+            metrics::count("nano_pool_rpc_requests_total", &action, 1.0);
+            metrics::observe("nano_pool_rpc_request_seconds", &action, started.elapsed());
+            if !matches!(&response, Ok(r) if r.get("error").is_none()) {
+                metrics::count("nano_pool_rpc_errors_total", &action, 1.0);
+            }
+            if let Err(e) = cmd.respond(response) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// Send a command, and remember actions the nodes turn out not to offer
+    fn send(&self, easy: &mut Easy, cmd: &RpcCommand) -> Result<Value, RpcError> {
+        let action = cmd.action();
+        let timeout = match cmd.timeout {
+            Some(t) => t,
+            None if action == "work_generate" => self.config.work_timeout,
+            None => self.config.timeout,
+        };
+        if let Some(url) = &cmd.url {
+            return post(easy, url, &cmd.json(), timeout, &[], self.config.tls_verify);
+        }
+        if self.disabled.lock().unwrap().contains(action, Instant::now()) {
+            return Err(RpcError::Disabled(action.to_owned()));
+        }
+
+        let response = self.send_with_failover(easy, cmd, timeout)?;
+        match response.get("error").and_then(|e| e.as_str()) {
+            Some(e) if is_disabled_error(action, e) => {
+                warn!("RPC action {} is not available on the node for {:?}: {}", action, DISABLED_EXPIRY, e);
+                self.disabled.lock().unwrap().detect(action, Instant::now());
+                Err(RpcError::Disabled(action.to_owned()))
+            }
+            _ => Ok(response),
+        }
+    }
+
+    /// Send a command, retrying on the next healthy node when a node can not be reached
+    fn send_with_failover(&self, easy: &mut Easy, cmd: &RpcCommand, timeout: Duration) -> Result<Value, RpcError> {
+        let action = cmd.action();
+        // Set once a process request may have reached a node, so it is only resent if the block is unknown
+        let mut maybe_published: Option<String> = None;
+        let mut result = Err(RpcError::Disconnected);
+
+        for attempt in 0..=self.config.retries {
+            if attempt > 0 {
+                thread::sleep(self.config.backoff * 2u32.pow(attempt - 1));
+            }
+            let (index, url) = self.endpoints.lock().unwrap().current();
+
+            if let Some(hash) = &maybe_published {
+                let check = json!({ "action": "block_info", "hash": hash });
+                match post(easy, &url, &check, self.config.timeout, &self.config.headers, self.config.tls_verify) {
+                    Ok(info) if info.get("error").is_none() => {
+                        info!("RPC process {} already reached the node, not resending", hash);
+                        return Ok(json!({ "hash": hash }));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.fail(index, &url, &e);
+                        result = Err(e);
+                        continue;
+                    }
+                }
+            }
 
-            transfer.perform().expect("curl transfer failed");
-            drop(transfer);
-            let dst = String::from_utf8(dst).unwrap();
-            let dst: Value = serde_json::from_str(&dst).unwrap();
-            info!("RPC recv {}", dst);
-            cmd.respond(dst).unwrap();
+            result = post(easy, &url, &cmd.json(), timeout, &self.config.headers, self.config.tls_verify);
+            match &result {
+                Err(e @ RpcError::Transport(_)) | Err(e @ RpcError::Timeout) => {
+                    self.fail(index, &url, e);
+                    if action == "process" {
+                        match serde_json::from_value::<Block>(cmd.json()["block"].clone()).map(|b| b.hash()) {
+                            Ok(Ok(hash)) => maybe_published = Some(hash),
+                            _ => return result,
+                        }
+                    } else if !is_idempotent(action) {
+                        return result;
+                    }
+                }
+                _ => return result,
+            }
+        }
+        result
+    }
+
+    fn fail(&self, index: usize, url: &str, e: &RpcError) {
+        warn!("RPC node {} failed: {}", url, e);
+        self.endpoints.lock().unwrap().fail(index);
+    }
+
+    /// Periodically check all nodes with block_count, so failed nodes are used again once they recover
+    fn run_health_checks(&self) {
+        let mut easy = Easy::new();
+        let check = json!({ "action": "block_count" });
+        let version = json!({ "action": "version" });
+        for url in &self.config.urls {
+            match post(&mut easy, url, &version, self.config.timeout, &self.config.headers, self.config.tls_verify) {
+                Ok(v) => info!("RPC node {} runs {}", url, v["node_vendor"]),
+                Err(e) => warn!("RPC node {} version check failed: {}", url, e),
+            }
+        }
+        loop {
+            thread::sleep(self.config.health_interval);
+            for (i, url) in self.config.urls.iter().enumerate() {
+                let healthy = match post(&mut easy, url, &check, self.config.timeout, &self.config.headers, self.config.tls_verify) {
+                    Ok(v) => v.get("count").is_some(),
+                    Err(_) => false,
+                };
+                let mut endpoints = self.endpoints.lock().unwrap();
+                if endpoints.healthy[i] != healthy {
+                    if healthy {
+                        info!("RPC node {} is healthy again", url);
+                    } else {
+                        warn!("RPC node {} failed its health check", url);
+                    }
+                }
+                endpoints.set_health(i, healthy);
+            }
         }
     }
 }
 
+/// Post a json request to a node
+fn post(
+    easy: &mut Easy,
+    url: &str,
+    json: &Value,
+    timeout: Duration,
+    headers: &[String],
+    tls_verify: bool,
+) -> Result<Value, RpcError> {
+    info!("RPC send {} {}", url, json);
+    let transport = |e: curl::Error| {
+        if e.is_operation_timedout() {
+            RpcError::Timeout
+        } else {
+            RpcError::Transport(e.to_string())
+        }
+    };
+    easy.url(url).map_err(transport)?;
+    easy.post(true).map_err(transport)?;
+    easy.post_fields_copy(json.to_string().as_bytes()).map_err(transport)?;
+    easy.timeout(timeout).map_err(transport)?;
+    easy.ssl_verify_peer(tls_verify).map_err(transport)?;
+    easy.ssl_verify_host(tls_verify).map_err(transport)?;
+    let mut list = List::new();
+    list.append("Content-Type: application/json").map_err(transport)?;
+    for header in headers {
+        list.append(header).map_err(transport)?;
+    }
+    easy.http_headers(list).map_err(transport)?;
+    let mut dst = Vec::new();
+    {
+        let mut transfer = easy.transfer();
+        transfer
+            .write_function(|data| {
+                dst.extend_from_slice(data);
+                Ok(data.len())
+            })
+            .map_err(transport)?;
+        transfer.perform().map_err(transport)?;
+    }
+    let dst: Value = match serde_json::from_slice(&dst) {
+        Ok(dst) => dst,
+        // Providers answer authentication and rate limit problems with a plain http error
+        Err(_) if easy.response_code().unwrap_or(0) >= 400 => {
+            return Err(RpcError::Transport(format!("HTTP {}", easy.response_code().unwrap_or(0))));
+        }
+        Err(e) => return Err(RpcError::Malformed(e.to_string())),
+    };
+    info!("RPC recv {}", dst);
+    Ok(dst)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RpcError {
+    /// The rpc client is not running or dropped the request
+    Disconnected,
+    /// The node could not be reached
+    Transport(String),
+    /// The node did not answer within the request timeout
+    Timeout,
+    /// The node answered with an error, e.g. "Account not found" or "Fork"
+    Node(String),
+    /// The node does not offer this action, e.g. block_create on hosted rpc providers
+    Disabled(String),
+    /// The node answer did not have the expected format
+    Malformed(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcError::Disconnected => write!(f, "RPC client disconnected"),
+            RpcError::Transport(e) => write!(f, "RPC transport error: {}", e),
+            RpcError::Timeout => write!(f, "RPC request timed out"),
+            RpcError::Node(e) => write!(f, "RPC node error: {}", e),
+            RpcError::Disabled(action) => write!(f, "RPC action {} is not available on the node", action),
+            RpcError::Malformed(e) => write!(f, "RPC malformed response: {}", e),
+        }
+    }
+}
+
+impl From<RpcError> for String {
+    fn from(e: RpcError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Send a message to the rpc client and wait for the raw response
+/// Responses containing an error field are turned into RpcError::Node
+fn rpc_request<M: ser::Serialize>(rpc_tx: Sender<RpcCommand>, message: M) -> Result<Value, RpcError> {
+    rpc_request_to(rpc_tx, message, None)
+}
+
+/// Same as rpc_request, but sends the message to the given url instead of the configured nodes
+fn rpc_request_to<M: ser::Serialize>(
+    rpc_tx: Sender<RpcCommand>,
+    message: M,
+    url: Option<String>,
+) -> Result<Value, RpcError> {
+    let message = serde_json::to_value(message).map_err(|e| RpcError::Malformed(e.to_string()))?;
+    info!("RPC cmd {}", message["action"].as_str().unwrap_or_default());
+
+    let (tx, rx) = mpsc::channel::<Result<Value, RpcError>>();
+    let mut cmd = RpcCommand::new(message, tx);
+    if let Some(url) = url {
+        cmd = cmd.with_url(url);
+    }
+    rpc_tx.send(cmd).map_err(|_| RpcError::Disconnected)?;
+
+    let value = rx.recv().map_err(|_| RpcError::Disconnected)??;
+    if let Some(error) = value.get("error") {
+        return Err(RpcError::Node(error.as_str().unwrap_or_default().to_owned()));
+    }
+    Ok(value)
+}
+
+/// Send a message to the rpc client and deserialize the response
+fn rpc_call<M: ser::Serialize, R: DeserializeOwned>(rpc_tx: Sender<RpcCommand>, message: M) -> Result<R, RpcError> {
+    let value = rpc_request(rpc_tx, message)?;
+    from_value(value)
+}
+
+fn from_value<R: DeserializeOwned>(value: Value) -> Result<R, RpcError> {
+    serde_json::from_value(value).map_err(|e| RpcError::Malformed(e.to_string()))
+}
+
+/// The node returns an empty string instead of an empty list or map
+fn is_empty_result(value: &Value) -> bool {
+    value.is_null() || *value == ""
+}
+
+/// The node encodes booleans as "true"/"false" or "1"/"0"
+pub(crate) fn deserialize_bool_from_string<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match <Value as de::Deserialize>::deserialize(deserializer)? {
+        Value::Bool(b) => Ok(b),
+        Value::String(s) if s == "true" || s == "1" => Ok(true),
+        Value::String(s) if s == "false" || s == "0" => Ok(false),
+        v => Err(de::Error::custom(format!("expected boolean, found {}", v))),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SUBTYPE {
     SEND,
     RECEIVE,
@@ -102,12 +596,18 @@ pub enum SUBTYPE {
 }
 
 #[derive(Serialize)]
-struct JsonAccountBalanceMessage {
+struct JsonAccountMessage {
     action: String,
     account: Address,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize)]
+struct JsonAccountsMessage {
+    action: String,
+    accounts: Vec<Address>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
 pub struct JsonAccountBalanceResponse {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub balance: Raw,
@@ -118,21 +618,88 @@ pub struct JsonAccountBalanceResponse {
 pub fn rpc_account_balance(
     rpc_tx: Sender<RpcCommand>,
     address: &Address,
-) -> Result<JsonAccountBalanceResponse, String> {
-    let action = "account_balance".to_owned();
-    info!("RPC cmd {}", &action);
+) -> Result<JsonAccountBalanceResponse, RpcError> {
+    let message = JsonAccountMessage {
+        action: "account_balance".to_owned(),
+        account: address.to_owned(),
+    };
+    rpc_call(rpc_tx, message)
+}
+
+#[derive(Deserialize)]
+struct JsonAccountsBalancesResponse {
+    balances: HashMap<Address, JsonAccountBalanceResponse>,
+}
+
+pub fn rpc_accounts_balances(
+    rpc_tx: Sender<RpcCommand>,
+    addresses: Vec<Address>,
+) -> Result<HashMap<Address, JsonAccountBalanceResponse>, RpcError> {
+    let message = JsonAccountsMessage {
+        action: "accounts_balances".to_owned(),
+        accounts: addresses,
+    };
+    let response: JsonAccountsBalancesResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.balances)
+}
+
+pub fn rpc_accounts_frontiers(
+    rpc_tx: Sender<RpcCommand>,
+    addresses: Vec<Address>,
+) -> Result<HashMap<Address, String>, RpcError> {
+    let message = JsonAccountsMessage {
+        action: "accounts_frontiers".to_owned(),
+        accounts: addresses,
+    };
+    let value = rpc_request(rpc_tx, message)?;
+    if is_empty_result(&value["frontiers"]) {
+        return Ok(HashMap::new());
+    }
+    from_value(value["frontiers"].clone())
+}
+
+#[derive(Deserialize)]
+struct JsonAccountWeightResponse {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    weight: Raw,
+}
 
-    let message = JsonAccountBalanceMessage {
-        action: action,
+pub fn rpc_account_weight(rpc_tx: Sender<RpcCommand>, address: &Address) -> Result<Raw, RpcError> {
+    let message = JsonAccountMessage {
+        action: "account_weight".to_owned(),
         account: address.to_owned(),
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
+    let response: JsonAccountWeightResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.weight)
+}
+
+#[derive(Deserialize)]
+struct JsonAccountRepresentativeResponse {
+    representative: Address,
+}
 
-    let response: JsonAccountBalanceResponse = serde_json::from_value(rx.recv().unwrap()).unwrap();
-    Ok(response)
+pub fn rpc_account_representative(rpc_tx: Sender<RpcCommand>, address: &Address) -> Result<Address, RpcError> {
+    let message = JsonAccountMessage {
+        action: "account_representative".to_owned(),
+        account: address.to_owned(),
+    };
+    let response: JsonAccountRepresentativeResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.representative)
+}
+
+#[derive(Deserialize)]
+struct JsonAccountBlockCountResponse {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    block_count: u64,
+}
+
+pub fn rpc_account_block_count(rpc_tx: Sender<RpcCommand>, address: &Address) -> Result<u64, RpcError> {
+    let message = JsonAccountMessage {
+        action: "account_block_count".to_owned(),
+        account: address.to_owned(),
+    };
+    let response: JsonAccountBlockCountResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.block_count)
 }
 
 #[derive(Serialize)]
@@ -140,16 +707,24 @@ struct JsonAccountsPendingMessage {
     action: String,
     accounts: Vec<Address>,
     count: usize,
+    #[serde(flatten)]
+    options: PendingOptions,
+}
+
+/// Optional accounts_pending parameters, the node's defaults apply to those left out
+#[derive(Serialize, Default)]
+pub struct PendingOptions {
+    /// a 0 threshold is left out
     #[serde(skip_serializing_if = "Option::is_none")]
-    threshold: Option<Raw>,
+    pub threshold: Option<Raw>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    source: Option<bool>,
+    pub source: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    include_active: Option<bool>,
+    pub include_active: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    sorting: Option<bool>,
+    pub sorting: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    include_only_confirmed: Option<bool>,
+    pub include_only_confirmed: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -164,183 +739,222 @@ struct JsonBlock {
     source: Option<Address>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct PendingBlock {
     pub hash: String,
     pub amount: Option<Raw>,
     pub source: Option<Address>,
 }
 
+/// Parse the pending blocks of a single account
+/// Depending on the threshold and source options the node returns a list of hashes,
+/// a map of hash to amount, or a map of hash to amount and source
+fn parse_pending_blocks(value: &Value) -> Result<Vec<PendingBlock>, RpcError> {
+    let mut blocks: Vec<PendingBlock> = vec![];
+    match value {
+        Value::Array(_) => {
+            let data: Vec<String> = from_value(value.clone())?;
+            for hash in data {
+                blocks.push(PendingBlock {
+                    hash,
+                    amount: None,
+                    source: None,
+                });
+            }
+        }
+        Value::Object(map) if map.values().all(|v| v.is_string()) => {
+            let data: HashMap<String, String> = from_value(value.clone())?;
+            for (hash, amount) in data {
+                let amount = amount
+                    .parse::<Raw>()
+                    .map_err(|_| RpcError::Malformed(format!("invalid amount {}", amount)))?;
+                blocks.push(PendingBlock {
+                    hash,
+                    amount: Some(amount),
+                    source: None,
+                });
+            }
+        }
+        Value::Object(_) => {
+            let data: HashMap<String, JsonBlock> = from_value(value.clone())?;
+            for (hash, block) in data {
+                blocks.push(PendingBlock {
+                    hash,
+                    amount: Some(block.amount),
+                    source: block.source,
+                });
+            }
+        }
+        // no pending blocks
+        v if is_empty_result(v) => {}
+        v => return Err(RpcError::Malformed(format!("unexpected pending blocks {}", v))),
+    }
+    Ok(blocks)
+}
+
 pub fn rpc_accounts_pending(
     rpc_tx: Sender<RpcCommand>,
     addresses: Vec<Address>,
     count: usize,
-    mut threshold: Option<Raw>,
-    source: Option<bool>,
-    include_active: Option<bool>,
-    sorting: Option<bool>,
-    include_only_confirmed: Option<bool>,
-) -> Result<HashMap<Address, Vec<PendingBlock>>, String> {
+    mut options: PendingOptions,
+) -> Result<HashMap<Address, Vec<PendingBlock>>, RpcError> {
     // treat 0 threshold as None threshold
-    match threshold {
-        Some(value) if value == 0 => {
-            threshold = None;
-        }
-        _ => {}
+    if options.threshold == Some(0) {
+        options.threshold = None;
     }
-    let action = "accounts_pending".to_owned();
-    info!("RPC cmd {}", &action);
-
     let message = JsonAccountsPendingMessage {
-        action,
+        action: "accounts_pending".to_owned(),
         accounts: addresses,
         count,
-        threshold,
-        source,
-        include_active,
-        sorting,
-        include_only_confirmed,
+        options,
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
 
     // If blocks is an empty string, return an empty result
-    let value = rx.recv().unwrap();
-    if value["blocks"] == "" {
-        return Ok(HashMap::new())
+    let value = rpc_request(rpc_tx, message)?;
+    if is_empty_result(&value["blocks"]) {
+        return Ok(HashMap::new());
     }
 
-    let response: JsonAccountsPendingResponse = serde_json::from_value(value).unwrap();
+    let response: JsonAccountsPendingResponse = from_value(value)?;
     let mut output: HashMap<Address, Vec<PendingBlock>> = HashMap::new();
-    for account in response.blocks.keys() {
-        let mut blocks: Vec<PendingBlock> = vec![];
-        match source {
-            Some(b) if b => {
-                // if source is included then we get the amount and source for each block hash
-                let data: Result<HashMap<String, JsonBlock>, serde_json::Error> =
-                    serde_json::from_value(response.blocks[account].clone());
-                match data {
-                    Ok(d) => {
-                        for (hash, block) in d {
-                            blocks.push(PendingBlock {
-                                hash,
-                                amount: Some(block.amount),
-                                source: block.source,
-                            });
-                        }
-                    }
-                    Err(_) => {
-                        // no pending blocks for this account
-                        continue;
-                    }
-                }
-            }
-            _ => {
-                match threshold {
-                    Some(_) => {
-                        // if threshold is included then then we get the amount for each block hash
-                        let data: HashMap<String, String> =
-                            serde_json::from_value(response.blocks[account].clone()).unwrap();
-                        for (hash, amount) in data {
-                            let amount = amount.parse::<Raw>().unwrap();
-                            blocks.push(PendingBlock {
-                                hash,
-                                amount: Some(amount),
-                                source: None,
-                            });
-                        }
-                    }
-                    _ => {
-                        // if neither threshold nor source is included we just get an array of blocks
-                        let data: Vec<String> =
-                            serde_json::from_value(response.blocks[account].clone()).unwrap();
-                        for hash in data {
-                            blocks.push(PendingBlock {
-                                hash,
-                                amount: None,
-                                source: None,
-                            });
-                        }
-                    }
-                }
-            }
-        }
-        output.insert(account.to_owned(), blocks);
+    for (account, blocks) in response.blocks {
+        output.insert(account, parse_pending_blocks(&blocks)?);
     }
     Ok(output)
 }
 
+#[derive(Serialize)]
+struct JsonReceivableMessage {
+    action: String,
+    account: Address,
+    count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    threshold: Option<Raw>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_only_confirmed: Option<bool>,
+}
+
+pub fn rpc_receivable(
+    rpc_tx: Sender<RpcCommand>,
+    address: &Address,
+    count: usize,
+    threshold: Option<Raw>,
+    source: Option<bool>,
+    include_only_confirmed: Option<bool>,
+) -> Result<Vec<PendingBlock>, RpcError> {
+    let message = JsonReceivableMessage {
+        action: "receivable".to_owned(),
+        account: address.to_owned(),
+        count,
+        threshold: threshold.filter(|t| *t != 0),
+        source,
+        include_only_confirmed,
+    };
+    let value = rpc_request(rpc_tx, message)?;
+    parse_pending_blocks(&value["blocks"])
+}
+
 #[derive(Serialize)]
 struct JsonWorkGenerateMessage<'a> {
     action: String,
     hash: String,
+    #[serde(flatten)]
+    options: WorkOptions<'a>,
+}
+
+/// Optional work_generate parameters, the node's defaults apply to those left out
+#[derive(Serialize, Default)]
+pub struct WorkOptions<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
-    use_peers: Option<bool>,
+    pub use_peers: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    difficulty: Option<String>,
+    pub difficulty: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    multiplier: Option<String>,
+    pub multiplier: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    account: Option<Address>,
+    pub account: Option<Address>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    version: Option<String>,
+    pub version: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    block: Option<&'a Block>,
+    pub block: Option<&'a Block>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    json_block: Option<bool>,
+    pub json_block: Option<bool>,
 }
 
 #[derive(Deserialize)]
 struct JsonWorkGenerateResponse {
     work: String,
-    difficulty: String,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    multiplier: f64,
-    hash: String,
 }
 
-pub fn rpc_work_generate(
+pub fn rpc_work_generate(rpc_tx: Sender<RpcCommand>, hash: String, options: WorkOptions) -> Result<String, RpcError> {
+    let message = JsonWorkGenerateMessage {
+        action: "work_generate".to_owned(),
+        hash,
+        options,
+    };
+    let response: JsonWorkGenerateResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.work)
+}
+
+/// Generate work with an external work server that speaks the work_generate rpc
+pub fn rpc_work_generate_external(
     rpc_tx: Sender<RpcCommand>,
+    url: &str,
     hash: String,
-    use_peers: Option<bool>,
     difficulty: Option<String>,
-    multiplier: Option<String>,
-    account: Option<Address>,
-    version: Option<String>,
-    block: Option<&Block>,
-    json_block: Option<bool>,
-) -> Result<String, String> {
-    let action = "work_generate".to_owned();
-    info!("RPC cmd {}", &action);
-
+) -> Result<String, RpcError> {
     let message = JsonWorkGenerateMessage {
-        action,
+        action: "work_generate".to_owned(),
         hash,
-        use_peers,
-        difficulty,
-        multiplier,
-        account,
-        version,
-        block,
-        json_block,
+        options: WorkOptions {
+            difficulty,
+            ..Default::default()
+        },
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
-
-    let response: JsonWorkGenerateResponse = serde_json::from_value(rx.recv().unwrap()).unwrap();
+    let response: JsonWorkGenerateResponse = from_value(rpc_request_to(rpc_tx, message, Some(url.to_owned()))?)?;
     Ok(response.work)
 }
 
+#[derive(Serialize)]
+struct JsonWorkValidateMessage {
+    action: String,
+    work: String,
+    hash: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonWorkValidateResponse {
+    #[serde(deserialize_with = "deserialize_bool_from_string")]
+    pub valid_all: bool,
+    #[serde(deserialize_with = "deserialize_bool_from_string")]
+    pub valid_receive: bool,
+    pub difficulty: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub multiplier: f64,
+}
+
+pub fn rpc_work_validate(
+    rpc_tx: Sender<RpcCommand>,
+    work: String,
+    hash: String,
+) -> Result<JsonWorkValidateResponse, RpcError> {
+    let message = JsonWorkValidateMessage {
+        action: "work_validate".to_owned(),
+        work,
+        hash,
+    };
+    rpc_call(rpc_tx, message)
+}
+
 #[derive(Serialize)]
 struct JsonAccountInfoMessage {
     action: String,
     account: Address,
     #[serde(skip_serializing_if = "Option::is_none")]
     include_confirmed: Option<bool>,
+    representative: bool,
 }
 
 #[derive(Deserialize, PartialEq, Debug)]
@@ -363,6 +977,7 @@ pub struct JsonAccountInfoResponse {
     // #[serde(deserialize_with = "deserialize_option_number_from_string")] <-- deserialize_option_number_from_string is broken
     pub confirmed_height: Option<String>,
     pub confirmation_height_frontier: Option<String>,
+    pub representative: Option<Address>,
 }
 
 // only includes confirmed, maybe support unconfirmed account info at some point?
@@ -371,25 +986,14 @@ pub fn rpc_account_info(
     rpc_tx: Sender<RpcCommand>,
     address: &Address,
     include_confirmed: Option<bool>,
-) -> Result<JsonAccountInfoResponse, String> {
-    let action = "account_info".to_owned();
-    info!("RPC cmd {}", &action);
-
+) -> Result<JsonAccountInfoResponse, RpcError> {
     let message = JsonAccountInfoMessage {
-        action,
+        action: "account_info".to_owned(),
         account: address.to_owned(),
         include_confirmed,
+        representative: true,
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
-
-    let response = serde_json::from_value(rx.recv().unwrap());
-    match response {
-        Ok(r) => Ok(r),
-        Err(_) => Err("Account does not exist or malformed response".to_owned()),
-    }
+    rpc_call(rpc_tx, message)
 }
 
 #[derive(Serialize)]
@@ -408,10 +1012,11 @@ struct JsonBlockCreateMessage {
 #[derive(Deserialize)]
 pub struct JsonBlockCreateResponse {
     hash: String,
-    difficulty: String,
     block: Block,
 }
 
+/// Let the node build and sign a block, this sends the private key to the node
+/// The wallet signs its blocks locally instead, so keys never reach a remote or hosted node
 pub fn rpc_block_create(
     rpc_tx: Sender<RpcCommand>,
     previous: String,
@@ -420,12 +1025,31 @@ pub fn rpc_block_create(
     balance: Raw,
     link: String,
     key: String,
-) -> Result<Block, String> {
-    let action = "block_create".to_owned();
-    info!("RPC cmd {}", &action);
+) -> Result<Block, RpcError> {
+    let (_, block) = rpc_block_create_hashed(
+        rpc_tx,
+        previous,
+        account,
+        representative,
+        balance,
+        link,
+        key,
+    )?;
+    Ok(block)
+}
 
+/// Same as rpc_block_create, but also returns the hash of the created block
+pub fn rpc_block_create_hashed(
+    rpc_tx: Sender<RpcCommand>,
+    previous: String,
+    account: Address,
+    representative: Address,
+    balance: Raw,
+    link: String,
+    key: String,
+) -> Result<(String, Block), RpcError> {
     let message = JsonBlockCreateMessage {
-        action,
+        action: "block_create".to_owned(),
         json_block: true,
         r#type: "state".to_owned(),
         previous,
@@ -435,13 +1059,8 @@ pub fn rpc_block_create(
         link,
         key,
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
-
-    let response: JsonBlockCreateResponse = serde_json::from_value(rx.recv().unwrap()).unwrap();
-    Ok(response.block)
+    let response: JsonBlockCreateResponse = rpc_call(rpc_tx, message)?;
+    Ok((response.hash, response.block))
 }
 
 #[derive(Serialize)]
@@ -461,33 +1080,530 @@ pub fn rpc_process(
     rpc_tx: Sender<RpcCommand>,
     subtype: SUBTYPE,
     block: Block,
-) -> Result<String, String> {
-    let subtypestr: String;
-    match subtype {
-        SUBTYPE::CHANGE => {
-            subtypestr = "change".to_owned();
-        }
-        SUBTYPE::SEND => {
-            subtypestr = "send".to_owned();
-        }
-        SUBTYPE::RECEIVE => {
-            subtypestr = "receive".to_owned();
-        }
-    }
-    let action = "process".to_owned();
-    info!("RPC cmd {}", &action);
-
+) -> Result<String, RpcError> {
+    let subtypestr = match subtype {
+        SUBTYPE::CHANGE => "change",
+        SUBTYPE::SEND => "send",
+        SUBTYPE::RECEIVE => "receive",
+    };
     let message = JsonProcessMessage {
-        action,
+        action: "process".to_owned(),
         json_block: true,
-        subtype: subtypestr,
+        subtype: subtypestr.to_owned(),
         block,
     };
-    let message = serde_json::to_value(message).unwrap();
-    let (tx, rx) = mpsc::channel::<Value>();
-    let cmd = RpcCommand::new(message, tx);
-    rpc_tx.send(cmd).unwrap();
-
-    let response: JsonProcessResponse = serde_json::from_value(rx.recv().unwrap()).unwrap();
+    let response: JsonProcessResponse = rpc_call(rpc_tx, message)?;
     Ok(response.hash)
 }
+
+#[derive(Serialize)]
+struct JsonBlockInfoMessage {
+    action: String,
+    json_block: bool,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct JsonBlocksInfoMessage {
+    action: String,
+    json_block: bool,
+    hashes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonBlockInfoResponse {
+    pub block_account: Address,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub amount: Raw,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub balance: Raw,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub height: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub local_timestamp: u64,
+    pub successor: Option<String>,
+    #[serde(deserialize_with = "deserialize_bool_from_string")]
+    pub confirmed: bool,
+    pub contents: Block,
+    pub subtype: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonBlocksInfoResponse {
+    blocks: HashMap<String, JsonBlockInfoResponse>,
+}
+
+pub fn rpc_block_info(rpc_tx: Sender<RpcCommand>, hash: &str) -> Result<JsonBlockInfoResponse, RpcError> {
+    let message = JsonBlockInfoMessage {
+        action: "block_info".to_owned(),
+        json_block: true,
+        hash: hash.to_owned(),
+    };
+    rpc_call(rpc_tx, message)
+}
+
+pub fn rpc_blocks_info(
+    rpc_tx: Sender<RpcCommand>,
+    hashes: Vec<String>,
+) -> Result<HashMap<String, JsonBlockInfoResponse>, RpcError> {
+    let message = JsonBlocksInfoMessage {
+        action: "blocks_info".to_owned(),
+        json_block: true,
+        hashes,
+    };
+    let response: JsonBlocksInfoResponse = rpc_call(rpc_tx, message)?;
+    Ok(response.blocks)
+}
+
+#[derive(Serialize)]
+struct JsonActionMessage {
+    action: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonBlockCountResponse {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub unchecked: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cemented: u64,
+}
+
+pub fn rpc_block_count(rpc_tx: Sender<RpcCommand>) -> Result<JsonBlockCountResponse, RpcError> {
+    let message = JsonActionMessage {
+        action: "block_count".to_owned(),
+    };
+    rpc_call(rpc_tx, message)
+}
+
+pub fn rpc_representatives_online(rpc_tx: Sender<RpcCommand>) -> Result<Vec<Address>, RpcError> {
+    let message = JsonActionMessage {
+        action: "representatives_online".to_owned(),
+    };
+    let value = rpc_request(rpc_tx, message)?;
+    // Older nodes return a map of representative to an empty string
+    match &value["representatives"] {
+        Value::Object(map) => Ok(map.keys().cloned().collect()),
+        v if is_empty_result(v) => Ok(vec![]),
+        v => from_value(v.clone()),
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonTelemetryResponse {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub block_count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cemented_count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub unchecked_count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub account_count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub bandwidth_cap: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub peer_count: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub protocol_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub uptime: u64,
+    pub genesis_block: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub major_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub minor_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub patch_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pre_release_version: u64,
+    pub maker: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timestamp: u64,
+    pub active_difficulty: String,
+}
+
+/// Telemetry of the connected node's peers, averaged by the node
+pub fn rpc_telemetry(rpc_tx: Sender<RpcCommand>) -> Result<JsonTelemetryResponse, RpcError> {
+    let message = JsonActionMessage {
+        action: "telemetry".to_owned(),
+    };
+    rpc_call(rpc_tx, message)
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonVersionResponse {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub rpc_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub store_version: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub protocol_version: u64,
+    pub node_vendor: String,
+    pub store_vendor: String,
+    pub network: String,
+    pub network_identifier: String,
+    pub build_info: String,
+}
+
+pub fn rpc_version(rpc_tx: Sender<RpcCommand>) -> Result<JsonVersionResponse, RpcError> {
+    let message = JsonActionMessage {
+        action: "version".to_owned(),
+    };
+    rpc_call(rpc_tx, message)
+}
+
+/// Accounts delegating to a representative, with their balance
+pub fn rpc_delegators(rpc_tx: Sender<RpcCommand>, address: &Address) -> Result<HashMap<Address, Raw>, RpcError> {
+    let message = JsonAccountMessage {
+        action: "delegators".to_owned(),
+        account: address.to_owned(),
+    };
+    let value = rpc_request(rpc_tx, message)?;
+    if is_empty_result(&value["delegators"]) {
+        return Ok(HashMap::new());
+    }
+    let data: HashMap<Address, String> = from_value(value["delegators"].clone())?;
+    data.into_iter()
+        .map(|(account, balance)| match balance.parse::<Raw>() {
+            Ok(balance) => Ok((account, balance)),
+            Err(_) => Err(RpcError::Malformed(format!("invalid balance {}", balance))),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct JsonAccountHistoryMessage {
+    action: String,
+    account: Address,
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct JsonAccountHistoryResponse {
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub r#type: String,
+    pub account: Address,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub amount: Raw,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub local_timestamp: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub height: u64,
+    pub hash: String,
+}
+
+pub fn rpc_account_history(
+    rpc_tx: Sender<RpcCommand>,
+    address: &Address,
+    count: usize,
+) -> Result<Vec<HistoryEntry>, RpcError> {
+    let message = JsonAccountHistoryMessage {
+        action: "account_history".to_owned(),
+        account: address.to_owned(),
+        count,
+    };
+
+    // If history is an empty string, return an empty result
+    let value = rpc_request(rpc_tx, message)?;
+    if is_empty_result(&value["history"]) {
+        return Ok(vec![]);
+    }
+    let response: JsonAccountHistoryResponse = from_value(value)?;
+    Ok(response.history)
+}
+
+/// Fake node for tests, answering every request with the response of the handler
+/// Requests the handler has no response for are dropped, which surfaces as RpcError::Disconnected
+#[cfg(test)]
+pub(crate) fn mock_node<F>(mut handler: F) -> Sender<RpcCommand>
+where
+    F: FnMut(&Value) -> Option<Value> + Send + 'static,
+{
+    let (rpc_tx, rpc_rx) = mpsc::channel::<RpcCommand>();
+    thread::spawn(move || {
+        for cmd in rpc_rx {
+            if let Some(response) = handler(&cmd.json()) {
+                let _ = cmd.respond(Ok(response));
+            }
+        }
+    });
+    rpc_tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answer every request for the given action with a recorded node response
+    fn mock_rpc(action: &'static str, fixture: &'static str) -> Sender<RpcCommand> {
+        mock_node(move |cmd| match cmd["action"] == action {
+            true => Some(serde_json::from_str(fixture).unwrap()),
+            false => None,
+        })
+    }
+
+    const ACCOUNT: &str = "nano_1ipx847tk8o46pwxt5qjdbncjqcbwcc1rrmqnkztrfjy5k7z4imsrata9est";
+    const HASH: &str = "87434F8041869A01C8F6F263B87972D7BA443A72E0A97D7A3FD0CCC2358FD6F9";
+
+    #[test]
+    fn rpc_accounts_no_node_required() {
+        let rpc_tx = mock_rpc("account_balance", include_str!("../tests/fixtures/rpc/account_balance.json"));
+        let balance = rpc_account_balance(rpc_tx, &ACCOUNT.to_owned()).unwrap();
+        assert_eq!(balance.balance, 10000);
+        assert_eq!(balance.pending, 10000);
+
+        let rpc_tx = mock_rpc("accounts_balances", include_str!("../tests/fixtures/rpc/accounts_balances.json"));
+        let balances = rpc_accounts_balances(rpc_tx, vec![ACCOUNT.to_owned()]).unwrap();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[ACCOUNT].balance, 325586539664609129644855132177);
+
+        let rpc_tx = mock_rpc("accounts_frontiers", include_str!("../tests/fixtures/rpc/accounts_frontiers.json"));
+        let frontiers = rpc_accounts_frontiers(rpc_tx, vec![ACCOUNT.to_owned()]).unwrap();
+        assert_eq!(frontiers[ACCOUNT], "791AF413173EEE674A6FCF633B5DFC0F3C33F397F0DA08E987D9E0741D40D81A");
+
+        let rpc_tx = mock_rpc("account_info", include_str!("../tests/fixtures/rpc/account_info.json"));
+        let info = rpc_account_info(rpc_tx, &ACCOUNT.to_owned(), Some(true)).unwrap();
+        assert_eq!(info.frontier, "80A6745762493FA21A22718ABFA4F635656A707B48B3324198AC7F3938DE6D4F");
+        assert_eq!(info.balance, 11999999999999999918751838129509869131);
+        assert_eq!(info.block_count, 33);
+        assert_eq!(info.confirmed_height, Some("28".to_owned()));
+        assert_eq!(
+            info.representative,
+            Some("nano_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3".to_owned())
+        );
+
+        let rpc_tx = mock_rpc("account_history", include_str!("../tests/fixtures/rpc/account_history.json"));
+        let history = rpc_account_history(rpc_tx, &ACCOUNT.to_owned(), 1).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].r#type, "send");
+        assert_eq!(history[0].amount, 80000000000000000000000000000000000);
+        assert_eq!(history[0].height, 60);
+
+        let rpc_tx = mock_rpc("account_weight", r#"{"weight": "10000"}"#);
+        assert_eq!(rpc_account_weight(rpc_tx, &ACCOUNT.to_owned()).unwrap(), 10000);
+
+        let rpc_tx = mock_rpc("account_block_count", r#"{"block_count": "19"}"#);
+        assert_eq!(rpc_account_block_count(rpc_tx, &ACCOUNT.to_owned()).unwrap(), 19);
+
+        let rpc_tx = mock_rpc("delegators", include_str!("../tests/fixtures/rpc/delegators.json"));
+        let delegators = rpc_delegators(rpc_tx, &ACCOUNT.to_owned()).unwrap();
+        assert_eq!(delegators.len(), 2);
+        assert_eq!(
+            delegators["nano_1111111111111111111111111111111111111111111111111117353trpda"],
+            96608869565000000000000000000000
+        );
+    }
+
+    #[test]
+    fn rpc_pending_no_node_required() {
+        let rpc_tx = mock_rpc("accounts_pending", include_str!("../tests/fixtures/rpc/accounts_pending.json"));
+        let pending = rpc_accounts_pending(rpc_tx, vec![ACCOUNT.to_owned()], 10, PendingOptions::default()).unwrap();
+        assert_eq!(pending[ACCOUNT].len(), 2);
+        assert!(pending[ACCOUNT].iter().all(|b| b.amount.is_none()));
+
+        let rpc_tx = mock_rpc(
+            "accounts_pending",
+            include_str!("../tests/fixtures/rpc/accounts_pending_source.json"),
+        );
+        let options = PendingOptions {
+            threshold: Some(0),
+            source: Some(true),
+            ..Default::default()
+        };
+        let pending = rpc_accounts_pending(rpc_tx, vec![ACCOUNT.to_owned()], 10, options).unwrap();
+        assert_eq!(
+            pending[ACCOUNT],
+            vec![PendingBlock {
+                hash: "142A538F36833D1CC78B94E11C766F75818F8B940771335C6C1B8AB880C5BB1D".to_owned(),
+                amount: Some(6000000000000000000000000000000),
+                source: Some("nano_3dcfozsmekr1tr9skf1oa5wbgmxt81qepfdnt7zicq5x3hk65fg4fqj58mbr".to_owned()),
+            }]
+        );
+        // Accounts without pending blocks are returned as an empty string
+        assert!(pending["nano_3t6k35gi95xu6tergt6p69ck76ogmitsa8mnijtpxm9fkcm736xtoncuohr3"].is_empty());
+
+        let rpc_tx = mock_rpc("accounts_pending", r#"{"blocks": ""}"#);
+        assert!(rpc_accounts_pending(rpc_tx, vec![ACCOUNT.to_owned()], 10, PendingOptions::default())
+            .unwrap()
+            .is_empty());
+
+        let rpc_tx = mock_rpc("receivable", include_str!("../tests/fixtures/rpc/receivable_threshold.json"));
+        let receivable = rpc_receivable(rpc_tx, &ACCOUNT.to_owned(), 10, Some(1), None, None).unwrap();
+        assert_eq!(
+            receivable,
+            vec![PendingBlock {
+                hash: "000D1BAEC8EC208142C99059B393051BAC8380F9B5A2E6B2489A277D81789F3F".to_owned(),
+                amount: Some(6000000000000000000000000000000),
+                source: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn rpc_blocks_no_node_required() {
+        let rpc_tx = mock_rpc("block_info", include_str!("../tests/fixtures/rpc/block_info.json"));
+        let info = rpc_block_info(rpc_tx, HASH).unwrap();
+        assert_eq!(info.block_account, ACCOUNT);
+        assert_eq!(info.amount, 30000000000000000000000000000000000);
+        assert_eq!(info.height, 58);
+        assert!(info.confirmed);
+        assert_eq!(info.subtype, Some("send".to_owned()));
+        assert_eq!(info.contents.previous, "CE898C131AAEE25E05362F247760F8A3ACF34A9796A5AE0D9204E86B0637965E");
+        assert_eq!(info.contents.balance, "5606157000000000000000000000000000000");
+
+        let rpc_tx = mock_rpc("blocks_info", include_str!("../tests/fixtures/rpc/blocks_info.json"));
+        let blocks = rpc_blocks_info(rpc_tx, vec![HASH.to_owned()]).unwrap();
+        assert_eq!(blocks[HASH].balance, 5606157000000000000000000000000000000);
+        assert!(!blocks[HASH].confirmed);
+
+        let rpc_tx = mock_rpc("process", r#"{"hash": "E2FB233EF4554077A7BF1AA85851D5BF0B36965D2B0FB504B2BC778AB89917D3"}"#);
+        assert_eq!(
+            rpc_process(rpc_tx, SUBTYPE::SEND, info.contents).unwrap(),
+            "E2FB233EF4554077A7BF1AA85851D5BF0B36965D2B0FB504B2BC778AB89917D3"
+        );
+
+        let rpc_tx = mock_rpc("work_validate", include_str!("../tests/fixtures/rpc/work_validate.json"));
+        let work = rpc_work_validate(rpc_tx, "2bf29ef00786a6bc".to_owned(), HASH.to_owned()).unwrap();
+        assert!(work.valid_all);
+        assert!(!work.valid_receive);
+        assert_eq!(work.difficulty, "ffffffd21c3933f3");
+    }
+
+    #[test]
+    fn rpc_node_no_node_required() {
+        let rpc_tx = mock_rpc("block_count", r#"{"count": "1000", "unchecked": "10", "cemented": "25"}"#);
+        assert_eq!(
+            rpc_block_count(rpc_tx).unwrap(),
+            JsonBlockCountResponse {
+                count: 1000,
+                unchecked: 10,
+                cemented: 25,
+            }
+        );
+
+        let rpc_tx = mock_rpc("version", include_str!("../tests/fixtures/rpc/version.json"));
+        let version = rpc_version(rpc_tx).unwrap();
+        assert_eq!(version.rpc_version, 1);
+        assert_eq!(version.node_vendor, "Nano V23.3");
+        assert_eq!(version.network, "live");
+
+        let rpc_tx = mock_rpc("telemetry", include_str!("../tests/fixtures/rpc/telemetry.json"));
+        let telemetry = rpc_telemetry(rpc_tx).unwrap();
+        assert_eq!(telemetry.block_count, 5777903);
+        assert_eq!(telemetry.peer_count, 32);
+        assert_eq!(telemetry.major_version, 21);
+        assert_eq!(telemetry.active_difficulty, "ffffffcdbf40aa45");
+
+        let rpc_tx = mock_rpc(
+            "representatives_online",
+            include_str!("../tests/fixtures/rpc/representatives_online.json"),
+        );
+        assert_eq!(rpc_representatives_online(rpc_tx).unwrap().len(), 2);
+        let rpc_tx = mock_rpc(
+            "representatives_online",
+            r#"{"representatives": {"nano_1111111111111111111111111111111111111111111111111117353trpda": ""}}"#,
+        );
+        assert_eq!(
+            rpc_representatives_online(rpc_tx).unwrap(),
+            vec!["nano_1111111111111111111111111111111111111111111111111117353trpda".to_owned()]
+        );
+    }
+
+    #[test]
+    fn rpc_errors_no_node_required() {
+        let rpc_tx = mock_rpc("account_info", r#"{"error": "Account not found"}"#);
+        assert_eq!(
+            rpc_account_info(rpc_tx, &ACCOUNT.to_owned(), None),
+            Err(RpcError::Node("Account not found".to_owned()))
+        );
+
+        let rpc_tx = mock_rpc("block_count", r#"{"count": "x"}"#);
+        assert!(matches!(rpc_block_count(rpc_tx), Err(RpcError::Malformed(_))));
+
+        // Nothing answers version requests
+        let rpc_tx = mock_rpc("block_count", "{}");
+        assert_eq!(rpc_version(rpc_tx), Err(RpcError::Disconnected));
+
+        let e: String = RpcError::Node("Fork".to_owned()).into();
+        assert_eq!(e, "RPC node error: Fork");
+    }
+
+    #[test]
+    fn rpc_queue_priority_no_node_required() {
+        let (tx, _rx) = mpsc::channel();
+        let command = |action: &str| RpcCommand::new(json!({ "action": action }), tx.clone());
+
+        let mut queue = RpcQueue::default();
+        queue.push(command("accounts_pending"));
+        queue.push(command("account_info"));
+        queue.push(command("account_history"));
+        queue.push(command("process"));
+        queue.push(command("account_balance").with_priority(RpcPriority::Low));
+        queue.push(command("block_create"));
+
+        let order: Vec<String> = std::iter::from_fn(|| queue.pop()).map(|c| c.action().to_owned()).collect();
+        assert_eq!(
+            order,
+            vec![
+                "process",
+                "block_create",
+                "account_info",
+                "accounts_pending",
+                "account_history",
+                "account_balance",
+            ]
+        );
+    }
+
+    #[test]
+    fn rpc_failover_no_node_required() {
+        let mut endpoints = RpcEndpoints::new(vec!["a:1".to_owned(), "b:1".to_owned(), "c:1".to_owned()]);
+        assert_eq!(endpoints.current(), (0, "a:1".to_owned()));
+
+        endpoints.fail(0);
+        assert_eq!(endpoints.current().0, 1);
+        // A failure on an endpoint that is not in use does not switch
+        endpoints.fail(2);
+        assert_eq!(endpoints.current().0, 1);
+        // Without healthy endpoints, keep rotating
+        endpoints.fail(1);
+        assert_eq!(endpoints.current().0, 2);
+        endpoints.fail(2);
+        assert_eq!(endpoints.current().0, 0);
+
+        // Recovered endpoints are used again, preferring the first ones
+        endpoints.set_health(2, true);
+        assert_eq!(endpoints.current().0, 2);
+        endpoints.set_health(1, true);
+        assert_eq!(endpoints.current().0, 1);
+        endpoints.set_health(2, true);
+        assert_eq!(endpoints.current().0, 1);
+
+        assert!(is_idempotent("account_info"));
+        assert!(is_idempotent("block_create"));
+        assert!(!is_idempotent("process"));
+        assert!(!is_idempotent("send"));
+
+        assert!(is_disabled_error("work_generate", "Action not allowed"));
+        assert!(is_disabled_error("block_create", "RPC control is disabled"));
+        assert!(!is_disabled_error("work_generate", "Account not found"));
+        // Only actions with a fallback are disabled
+        assert!(!is_disabled_error("process", "Forbidden"));
+        assert!(!is_disabled_error("account_info", "Not allowed"));
+
+        // Configured actions stay disabled, detected ones until their mark expires
+        let now = Instant::now();
+        let mut disabled = DisabledActions::new(&["block_create".to_owned()]);
+        disabled.detect("work_generate", now);
+        assert!(disabled.contains("block_create", now + DISABLED_EXPIRY));
+        assert!(disabled.contains("work_generate", now));
+        assert!(!disabled.contains("process", now));
+        assert!(!disabled.contains("work_generate", now + DISABLED_EXPIRY));
+        assert!(!disabled.contains("work_generate", now));
+    }
+}
@@ -0,0 +1,383 @@
+use log::{error, info};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::address::Address;
+use crate::audit::{audit, AuditEntry, AuditReason};
+use crate::block::Block;
+use crate::common::{decrypt_bytes, encrypt_bytes, write_atomic, StorageKey};
+use crate::rpc::{rpc_account_info, rpc_process, RpcCommand, SUBTYPE};
+use crate::shutdown::begin_payment;
+use crate::unit::Raw;
+
+/// A payment that should be sent out at a given unix timestamp
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScheduledPayment {
+    pub destination: Address,
+    pub amount: Raw,
+    pub due: u64,
+}
+
+impl ScheduledPayment {
+    pub fn new(destination: Address, amount: Raw, due: u64) -> Self {
+        Self {
+            destination,
+            amount,
+            due,
+        }
+    }
+
+    /// Parse payments from csv lines formatted as <nano_address>,<amount_in_raw>,<due_unix_timestamp>
+    /// Empty lines and lines starting with # are skipped
+    pub fn parse_csv(contents: &str) -> Result<Vec<Self>, String> {
+        let mut payments = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let split: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+            if split.len() != 3 {
+                return Err(format!("Line {}: expected 3 columns, found {}", i + 1, split.len()));
+            }
+            let amount = split[1]
+                .parse::<Raw>()
+                .map_err(|_| format!("Line {}: invalid amount {}", i + 1, split[1]))?;
+            let due = split[2]
+                .parse::<u64>()
+                .map_err(|_| format!("Line {}: invalid timestamp {}", i + 1, split[2]))?;
+            payments.push(Self::new(split[0].to_owned(), amount, due));
+        }
+        // Blocks chain on previous, so they have to be signed and published in due order
+        payments.sort_by_key(|p| p.due);
+        Ok(payments)
+    }
+}
+
+/// A signed send block (including work) that has not been published yet
+#[derive(Serialize, Deserialize)]
+pub struct PresignedBlock {
+    pub hash: String,
+    pub previous: String,
+    pub payment: ScheduledPayment,
+    pub block: Block,
+}
+
+/// Chain of presigned send blocks for a single account, ordered by due time
+#[derive(Serialize, Deserialize)]
+pub struct PresignedChain {
+    pub account: Address,
+    pub blocks: Vec<PresignedBlock>,
+}
+
+impl PresignedChain {
+    pub fn new(account: Address, blocks: Vec<PresignedBlock>) -> Self {
+        Self { account, blocks }
+    }
+
+    /// Encrypt and write the chain to disk
+    pub fn save(&self, path: &str, key: &StorageKey) -> Result<(), String> {
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        write_atomic(path, &encrypt_bytes(key, &json)?)
+    }
+
+    /// Derive the key a stored chain was encrypted with from the passphrase
+    pub fn key(path: &str, passphrase: &str) -> Result<StorageKey, String> {
+        let data = fs::read(path).map_err(|e| format!("Could not read presigned chain {}: {}", path, e))?;
+        StorageKey::for_data(passphrase, &data)
+    }
+
+    /// Read and decrypt a chain from disk
+    pub fn load(path: &str, key: &StorageKey) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let json = decrypt_bytes(key, &data)?;
+        serde_json::from_slice(&json).map_err(|e| e.to_string())
+    }
+}
+
+/// Start publishing a stored presigned chain at the scheduled times
+pub fn start_scheduler(rpc_tx: Sender<RpcCommand>, path: String, key: StorageKey) -> Result<(), String> {
+    let chain = PresignedChain::load(&path, &key)?;
+    Scheduler::start(rpc_tx, chain, path, key);
+    Ok(())
+}
+
+pub struct Scheduler {
+    rpc_tx: Sender<RpcCommand>,
+    chain: PresignedChain,
+    path: String,
+    key: StorageKey,
+}
+
+impl Scheduler {
+    pub fn start(rpc_tx: Sender<RpcCommand>, chain: PresignedChain, path: String, key: StorageKey) {
+        let mut scheduler = Self {
+            rpc_tx,
+            chain,
+            path,
+            key,
+        };
+
+        thread::Builder::new()
+            .name("scheduler".to_owned())
+            .spawn(move || {
+                scheduler.run();
+            })
+            .unwrap();
+    }
+
+    fn run(&mut self) {
+        info!(
+            "SCHEDULE started with {} presigned blocks for {}",
+            self.chain.blocks.len(),
+            self.chain.account
+        );
+        while !self.chain.blocks.is_empty() {
+            // todo non polling solution?
+            thread::sleep(Duration::from_millis(1000));
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            if self.chain.blocks[0].payment.due > now {
+                continue;
+            }
+
+            // The presigned block is only valid if it still builds on the current account head
+            let frontier = match rpc_account_info(self.rpc_tx.clone(), &self.chain.account, None) {
+                Ok(info) => info.frontier,
+                Err(e) => {
+                    info!("SCHEDULE could not fetch frontier for {}: {}", self.chain.account, e);
+                    continue;
+                }
+            };
+            // A block whose publish failed may still have reached the node, then it is the new head
+            if frontier == self.chain.blocks[0].hash {
+                let next = self.chain.blocks.remove(0);
+                info!("SCHEDULE presigned block {} is already published", next.hash);
+                self.audit(&next.hash, &next.payment);
+                self.persist();
+                continue;
+            }
+            if frontier != self.chain.blocks[0].previous {
+                self.invalidate(&frontier);
+                return;
+            }
+
+            // The remaining blocks stay stored and are published when the chain is resumed
+            let _in_flight = match begin_payment() {
+                Ok(in_flight) => in_flight,
+                Err(_) => {
+                    info!("SCHEDULE stopped with {} presigned blocks left to shut down", self.chain.blocks.len());
+                    return;
+                }
+            };
+            let next = &self.chain.blocks[0];
+            match rpc_process(self.rpc_tx.clone(), SUBTYPE::SEND, next.block.clone()) {
+                Ok(hash) => {
+                    self.audit(&hash, &next.payment);
+                    if hash == next.hash {
+                        info!(
+                            "SCHEDULE published {} sending {} to {}",
+                            hash, next.payment.amount, next.payment.destination
+                        );
+                    } else {
+                        error!("SCHEDULE published block hash {} does not match presigned hash {}", hash, next.hash);
+                    }
+                }
+                // Kept and retried, the frontier check above notices when it was published after all
+                Err(e) => {
+                    error!("SCHEDULE failed to publish {}, retrying: {}", next.hash, e);
+                    continue;
+                }
+            }
+            self.chain.blocks.remove(0);
+            self.persist();
+        }
+        info!("SCHEDULE all presigned blocks for {} published", self.chain.account);
+    }
+
+    fn audit(&self, hash: &str, payment: &ScheduledPayment) {
+        audit(AuditEntry::new(
+            hash,
+            &self.chain.account,
+            Some(0),
+            SUBTYPE::SEND,
+            payment.amount,
+            &payment.destination,
+            AuditReason::Scheduled,
+        ));
+    }
+
+    /// Drop the remaining chain after the account head moved unexpectedly
+    fn invalidate(&mut self, frontier: &str) {
+        error!(
+            "SCHEDULE account {} frontier changed unexpectedly to {} (expected {}), invalidating {} presigned blocks:",
+            self.chain.account,
+            frontier,
+            self.chain.blocks[0].previous,
+            self.chain.blocks.len()
+        );
+        for block in &self.chain.blocks {
+            error!(
+                "SCHEDULE dropped {} sending {} to {} due at {}",
+                block.hash, block.payment.amount, block.payment.destination, block.payment.due
+            );
+        }
+        self.chain.blocks.clear();
+        self.persist();
+    }
+
+    /// Store the remaining chain, or remove the store once everything is published
+    fn persist(&self) {
+        let result = if self.chain.blocks.is_empty() {
+            fs::remove_file(&self.path).map_err(|e| e.to_string())
+        } else {
+            self.chain.save(&self.path, &self.key)
+        };
+        if let Err(e) = result {
+            error!("SCHEDULE failed to persist presigned chain to {}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::test_path;
+    use std::path::Path;
+    use crate::rpc::mock_node;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn schedule_parse_csv_no_node_required() {
+        let csv = "# destination,amount,due\n\
+                   nano_b,2,200\n\
+                   \n\
+                   nano_a, 1 , 100\n";
+        assert_eq!(
+            ScheduledPayment::parse_csv(csv).unwrap(),
+            vec![
+                ScheduledPayment::new("nano_a".to_owned(), 1, 100),
+                ScheduledPayment::new("nano_b".to_owned(), 2, 200),
+            ]
+        );
+        assert!(ScheduledPayment::parse_csv("nano_a,1").is_err());
+        assert!(ScheduledPayment::parse_csv("nano_a,x,100").is_err());
+        assert!(ScheduledPayment::parse_csv("nano_a,1,-5").is_err());
+    }
+
+    #[test]
+    fn schedule_chain_store_no_node_required() {
+        let path = test_path("presigned_chain_store.bin");
+        let key = StorageKey::new("correct horse").unwrap();
+        let block = Block::new(
+            "nano_a".to_owned(),
+            "A".repeat(64),
+            "nano_rep".to_owned(),
+            5,
+            "nano_b".to_owned(),
+            "nano_b".to_owned(),
+            Some("S".repeat(128)),
+            Some("0000000000000000".to_owned()),
+            Some("send".to_owned()),
+        );
+        let chain = PresignedChain::new(
+            "nano_a".to_owned(),
+            vec![PresignedBlock {
+                hash: "B".repeat(64),
+                previous: "A".repeat(64),
+                payment: ScheduledPayment::new("nano_b".to_owned(), 1, 100),
+                block,
+            }],
+        );
+        chain.save(&path, &key).unwrap();
+
+        // Stored data is not plaintext
+        let data = fs::read(&path).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("nano_b"));
+
+        // The key is derived again from the salt stored with the chain
+        let loaded = PresignedChain::load(&path, &PresignedChain::key(&path, "correct horse").unwrap()).unwrap();
+        assert_eq!(loaded.account, "nano_a");
+        assert_eq!(loaded.blocks.len(), 1);
+        assert_eq!(loaded.blocks[0].hash, "B".repeat(64));
+        assert_eq!(loaded.blocks[0].block.signature, Some("S".repeat(128)));
+
+        // Wrong key or tampering is rejected
+        assert!(PresignedChain::load(&path, &PresignedChain::key(&path, "wrong").unwrap()).is_err());
+        let mut tampered = data.clone();
+        tampered[20] ^= 1;
+        fs::write(&path, tampered).unwrap();
+        assert!(PresignedChain::load(&path, &key).is_err());
+        // Data without the header, e.g. stored by an older version, is reported as such
+        fs::write(&path, &data[4..]).unwrap();
+        assert!(PresignedChain::key(&path, "correct horse").err().unwrap().contains("older version"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn schedule_retry_no_node_required() {
+        let path = test_path("presigned_retry.bin");
+        let key = StorageKey::new("correct horse").unwrap();
+        let (previous, hash) = ("A".repeat(64), "B".repeat(64));
+        let block = Block::new(
+            "nano_a".to_owned(),
+            previous.clone(),
+            "nano_rep".to_owned(),
+            5,
+            "nano_b".to_owned(),
+            "nano_b".to_owned(),
+            Some("S".repeat(128)),
+            Some("0000000000000000".to_owned()),
+            Some("send".to_owned()),
+        );
+        let chain = PresignedChain::new(
+            "nano_a".to_owned(),
+            vec![PresignedBlock {
+                hash: hash.clone(),
+                previous: previous.clone(),
+                payment: ScheduledPayment::new("nano_b".to_owned(), 1, 100),
+                block,
+            }],
+        );
+        chain.save(&path, &key).unwrap();
+
+        // The first publish gets no answer, the second fails although the block reached the node
+        let account_info: Value =
+            serde_json::from_str(include_str!("../tests/fixtures/rpc/account_info.json")).unwrap();
+        let mut attempts = 0;
+        let (tx, rx) = std::sync::mpsc::channel::<usize>();
+        let rpc_tx = mock_node(move |cmd| match cmd["action"].as_str().unwrap() {
+            "account_info" => {
+                let mut info = account_info.clone();
+                info["frontier"] = json!(if attempts < 2 { &previous } else { &hash });
+                Some(info)
+            }
+            "process" => {
+                attempts += 1;
+                tx.send(attempts).unwrap();
+                match attempts {
+                    1 => None,
+                    _ => Some(json!({ "error": "Unable to parse json" })),
+                }
+            }
+            _ => None,
+        });
+        let mut scheduler = Scheduler {
+            rpc_tx,
+            chain,
+            path: path.clone(),
+            key,
+        };
+        scheduler.run();
+
+        // The block was kept until the node had it, then dropped from the stored chain
+        assert_eq!(rx.try_iter().collect::<Vec<usize>>(), vec![1, 2]);
+        assert!(scheduler.chain.blocks.is_empty());
+        assert!(!Path::new(&path).exists());
+    }
+}
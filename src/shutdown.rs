@@ -0,0 +1,130 @@
+use log::{error, info};
+use once_cell::sync::Lazy;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cli::{EXIT_FAILED, EXIT_OK};
+use crate::config::CONFIG;
+use crate::ws::{ws_close, WsSubscription};
+
+/// Set once shutdown started, no new payments are accepted after that
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Number of payments in progress, the condvar is notified whenever one finishes
+static IN_FLIGHT: Lazy<(Mutex<usize>, Condvar)> = Lazy::new(|| (Mutex::new(0), Condvar::new()));
+
+/// Whether all payments finished in time, set once shutdown completed
+static DONE: Lazy<Mutex<Option<bool>>> = Lazy::new(|| Mutex::new(None));
+
+/// Runs once the payments stopped, e.g. to restore the terminal after the dashboard
+pub type ExitHook = Box<dyn FnOnce() + Send>;
+
+static EXIT_HOOKS: Lazy<Mutex<Vec<ExitHook>>> = Lazy::new(|| Mutex::new(vec![]));
+
+pub fn shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Register a payment in progress until the guard is dropped, fails once shutdown started
+pub fn begin_payment() -> Result<InFlight, String> {
+    let mut count = IN_FLIGHT.0.lock().unwrap();
+    // Checked under the lock, so shutdown can't miss a payment that starts while it begins waiting
+    if shutting_down() {
+        return Err("Shutting down, not accepting new payments".to_owned());
+    }
+    *count += 1;
+    Ok(InFlight(()))
+}
+
+/// A payment in progress, see begin_payment
+pub struct InFlight(());
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let (count, finished) = &*IN_FLIGHT;
+        *count.lock().unwrap() -= 1;
+        finished.notify_all();
+    }
+}
+
+/// Run a hook on shutdown, after the payments in progress stopped
+pub fn at_exit(hook: ExitHook) {
+    EXIT_HOOKS.lock().unwrap().push(hook);
+}
+
+/// Stop accepting payments, wait up to timeouts.shutdown seconds for those in progress, then run the exit hooks,
+/// close the websocket and flush the logs
+/// Returns false when payments were still in progress, they are resumed from the payment journal on the next start
+/// It runs once, later callers wait for the first one to complete
+pub fn shutdown(ws_tx: &Sender<WsSubscription>) -> bool {
+    let mut done = DONE.lock().unwrap();
+    if let Some(finished) = *done {
+        return finished;
+    }
+    let finished = wait_for_payments(Duration::from_secs(CONFIG.timeouts.shutdown as u64));
+    run_exit_hooks();
+    if let Err(e) = ws_close(ws_tx) {
+        error!("SHUTDOWN could not close the websocket: {}", e);
+    }
+    info!("SHUTDOWN complete");
+    log::logger().flush();
+    *done = Some(finished);
+    finished
+}
+
+fn run_exit_hooks() {
+    let hooks: Vec<ExitHook> = EXIT_HOOKS.lock().unwrap().drain(..).collect();
+    for hook in hooks {
+        hook();
+    }
+}
+
+fn wait_for_payments(timeout: Duration) -> bool {
+    let (count, finished) = &*IN_FLIGHT;
+    let mut count = count.lock().unwrap();
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    if *count > 0 {
+        info!("SHUTDOWN waiting for {} payments in progress", *count);
+    }
+    let deadline = Instant::now() + timeout;
+    while *count > 0 {
+        let now = Instant::now();
+        if now >= deadline {
+            error!(
+                "SHUTDOWN {} payments still in progress after {:?}, they are resumed on the next start",
+                *count, timeout
+            );
+            return false;
+        }
+        count = finished.wait_timeout(count, deadline - now).unwrap().0;
+    }
+    true
+}
+
+/// Shut down on SIGINT or SIGTERM and exit, with exit code 1 when payments were still in progress
+/// A second signal while shutting down exits right away
+pub fn start_signal_handler(ws_tx: Sender<WsSubscription>) -> Result<(), String> {
+    let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(|e| format!("Could not handle signals: {}", e))?;
+    thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || {
+            for signal in signals.forever() {
+                if shutting_down() {
+                    error!("SHUTDOWN received signal {} again, exiting without waiting", signal);
+                    run_exit_hooks();
+                    process::exit(EXIT_FAILED);
+                }
+                info!("SHUTDOWN received signal {}", signal);
+                let ws_tx = ws_tx.clone();
+                thread::spawn(move || process::exit(if shutdown(&ws_tx) { EXIT_OK } else { EXIT_FAILED }));
+            }
+        })
+        .unwrap();
+    Ok(())
+}
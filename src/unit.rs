@@ -1 +1,64 @@
 pub type Raw = u128;
+
+/// Number of decimals of a nano (XNO) amount in raw
+const NANO_DECIMALS: usize = 30;
+
+/// Raw per nano (XNO)
+pub const RAW_PER_NANO: Raw = 1_000_000_000_000_000_000_000_000_000_000;
+
+/// Format a raw amount as nano, e.g. 1500000000000000000000000000000 as "1.5"
+pub fn raw_to_nano(raw: Raw) -> String {
+    let fraction = format!("{:030}", raw % RAW_PER_NANO);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        (raw / RAW_PER_NANO).to_string()
+    } else {
+        format!("{}.{}", raw / RAW_PER_NANO, fraction)
+    }
+}
+
+/// Parse a nano amount with up to 30 decimals, e.g. "1.5", to raw
+pub fn nano_to_raw(nano: &str) -> Result<Raw, String> {
+    let invalid = || format!("Invalid nano amount {}", nano);
+    let (whole, fraction) = nano.split_once('.').unwrap_or((nano, ""));
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > NANO_DECIMALS
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let whole = if whole.is_empty() { 0 } else { whole.parse::<Raw>().map_err(|_| invalid())? };
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<30}", fraction).parse::<Raw>().map_err(|_| invalid())?
+    };
+    whole
+        .checked_mul(RAW_PER_NANO)
+        .and_then(|w| w.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_nano_raw_no_node_required() {
+        assert_eq!(raw_to_nano(0), "0");
+        assert_eq!(raw_to_nano(RAW_PER_NANO), "1");
+        assert_eq!(raw_to_nano(1_500_000_000_000_000_000_000_000_000_000), "1.5");
+        assert_eq!(raw_to_nano(1), "0.000000000000000000000000000001");
+
+        assert_eq!(nano_to_raw("1"), Ok(RAW_PER_NANO));
+        assert_eq!(nano_to_raw("1.5"), Ok(1_500_000_000_000_000_000_000_000_000_000));
+        assert_eq!(nano_to_raw(".5"), Ok(RAW_PER_NANO / 2));
+        assert_eq!(nano_to_raw("0.000000000000000000000000000001"), Ok(1));
+        assert!(nano_to_raw("0.0000000000000000000000000000001").is_err());
+        assert!(nano_to_raw("").is_err());
+        assert!(nano_to_raw(".").is_err());
+        assert!(nano_to_raw("1,5").is_err());
+        assert!(nano_to_raw("-1").is_err());
+        assert!(nano_to_raw("1000000000").is_err());
+    }
+}
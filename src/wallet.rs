@@ -1,45 +1,228 @@
-use log::info;
+use log::{error, info};
+use std::ops::Range;
+use std::path::Path;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::account::Account;
-use crate::address::Address;
-use crate::common::bytes_to_hexstring;
-use crate::config::get_config;
+use crate::address::{validate_address, Address};
+use crate::audit::AuditReason;
+use crate::common::{bytes_to_hexstring, StorageKey};
+use crate::block::Block;
+use crate::config::CONFIG;
+use crate::contacts::display_address;
+use crate::journal::{owns_data_dir, JournalEntry, PaymentJournal, PaymentKind};
+use crate::limits::SpendingLimits;
+use crate::logger::log_payment;
+use crate::metrics;
+use crate::offline;
 use crate::pool::Pool;
-use crate::rpc::RpcCommand;
+use crate::rpc::{
+    rpc_account_balance, rpc_account_history, rpc_accounts_pending, HistoryEntry, PendingBlock, PendingOptions, RpcCommand,
+};
+use crate::schedule::{start_scheduler, PresignedChain, ScheduledPayment};
 use crate::seed::Seed;
+use crate::shutdown::{begin_payment, shutting_down};
 use crate::unit::Raw;
 use crate::ws::WsSubscription;
 
+/// Record of the recent sends of a wallet, for the daily spending limit
+fn spent_path(wallet: &str) -> String {
+    format!("data/spent_{}.json", wallet)
+}
+
+/// Journal of the pool payments of a wallet that are in progress
+fn journal_path(wallet: &str) -> String {
+    format!("data/payments_{}.json", wallet)
+}
+
 pub struct Wallet {
-    seed: Seed,
+    name: String,
+    seed: Option<Seed>,
     account: Arc<Mutex<Account>>,
     pool: Pool,
+    limits: SpendingLimits,
+    journal: PaymentJournal,
     rpc_tx: Sender<RpcCommand>,
 }
 
 impl Wallet {
-    pub fn new(seed: Seed, rpc_tx: Sender<RpcCommand>, ws_tx: Sender<WsSubscription>) -> Wallet {
-        let account = Account::new(seed, 0, rpc_tx.clone(), ws_tx.clone());
-        Wallet {
-            seed,
+    pub fn new(
+        name: String,
+        seed: Seed,
+        representative: Address,
+        start_index: u32,
+        rpc_tx: Sender<RpcCommand>,
+        ws_tx: Sender<WsSubscription>,
+    ) -> Result<Wallet, String> {
+        info!("WALLET {} starting", name);
+        let account = Account::new(seed, 0, representative.clone(), rpc_tx.clone(), ws_tx.clone())?;
+        let limits = SpendingLimits::load(
+            &spent_path(&name),
+            CONFIG.limits.transaction_raw(),
+            CONFIG.limits.daily_raw(),
+        )?;
+        let journal = PaymentJournal::load(&journal_path(&name))?;
+        let mut wallet = Wallet {
+            name,
+            seed: Some(seed),
             account: account.clone(),
             pool: Pool::new(
                 seed,
+                start_index,
+                representative,
                 rpc_tx.clone(),
                 ws_tx.clone(),
                 account.clone().lock().unwrap().address(),
             ),
+            limits,
+            journal,
+            rpc_tx,
+        };
+        if owns_data_dir() {
+            wallet.resume_payments();
+        } else if !wallet.journal.entries().is_empty() {
+            info!(
+                "WALLET {} another process uses the data directory, leaving interrupted payments to it",
+                wallet.name
+            );
+        }
+        Ok(wallet)
+    }
+
+    /// Create a watch-only wallet from the main account address and the pool account addresses
+    /// It tracks balances and incoming payments, but refuses to send
+    pub fn watch(
+        name: String,
+        address: &str,
+        pool_addresses: &[Address],
+        rpc_tx: Sender<RpcCommand>,
+        ws_tx: Sender<WsSubscription>,
+    ) -> Result<Wallet, String> {
+        info!("WALLET {} starting watch-only", name);
+        for address in std::iter::once(address).chain(pool_addresses.iter().map(|a| a.as_str())) {
+            validate_address(address).map_err(|e| format!("invalid address: {}", e))?;
+        }
+        let account = Account::watch(address, 0, rpc_tx.clone(), ws_tx.clone())?;
+        let mut pool_accounts = vec![];
+        for (i, pool_address) in pool_addresses.iter().enumerate() {
+            pool_accounts.push(Account::watch(pool_address, i as u32 + 1, rpc_tx.clone(), ws_tx.clone())?);
+        }
+        let wallet_address = account.lock().unwrap().address();
+        // Watch-only wallets only send blocks signed offline, those are limited like any other send
+        let limits = SpendingLimits::load(
+            &spent_path(&name),
+            CONFIG.limits.transaction_raw(),
+            CONFIG.limits.daily_raw(),
+        )?;
+        let journal = PaymentJournal::load(&journal_path(&name))?;
+        Ok(Wallet {
+            name,
+            seed: None,
+            account,
+            pool: Pool::watch(pool_accounts, rpc_tx.clone(), ws_tx, wallet_address),
+            limits,
+            journal,
             rpc_tx,
+        })
+    }
+
+    /// Check if the wallet is watch-only
+    pub fn is_watch_only(&self) -> bool {
+        self.seed.is_none()
+    }
+
+    /// Fail for watch-only wallets, which can't sign blocks
+    fn check_can_send(&self) -> Result<(), String> {
+        if self.is_watch_only() {
+            Err(format!("Wallet {} is watch-only and can't send", self.name))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that an amount can be sent from the main account, and that it is within the spending limits
+    /// The limits are only skipped when explicitly overridden
+    pub fn check_send(&self, amount: Raw, override_limits: bool) -> Result<(), String> {
+        self.check_can_send()?;
+        let balance = self.balance();
+        if amount == 0 {
+            return Err("Cannot send 0 raw".to_string());
+        }
+        if balance < amount {
+            return Err(format!(
+                "Cannot send {} raw because the main account only holds {}",
+                amount, balance
+            ));
         }
+        self.check_limits(&[amount], override_limits)
     }
 
-    /// Get wallet account seed as string
-    pub fn seed(&self) -> String {
-        bytes_to_hexstring(&self.seed)
+    /// Check that sends are within the spending limits, unless explicitly overridden
+    fn check_limits(&self, amounts: &[Raw], override_limits: bool) -> Result<(), String> {
+        if override_limits {
+            info!("WALLET {} spending limits overridden to send {:?}", self.name, amounts);
+            return Ok(());
+        }
+        self.limits.check_all(amounts, SpendingLimits::now())
+    }
+
+    /// Count a send from the main account towards the daily limit
+    fn record_spent(&mut self, amount: Raw) {
+        if let Err(e) = self.limits.record(amount, SpendingLimits::now()) {
+            error!("WALLET {} {}", self.name, e);
+        }
+    }
+
+    /// Finish the pool payments that were interrupted by a shutdown or crash
+    /// A send whose pool account holds the amount is sent on to its destination, anything else is swept to the main account
+    fn resume_payments(&mut self) {
+        for entry in self.journal.entries() {
+            let _payment = log_payment(&entry.id);
+            info!(
+                "WALLET {} resuming interrupted {:?} of {} on pool account {}",
+                self.name, entry.kind, entry.amount, entry.index
+            );
+            match self.resume_payment(&entry) {
+                Ok(()) => self.finish_payment(&entry.id),
+                Err(e) => error!("WALLET {} could not resume payment {}: {}", self.name, entry.id, e),
+            }
+        }
+    }
+
+    fn resume_payment(&mut self, entry: &JournalEntry) -> Result<(), String> {
+        let pool_account_arc = self.pool.recover_account(entry.index)?;
+        let mut pool_account = pool_account_arc.lock().unwrap();
+        if entry.kind == PaymentKind::PoolSend && pool_account.balance() >= entry.amount {
+            pool_account.send(entry.amount, entry.destination.clone(), AuditReason::PoolSend)?;
+        } else if entry.kind == PaymentKind::PoolSend {
+            info!(
+                "WALLET {} pool account {} was not funded with {}, sweeping it",
+                self.name, entry.index, entry.amount
+            );
+        }
+        drop(pool_account);
+        self.pool.return_account(pool_account_arc);
+        Ok(())
+    }
+
+    /// Remove a pool payment from the journal once its pool account is returned
+    fn finish_payment(&mut self, id: &str) {
+        if let Err(e) = self.journal.remove(id) {
+            error!("WALLET {} {}", self.name, e);
+        }
+    }
+
+    /// Get the wallet name
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Get wallet account seed as string, None for watch-only wallets
+    pub fn seed(&self) -> Option<String> {
+        self.seed.map(|seed| bytes_to_hexstring(&seed))
     }
 
     /// Get a reference to the wallet account
@@ -52,82 +235,337 @@ impl Wallet {
         &self.pool
     }
 
+    /// Get the main account balance
+    pub fn balance(&self) -> Raw {
+        self.account.lock().unwrap().balance()
+    }
+
+    /// Get the main account address
+    pub fn address(&self) -> Address {
+        self.account.lock().unwrap().address()
+    }
+
+    /// Get the blocks that are ready to be received by the main account, largest first
+    pub fn pending(&self, count: usize) -> Result<Vec<PendingBlock>, String> {
+        let address = self.address();
+        let options = PendingOptions {
+            source: Some(true),
+            sorting: Some(true),
+            ..Default::default()
+        };
+        let mut pending = rpc_accounts_pending(self.rpc_tx.clone(), vec![address.clone()], count, options)?;
+        Ok(pending.remove(&address).unwrap_or_default())
+    }
+
+    /// Derive the addresses of a range of account indices, e.g. to distribute pool addresses
+    pub fn derive_addresses(&self, indices: Range<u32>) -> Result<Vec<(u32, Address)>, String> {
+        let seed = self
+            .seed
+            .ok_or(format!("Wallet {} is watch-only and can't derive addresses", self.name))?;
+        Ok(indices
+            .map(|i| {
+                let public_key = Account::derive_public_key(Account::derive_private_key(seed, i));
+                (i, Account::derive_address(public_key))
+            })
+            .collect())
+    }
+
     /// Send an amount of nano from the wallet to a destination through the pool
     /// > send_payment nano_3qy8738374rbpc37sna1mb5hu8in7rbfapagba6gthsdnyrarf7457er5f39 1000000000000000000000000000
-    pub fn send_payment(&mut self, amount: Raw, destination: Address) -> Result<(), String> {
-        info!("WALLET sending {} to {} via pool", amount, destination);
-        let mut account = self.account.lock().unwrap();
-        if amount == 0 {
-            Err("Cannot send 0 raw".to_string())
-        } else if account.balance() < amount {
-            Err(format!(
-                "Cannot send {} raw because the main account only holds {}",
-                amount,
-                account.balance()
-            ))
-        } else {
-            let pool_account_arc = self.pool.get_account();
-            let pool_account = pool_account_arc.lock().unwrap();
-            account.send(amount, pool_account.address())?;
-            drop(account);
-
-            let mut balance = 0;
-            let address = &pool_account.address();
-            drop(pool_account);
-            while balance < amount {
-                // todo non polling solution?
-                thread::sleep(Duration::from_millis(1000));
-                let (b, _) = Account::fetch_balance(self.rpc_tx.clone(), address);
-                balance = b;
+    pub fn send_payment(&mut self, amount: Raw, destination: Address, override_limits: bool) -> Result<(), String> {
+        let _in_flight = begin_payment()?;
+        let id = payment_id();
+        let _payment = log_payment(&id);
+        let result = self.send_via_pool(&id, amount, destination, override_limits);
+        self.count_payment("pool_send", &result);
+        result
+    }
+
+    fn send_via_pool(&mut self, id: &str, amount: Raw, destination: Address, override_limits: bool) -> Result<(), String> {
+        info!("WALLET {} sending {} to {} via pool", self.name, amount, display_address(&destination));
+        self.check_send(amount, override_limits)?;
+        // Reserved before locking the main account, watching a pool account waits for the websocket ack
+        let pool_account_arc = self.pool.get_account()?;
+        let pool_account = pool_account_arc.lock().unwrap();
+        let address = pool_account.address();
+        let entry = JournalEntry {
+            id: id.to_owned(),
+            kind: PaymentKind::PoolSend,
+            index: pool_account.index(),
+            amount,
+            destination: destination.clone(),
+        };
+        drop(pool_account);
+        // Recorded before the pool account is funded, so an interrupted payment is finished on the next start
+        if let Err(e) = self.journal.add(entry) {
+            self.pool.return_account(pool_account_arc);
+            return Err(e);
+        }
+        let result = self.account.lock().unwrap().send(amount, address.clone(), AuditReason::PoolFund);
+        if let Err(e) = result {
+            self.abandon_payment(id, pool_account_arc);
+            return Err(e);
+        }
+        self.record_spent(amount);
+
+        // Shutdown waits for the funds to arrive, the pool account must not keep them
+        if let Err(e) = self.await_pool_funds(&address, amount) {
+            self.abandon_payment(id, pool_account_arc);
+            return Err(e);
+        }
+        let result = pool_account_arc.lock().unwrap().send(amount, destination, AuditReason::PoolSend);
+        if let Err(e) = result {
+            self.abandon_payment(id, pool_account_arc);
+            return Err(e);
+        }
+        self.pool.return_account(pool_account_arc);
+        self.finish_payment(id);
+        Ok(())
+    }
+
+    /// Wait up to timeouts.transaction seconds for the funds sent to a pool account to arrive
+    fn await_pool_funds(&self, address: &Address, amount: Raw) -> Result<(), String> {
+        let deadline = Instant::now() + Duration::from_secs(CONFIG.timeouts.transaction as u64);
+        loop {
+            thread::sleep(Duration::from_millis(CONFIG.timeouts.poll_interval as u64));
+            match rpc_account_balance(self.rpc_tx.clone(), address) {
+                Ok(b) if b.balance >= amount => return Ok(()),
+                Ok(_) => {}
+                Err(e) => error!("WALLET {} could not check pool account {}: {}", self.name, address, e),
+            }
+            if Instant::now() >= deadline {
+                return Err(format!(
+                    "Timed out awaiting {} on pool account {} after {} seconds",
+                    amount, address, CONFIG.timeouts.transaction
+                ));
             }
-            let mut pool_account = pool_account_arc.lock().unwrap();
-            pool_account.send(amount, destination)?;
-            self.pool.return_account(pool_account_arc.clone());
-            Ok(())
+        }
+    }
+
+    /// Give back the pool account of a pool payment that failed, a balance left on it is swept to the main account
+    /// The payment stays in the journal only when funds may be stranded on the pool account, i.e. the node still
+    /// reports some or can't be asked, so the next start resumes it
+    fn abandon_payment(&mut self, id: &str, pool_account_arc: Arc<Mutex<Account>>) {
+        let address = pool_account_arc.lock().unwrap().address();
+        self.pool.return_account(pool_account_arc);
+        match rpc_account_balance(self.rpc_tx.clone(), &address) {
+            Ok(b) if b.balance == 0 && b.pending == 0 => self.finish_payment(id),
+            Ok(b) => error!(
+                "WALLET {} pool account {} holds {} and {} pending, the payment is resumed on the next start",
+                self.name, address, b.balance, b.pending
+            ),
+            Err(e) => error!(
+                "WALLET {} could not check pool account {}, the payment is resumed on the next start: {}",
+                self.name, address, e
+            ),
         }
     }
 
     /// Receive some amount of nano through the pool (0 = any amount)
     /// > receive_payment 1000000000000000000000000000
     pub fn receive_payment(&mut self, amount: Raw) -> Result<(), String> {
-        let pool_account_arc = self.pool.get_account();
-        let pool_account = pool_account_arc.lock().unwrap();
+        self.receive_payment_with(amount, CONFIG.timeouts.transaction, |_| {})
+    }
 
-        let mut balance = 0;
+    /// Receive some amount of nano through the pool within a timeout in seconds
+    /// The reserved pool address is passed to on_reserved before waiting, so it can be shared with the payer
+    pub fn receive_payment_with<F: FnOnce(&Address)>(
+        &mut self,
+        amount: Raw,
+        timeout: u32,
+        on_reserved: F,
+    ) -> Result<(), String> {
+        let _in_flight = begin_payment()?;
+        let id = payment_id();
+        let _payment = log_payment(&id);
+        let result = self.receive_via_pool(&id, amount, timeout, on_reserved);
+        self.count_payment("pool_receive", &result);
+        result
+    }
+
+    fn receive_via_pool<F: FnOnce(&Address)>(
+        &mut self,
+        id: &str,
+        amount: Raw,
+        timeout: u32,
+        on_reserved: F,
+    ) -> Result<(), String> {
+        self.check_can_send()?;
+        let pool_account_arc = self.pool.get_account()?;
+        let pool_account = pool_account_arc.lock().unwrap();
+        let entry = JournalEntry {
+            id: id.to_owned(),
+            kind: PaymentKind::PoolReceive,
+            index: pool_account.index(),
+            amount,
+            destination: "".to_owned(),
+        };
         let address = &pool_account.address();
-        let transaction_timeout = get_config("config/config.toml").transaction_timeout * 1000;
-        let mut total_duration: u32 = 0;
         drop(pool_account);
-        info!("WALLET receiving {} on {}", amount, address);
+        if let Err(e) = self.journal.add(entry) {
+            self.pool.return_account(pool_account_arc);
+            return Err(e);
+        }
+
+        let mut balance = 0;
+        on_reserved(address);
+        let transaction_timeout = timeout as u64 * 1000;
+        let poll_interval = CONFIG.timeouts.poll_interval as u64;
+        let mut total_duration: u64 = 0;
+        info!(account:% = address; "WALLET {} receiving {} on {}", self.name, amount, address);
         while balance != amount {
             // todo non polling solution?
-            thread::sleep(Duration::from_millis(1000));
+            thread::sleep(Duration::from_millis(poll_interval));
             let (b, _) = Account::fetch_balance(self.rpc_tx.clone(), address);
             balance = b;
-            total_duration += 1000;
-            if total_duration >= transaction_timeout {
-                info!("WALLET timed out receiving {} on {}", amount, address);
-                self.pool.return_account(pool_account_arc.clone());
-                return Err("Timed out awaiting payment".to_owned());
-            }
+            total_duration += poll_interval;
+            // The payer may never pay, so shutdown does not wait for it like for a timeout
+            let error = if total_duration >= transaction_timeout {
+                "Timed out awaiting payment"
+            } else if shutting_down() {
+                "Shutting down, stopped awaiting payment"
+            } else {
+                continue;
+            };
+            info!("WALLET {} stopped receiving {} on {}: {}", self.name, amount, address, error);
+            self.pool.return_account(pool_account_arc);
+            self.finish_payment(id);
+            return Err(error.to_owned());
         }
-        let mut pool_account = pool_account_arc.lock().unwrap();
-        pool_account.send(amount, self.account.lock().unwrap().address())?;
-        self.pool.return_account(pool_account_arc.clone());
+        let main_address = self.account.lock().unwrap().address();
+        let result = pool_account_arc.lock().unwrap().send(amount, main_address, AuditReason::PoolForward);
+        if let Err(e) = result {
+            self.abandon_payment(id, pool_account_arc);
+            return Err(e);
+        }
+        self.pool.return_account(pool_account_arc);
+        self.finish_payment(id);
         Ok(())
     }
 
     /// Send a transaction directly from the main account
     /// > send_direct nano_3qy8738374rbpc37sna1mb5hu8in7rbfapagba6gthsdnyrarf7457er5f39 1000000000000000000000000000
-    pub fn send_direct(&mut self, amount: Raw, destination: Address) {
-        info!("WALLET send {} to {} directly", amount, destination);
-        let mut account = self.account.lock().unwrap();
-        account.send(amount, destination).unwrap();
+    pub fn send_direct(&mut self, amount: Raw, destination: Address, override_limits: bool) -> Result<(), String> {
+        let _in_flight = begin_payment()?;
+        let _payment = log_payment(&payment_id());
+        info!("WALLET {} send {} to {} directly", self.name, amount, display_address(&destination));
+        self.check_send(amount, override_limits)?;
+        let result = self.account.lock().unwrap().send(amount, destination, AuditReason::Direct);
+        self.count_payment("direct_send", &result);
+        result?;
+        self.record_spent(amount);
+        Ok(())
+    }
+
+    /// Count a payment that was started by kind and result
+    fn count_payment(&self, kind: &str, result: &Result<(), String>) {
+        let result = match result {
+            Ok(()) => "completed",
+            Err(e) if e.starts_with("Timed out") => "timed_out",
+            Err(_) => "failed",
+        };
+        metrics::count(
+            "nano_pool_payments_total",
+            &[("wallet", &self.name), ("kind", kind), ("result", result)],
+            1.0,
+        );
+    }
+
+    /// Build and sign the block a send would publish from the main account, without publishing it
+    /// Pool payments send to the pool account the next transaction would reserve
+    pub fn dry_run_send(
+        &self,
+        amount: Raw,
+        destination: Address,
+        via_pool: bool,
+        override_limits: bool,
+    ) -> Result<(String, Block), String> {
+        self.check_send(amount, override_limits)?;
+        let link = if via_pool { self.pool.next_address()? } else { destination };
+        info!("WALLET {} dry run send {} to {}", self.name, amount, display_address(&link));
+        self.account.lock().unwrap().build_send(amount, link)
     }
 
     /// Receive all transactions coming directly to the main account
-    pub fn receive_all_direct(&self) {
+    pub fn receive_all_direct(&self) -> Result<(), String> {
+        self.check_can_send()?;
         let mut account = self.account.lock().unwrap();
-        account.receive_all();
+        account.receive_all()
+    }
+
+    /// Get the most recent blocks of the main account
+    pub fn history(&self, count: usize) -> Result<Vec<HistoryEntry>, String> {
+        let address = self.account.lock().unwrap().address();
+        Ok(rpc_account_history(self.rpc_tx.clone(), &address, count)?)
     }
+
+    /// Presign send blocks from the main account for scheduled payments, store them encrypted, and publish them when due
+    /// The stored blocks do not require the seed to be published
+    /// Each payment and their total are checked against the spending limits, and counted as sent once signed
+    pub fn presign_payments(
+        &mut self,
+        payments: Vec<ScheduledPayment>,
+        path: &str,
+        key: StorageKey,
+        override_limits: bool,
+    ) -> Result<(), String> {
+        self.check_can_send()?;
+        if Path::new(path).exists() {
+            return Err(format!("A presigned chain is already stored at {}", path));
+        }
+        let amounts: Vec<Raw> = payments.iter().map(|p| p.amount).collect();
+        self.check_limits(&amounts, override_limits)?;
+        let account = self.account.lock().unwrap();
+        info!("WALLET {} presigning {} scheduled payments", self.name, payments.len());
+        let blocks = account.presign_sends(&payments)?;
+        PresignedChain::new(account.address(), blocks).save(path, &key)?;
+        drop(account);
+        for amount in amounts {
+            self.record_spent(amount);
+        }
+        start_scheduler(self.rpc_tx.clone(), path.to_owned(), key)
+    }
+
+    /// Resume publishing a previously stored presigned chain
+    pub fn resume_presigned(&self, path: &str, key: StorageKey) -> Result<(), String> {
+        start_scheduler(self.rpc_tx.clone(), path.to_owned(), key)
+    }
+
+    /// Build an unsigned send block from the main account, to be signed offline
+    /// This also works for watch-only wallets
+    pub fn prepare_send(&self, amount: Raw, destination: Address) -> Result<Block, String> {
+        info!("WALLET {} preparing unsigned send {} to {}", self.name, amount, display_address(&destination));
+        let address = self.account.lock().unwrap().address();
+        offline::prepare_send(self.rpc_tx.clone(), &address, &destination, amount)
+    }
+
+    /// Attach work to a block that was signed offline and publish it
+    /// A send is checked against the spending limits like any other send from the main account
+    pub fn publish_signed(&mut self, block: Block, override_limits: bool) -> Result<String, String> {
+        let address = self.account.lock().unwrap().address();
+        if block.account != address {
+            return Err(format!("Block account {} is not the main account of wallet {}", block.account, self.name));
+        }
+        let amount = match block.subtype.as_deref() {
+            Some("send") => {
+                let balance = block.balance.parse::<Raw>().map_err(|e| format!("Invalid block balance: {}", e))?;
+                self.balance().saturating_sub(balance)
+            }
+            _ => 0,
+        };
+        if amount > 0 {
+            self.check_limits(&[amount], override_limits)?;
+        }
+        info!("WALLET {} publishing signed block", self.name);
+        let hash = offline::publish_signed(self.rpc_tx.clone(), block)?;
+        if amount > 0 {
+            self.record_spent(amount);
+        }
+        Ok(hash)
+    }
+}
+
+/// Random id of a payment, logged with everything done for it
+fn payment_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
 }
@@ -0,0 +1,174 @@
+use blake2b_simd::Params;
+use log::info;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+use crate::block::hex_to_bytes;
+use crate::config::CONFIG;
+use crate::metrics;
+use crate::rpc::{rpc_work_generate, rpc_work_generate_external, RpcCommand, RpcError, WorkOptions};
+
+/// Minimum work difficulty for send and change blocks
+pub const SEND_DIFFICULTY: &str = "fffffff800000000";
+/// Minimum work difficulty for receive and open blocks
+pub const RECEIVE_DIFFICULTY: &str = "fffffe0000000000";
+
+/// Network difficulty from the node's active_difficulty websocket topic, 0 until it is known
+static ACTIVE_SEND_DIFFICULTY: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_RECEIVE_DIFFICULTY: AtomicU64 = AtomicU64::new(0);
+
+/// Record the current network difficulty, work for new blocks is generated for it when it is above the minimum
+pub fn set_active_difficulty(send: &str, receive: &str) -> Result<(), String> {
+    let send = parse_difficulty(send)?;
+    let receive = parse_difficulty(receive)?;
+    ACTIVE_SEND_DIFFICULTY.store(send, Ordering::Relaxed);
+    ACTIVE_RECEIVE_DIFFICULTY.store(receive, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Difficulty for new send and change blocks
+pub fn send_difficulty() -> String {
+    active_difficulty(SEND_DIFFICULTY, &ACTIVE_SEND_DIFFICULTY)
+}
+
+/// Difficulty for new receive and open blocks
+pub fn receive_difficulty() -> String {
+    active_difficulty(RECEIVE_DIFFICULTY, &ACTIVE_RECEIVE_DIFFICULTY)
+}
+
+fn active_difficulty(minimum: &str, active: &AtomicU64) -> String {
+    let minimum = parse_difficulty(minimum).unwrap();
+    format!("{:016x}", minimum.max(active.load(Ordering::Relaxed)))
+}
+
+/// Generate work for a block root with the node, falling back to the external work server
+/// and then to local generation when the node does not offer work_generate
+pub fn generate_work(rpc_tx: Sender<RpcCommand>, root: String, difficulty: &str) -> Result<String, String> {
+    let observe = |source, started: Instant| {
+        metrics::observe("nano_pool_work_seconds", &[("source", source)], started.elapsed());
+    };
+    let started = Instant::now();
+    let options = WorkOptions {
+        difficulty: Some(difficulty.to_owned()),
+        ..Default::default()
+    };
+    match rpc_work_generate(rpc_tx.clone(), root.clone(), options) {
+        Err(RpcError::Disabled(_)) => {}
+        result => {
+            let work = result?;
+            observe("node", started);
+            return Ok(work);
+        }
+    }
+    if !CONFIG.node.work_url.is_empty() {
+        let started = Instant::now();
+        match rpc_work_generate_external(rpc_tx, &CONFIG.node.work_url, root.clone(), Some(difficulty.to_owned())) {
+            Ok(work) => {
+                observe("external", started);
+                return Ok(work);
+            }
+            Err(e) => info!("WORK external work server {} failed: {}", CONFIG.node.work_url, e),
+        }
+    }
+    let started = Instant::now();
+    let work = generate_work_local(&root, difficulty)?;
+    observe("local", started);
+    Ok(work)
+}
+
+/// Difficulty value of work for a block root
+pub fn work_value(root: &str, work: &str) -> Result<u64, String> {
+    let root = hex_to_bytes(root)?;
+    let work = u64::from_str_radix(work, 16).map_err(|_| format!("Work {} is not a 64 bit hex value", work))?;
+    Ok(value(&root, work))
+}
+
+/// Check that work for a block root reaches the difficulty
+pub fn validate_work(root: &str, work: &str, difficulty: &str) -> Result<bool, String> {
+    Ok(work_value(root, work)? >= parse_difficulty(difficulty)?)
+}
+
+/// Generate work on all cpu cores
+pub fn generate_work_local(root: &str, difficulty: &str) -> Result<String, String> {
+    let root_bytes = hex_to_bytes(root)?;
+    let threshold = parse_difficulty(difficulty)?;
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    info!("WORK generating locally for {} with {} threads", root, threads);
+
+    let start = Instant::now();
+    let found = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel::<u64>();
+    for i in 0..threads {
+        let found = found.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            // Threads start at evenly spread points of the nonce space
+            let mut work = (u64::MAX / threads as u64) * i as u64;
+            while !found.load(Ordering::Relaxed) {
+                if value(&root_bytes, work) >= threshold {
+                    found.store(true, Ordering::Relaxed);
+                    let _ = tx.send(work);
+                    return;
+                }
+                work = work.wrapping_add(1);
+            }
+        });
+    }
+    let work = rx.recv().map_err(|e| e.to_string())?;
+    info!("WORK generated locally for {} in {:?}", root, start.elapsed());
+    Ok(format!("{:016x}", work))
+}
+
+fn parse_difficulty(difficulty: &str) -> Result<u64, String> {
+    u64::from_str_radix(difficulty, 16).map_err(|_| format!("Difficulty {} is not a 64 bit hex value", difficulty))
+}
+
+/// Work is hashed as little endian bytes, while it is written as big endian hex
+fn value(root: &[u8; 32], work: u64) -> u64 {
+    let hash = Params::new()
+        .hash_length(8)
+        .to_state()
+        .update(&work.to_le_bytes())
+        .update(root)
+        .finalize();
+    u64::from_le_bytes(hash.as_bytes().try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOT: &str = "718CC2121C3E641059BC1C2CFC45666C99E8AE922F7A807B7D07B62C995D79E2";
+
+    #[test]
+    fn work_validate_no_node_required() {
+        assert_eq!(format!("{:016x}", work_value(ROOT, "2bf29ef00786a6bc").unwrap()), "ffffffd21c3933f4");
+        assert!(validate_work(ROOT, "2bf29ef00786a6bc", RECEIVE_DIFFICULTY).unwrap());
+        assert!(!validate_work(ROOT, "2bf29ef00786a6bc", SEND_DIFFICULTY).unwrap());
+        assert!(work_value(ROOT, "xyz").is_err());
+    }
+
+    #[test]
+    fn work_active_difficulty_no_node_required() {
+        assert_eq!(send_difficulty(), SEND_DIFFICULTY);
+        set_active_difficulty("fffffffaaaaaaaab", "fffffeaaaaaaaaab").unwrap();
+        assert_eq!(send_difficulty(), "fffffffaaaaaaaab");
+        assert_eq!(receive_difficulty(), "fffffeaaaaaaaaab");
+        // Never below the minimum
+        set_active_difficulty("fffffff000000000", "fffffc0000000000").unwrap();
+        assert_eq!(send_difficulty(), SEND_DIFFICULTY);
+        assert_eq!(receive_difficulty(), RECEIVE_DIFFICULTY);
+        assert!(set_active_difficulty("xyz", RECEIVE_DIFFICULTY).is_err());
+    }
+
+    #[test]
+    fn work_generate_local_no_node_required() {
+        let work = generate_work_local(ROOT, "ff00000000000000").unwrap();
+        assert_eq!(work.len(), 16);
+        assert!(validate_work(ROOT, &work, "ff00000000000000").unwrap());
+    }
+}
@@ -1,65 +1,293 @@
-use log::info;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
 use serde_json::{json, Value};
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::TcpStream;
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
 use websocket::client::sync::Client;
 use websocket::message::OwnedMessage;
-use websocket::{ClientBuilder, Message};
+use websocket::{ClientBuilder, Message, WebSocketError};
 
 use crate::account::Account;
 use crate::address::Address;
 use crate::block::Block;
 use crate::config::CONFIG;
+use crate::metrics;
+use crate::rpc::{deserialize_bool_from_string, JsonTelemetryResponse};
 use crate::unit::Raw;
+use crate::work::set_active_difficulty;
 
-// start websocket interface
-pub fn start_ws() -> Sender<WsSubscription> {
-    let url = format!("ws://{}:{}", CONFIG.node_address, CONFIG.node_ws_port);
+/// Whether the websocket client is connected to the node, cleared while it reconnects
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub fn ws_connected() -> bool {
+    CONNECTED.load(Ordering::Relaxed)
+}
+
+/// Set on shutdown, the listener stops instead of reconnecting
+static CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Set while the periodic check of unconfirmed blocks runs, the next one is skipped until it finished
+static CHECKING: AtomicBool = AtomicBool::new(false);
+
+/// Time a caller waits for an ack beyond timeouts.ws_ack, the listener fails requests that are not acked by then
+/// but only notices on its next poll, and the request may wait for the client lock before it is sent
+const ACK_MARGIN: Duration = Duration::from_millis(1000);
+
+// start websocket interface, fails when the node can't be reached
+pub fn start_ws() -> Result<Sender<WsSubscription>, String> {
+    let url = format!("ws://{}:{}", CONFIG.node.address, CONFIG.node.ws_port);
     let (ws_tx, ws_rx) = mpsc::channel::<WsSubscription>();
-    WsClient::start(url, ws_rx);
-    ws_tx
+    WsClient::start(url, ws_rx)?;
+
+    // Work is generated for the current network difficulty, the subscription is not awaited since newer nodes
+    // no longer publish active_difficulty
+    let handler: WsHandler = Box::new(|message| {
+        if let WsMessage::ActiveDifficulty(d) = message {
+            if let Err(e) = set_active_difficulty(&d.network_current, &d.network_receive_current) {
+                error!("WS invalid active difficulty: {}", e);
+            }
+        }
+    });
+    let (tx, _) = mpsc::channel::<Result<(), String>>();
+    let _ = ws_tx.send(WsSubscription::topic(WsTopic::ActiveDifficulty, None, handler, tx));
+    Ok(ws_tx)
+}
+
+/// Called by the listener for every message of the topic it was registered for
+/// It runs on the listener thread, so it must not block or wait for other websocket requests
+pub type WsHandler = Box<dyn Fn(&WsMessage) + Send>;
+
+/// Work on watched accounts, e.g. receiving a confirmed send
+/// It runs on the accounts thread, the listener never locks an account while it holds the client lock,
+/// since account owners wait for websocket acks while holding their account
+type AccountJob = Box<dyn FnOnce() + Send>;
+
+enum WsRequest {
+    Watch(Address, Arc<Mutex<Account>>),
+    Unwatch(Address),
+    Topic(WsTopic, Option<Value>, WsHandler),
+    Close,
 }
 
 pub struct WsSubscription {
-    account: Arc<Mutex<Account>>,
-    tx_response: Sender<()>,
+    request: WsRequest,
+    tx_response: Sender<Result<(), String>>,
 }
 
 impl WsSubscription {
-    pub fn new(account: Arc<Mutex<Account>>, tx_response: Sender<()>) -> Self {
+    /// The response is sent once the node acked the subscription, or with an error when it did not in time
+    /// The account must not be locked by the caller, it is locked to get the address
+    pub fn new(account: Arc<Mutex<Account>>, tx_response: Sender<Result<(), String>>) -> Self {
+        let address = account.lock().unwrap().address();
+        Self {
+            request: WsRequest::Watch(address, account),
+            tx_response,
+        }
+    }
+
+    pub fn unwatch(account: Arc<Mutex<Account>>, tx_response: Sender<Result<(), String>>) -> Self {
+        let address = account.lock().unwrap().address();
+        Self {
+            request: WsRequest::Unwatch(address),
+            tx_response,
+        }
+    }
+
+    /// Subscribe to a topic with the node's options for it, and register a handler for its messages
+    /// Confirmations are filtered by the watched accounts, so the options are ignored for that topic
+    pub fn topic(
+        topic: WsTopic,
+        options: Option<Value>,
+        handler: WsHandler,
+        tx_response: Sender<Result<(), String>>,
+    ) -> Self {
+        Self {
+            request: WsRequest::Topic(topic, options, handler),
+            tx_response,
+        }
+    }
+
+    /// Close the connection to the node, it is not reconnected afterwards
+    pub fn close(tx_response: Sender<Result<(), String>>) -> Self {
         Self {
-            account,
+            request: WsRequest::Close,
             tx_response,
         }
     }
+
+    /// Send the subscription and wait until the node acked it, or fail when no answer came in time
+    pub fn send(self, ws_tx: &Sender<WsSubscription>, rx: Receiver<Result<(), String>>) -> Result<(), String> {
+        ws_tx.send(self).map_err(|_| "WS client is not running".to_owned())?;
+        let timeout = Duration::from_millis(CONFIG.timeouts.ws_ack as u64) + ACK_MARGIN;
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(format!("WS subscription was not acked within {:?}", timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err("WS client stopped before acking the subscription".to_owned()),
+        }
+    }
+}
+
+/// Watch an account for confirmations, waits until the node acked it
+pub fn ws_watch(ws_tx: &Sender<WsSubscription>, account: Arc<Mutex<Account>>) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel::<Result<(), String>>();
+    WsSubscription::new(account, tx).send(ws_tx, rx)
+}
+
+/// Stop watching an account, waits until the node acked it
+pub fn ws_unwatch(ws_tx: &Sender<WsSubscription>, account: Arc<Mutex<Account>>) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel::<Result<(), String>>();
+    WsSubscription::unwatch(account, tx).send(ws_tx, rx)
+}
+
+/// Subscribe to a topic and handle its messages, waits until the node acked it
+pub fn ws_subscribe(
+    ws_tx: &Sender<WsSubscription>,
+    topic: WsTopic,
+    options: Option<Value>,
+    handler: WsHandler,
+) -> Result<(), String> {
+    let (tx, rx) = mpsc::channel::<Result<(), String>>();
+    WsSubscription::topic(topic, options, handler, tx).send(ws_tx, rx)
+}
+
+/// Close the websocket on shutdown, waits up to timeouts.ws_ack until the close frame is sent
+/// A connection that is being reconnected is just not reconnected
+pub fn ws_close(ws_tx: &Sender<WsSubscription>) -> Result<(), String> {
+    CLOSED.store(true, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel::<Result<(), String>>();
+    ws_tx
+        .send(WsSubscription::close(tx))
+        .map_err(|_| "WS client is not running".to_owned())?;
+    rx.recv_timeout(Duration::from_millis(CONFIG.timeouts.ws_ack as u64))
+        .map_err(|_| "WS close frame was not sent in time".to_owned())?
+}
+
+/// Node websocket topics
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WsTopic {
+    Confirmation,
+    Vote,
+    StoppedElection,
+    ActiveDifficulty,
+    Telemetry,
+    Work,
+    NewUnconfirmedBlock,
+}
+
+impl WsTopic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WsTopic::Confirmation => "confirmation",
+            WsTopic::Vote => "vote",
+            WsTopic::StoppedElection => "stopped_election",
+            WsTopic::ActiveDifficulty => "active_difficulty",
+            WsTopic::Telemetry => "telemetry",
+            WsTopic::Work => "work",
+            WsTopic::NewUnconfirmedBlock => "new_unconfirmed_block",
+        }
+    }
+
+    pub fn from_name(topic: &str) -> Option<Self> {
+        match topic {
+            "confirmation" => Some(WsTopic::Confirmation),
+            "vote" => Some(WsTopic::Vote),
+            "stopped_election" => Some(WsTopic::StoppedElection),
+            "active_difficulty" => Some(WsTopic::ActiveDifficulty),
+            "telemetry" => Some(WsTopic::Telemetry),
+            "work" => Some(WsTopic::Work),
+            "new_unconfirmed_block" => Some(WsTopic::NewUnconfirmedBlock),
+            _ => None,
+        }
+    }
+}
+
+/// A message published by the node, typed by its topic
+#[derive(Debug, PartialEq)]
+pub enum WsMessage {
+    Confirmation(JsonConfirmation),
+    Vote(JsonVote),
+    StoppedElection(JsonStoppedElection),
+    ActiveDifficulty(JsonActiveDifficulty),
+    Telemetry(JsonWsTelemetry),
+    Work(JsonWork),
+    NewUnconfirmedBlock(Block),
+}
+
+impl WsMessage {
+    /// Parse a message with a topic, e.g. {"topic": "vote", "time": "...", "message": {...}}
+    pub fn parse(v: &Value) -> Result<(WsTopic, WsMessage), String> {
+        let topic = v["topic"].as_str().unwrap_or_default();
+        let topic = WsTopic::from_name(topic).ok_or_else(|| format!("WS unknown topic {}", v["topic"]))?;
+        let message = v["message"].clone();
+        let parsed = match topic {
+            WsTopic::Confirmation => serde_json::from_value(message).map(WsMessage::Confirmation),
+            WsTopic::Vote => serde_json::from_value(message).map(WsMessage::Vote),
+            WsTopic::StoppedElection => serde_json::from_value(message).map(WsMessage::StoppedElection),
+            WsTopic::ActiveDifficulty => serde_json::from_value(message).map(WsMessage::ActiveDifficulty),
+            WsTopic::Telemetry => serde_json::from_value(message).map(WsMessage::Telemetry),
+            WsTopic::Work => serde_json::from_value(message).map(WsMessage::Work),
+            WsTopic::NewUnconfirmedBlock => serde_json::from_value(message).map(WsMessage::NewUnconfirmedBlock),
+        };
+        parsed
+            .map(|m| (topic, m))
+            .map_err(|e| format!("WS invalid {} message: {}", topic.as_str(), e))
+    }
+}
+
+/// A subscribe, update or unsubscribe request waiting for its ack
+struct PendingAck {
+    id: String,
+    action: String,
+    /// accounts added by the request, they are not watched when it fails
+    addresses: Vec<Address>,
+    deadline: Instant,
+    txs: Vec<Sender<Result<(), String>>>,
+    /// a subscription that replaces the filter with all watched accounts, after reconnecting or switching
+    /// between filtering on the node and all confirmations
+    resubscribe: bool,
 }
 
 pub struct WsClient {
     url: String,
-    client: Client<TcpStream>,
+    /// none while reconnecting, requests are only queued then and sent again by resubscribe
+    client: Option<Client<TcpStream>>,
     watched_accounts: HashMap<Address, Arc<Mutex<Account>>>,
-    awaiting_ack: Option<(String, Sender<()>)>,
+    /// requests in the order they were sent, acks are matched by id
+    pending: VecDeque<PendingAck>,
+    next_id: u64,
+    /// subscribed to all confirmations because too many accounts are watched, they are filtered locally
+    all_confirmations: bool,
+    /// subscriptions to other topics than confirmation with their options
+    topics: HashMap<WsTopic, Option<Value>>,
+    handlers: HashMap<WsTopic, Vec<WsHandler>>,
+    last_ping: Instant,
+    last_recv: Instant,
+    /// last check of blocks that are unconfirmed for too long
+    last_check: Instant,
+    /// work on watched accounts, run by the accounts thread
+    jobs: Sender<AccountJob>,
+    /// checks of published blocks, run by the checks thread since they can rebuild blocks and generate work locally
+    checks: Sender<AccountJob>,
 }
 
 impl WsClient {
-    pub fn start(url: String, rx: Receiver<WsSubscription>) {
-        let mut builder = ClientBuilder::new(&url).unwrap();
-        let client = builder.connect_insecure().unwrap();
-        client.set_nonblocking(true).unwrap();
-        let wsc = Self {
-            url,
-            client,
-            watched_accounts: HashMap::new(),
-            awaiting_ack: None,
-        };
+    pub fn start(url: String, rx: Receiver<WsSubscription>) -> Result<(), String> {
+        let client = WsClient::connect(&url).map_err(|e| format!("WS could not connect to {}: {}", url, e))?;
+        CONNECTED.store(true, Ordering::Relaxed);
+        let mut wsc = WsClient::new(url);
+        wsc.client = Some(client);
+
+        // Elections of our blocks that stop without confirmation mean the block was dropped
+        wsc.topics.insert(WsTopic::StoppedElection, None);
+        wsc.request("subscribe", WsTopic::StoppedElection.as_str(), None, vec![], vec![], false);
 
         let wsc = Arc::new(Mutex::new(wsc));
         let wsc2 = wsc.clone();
@@ -79,157 +307,456 @@ impl WsClient {
                 WsClient::run_listener(wsc2);
             })
             .unwrap();
+        Ok(())
     }
 
-    fn subscribe(
-        &mut self,
-        topic: String,
-        options: Option<Value>,
-        tx_ack: Sender<()>,
-    ) -> Result<(), String> {
-        let json = JsonSubscribeMessage {
-            action: "subscribe".to_owned(),
-            ack: true,
-            topic: topic.to_owned(),
-            options,
-        };
-        self.awaiting_ack = Some(("subscribe".to_owned(), tx_ack));
-        self.send(serde_json::to_value(json).unwrap()).unwrap();
-        Ok(())
+    /// Client without a connection yet, with its worker threads
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            client: None,
+            watched_accounts: HashMap::new(),
+            pending: VecDeque::new(),
+            next_id: 0,
+            all_confirmations: false,
+            topics: HashMap::new(),
+            handlers: HashMap::new(),
+            last_ping: Instant::now(),
+            last_recv: Instant::now(),
+            last_check: Instant::now(),
+            jobs: WsClient::start_worker("ws accounts"),
+            checks: WsClient::start_worker("ws checks"),
+        }
+    }
+
+    /// Start a thread that runs the account jobs handed over by the listener in order
+    fn start_worker(name: &str) -> Sender<AccountJob> {
+        let (tx, rx) = mpsc::channel::<AccountJob>();
+        thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                for job in rx {
+                    job();
+                }
+            })
+            .unwrap();
+        tx
+    }
+
+    /// Hand work on watched accounts to the accounts thread
+    fn run<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if self.jobs.send(Box::new(job)).is_err() {
+            error!("WS accounts thread stopped");
+        }
     }
 
-    fn update(
+    /// Hand a check of published blocks to the checks thread, so receiving payments does not wait for it
+    fn check<F: FnOnce() + Send + 'static>(&self, job: F) {
+        if self.checks.send(Box::new(job)).is_err() {
+            error!("WS checks thread stopped");
+        }
+    }
+
+    /// Send a request that is acked by the node, the senders get the result once it is acked or timed out
+    fn request(
         &mut self,
-        topic: String,
+        action: &str,
+        topic: &str,
         options: Option<Value>,
-        tx_ack: Sender<()>,
-    ) -> Result<(), String> {
+        addresses: Vec<Address>,
+        txs: Vec<Sender<Result<(), String>>>,
+        resubscribe: bool,
+    ) {
+        self.next_id += 1;
+        let id = self.next_id.to_string();
         let json = JsonSubscribeMessage {
-            action: "update".to_owned(),
-            ack: true,
+            action: action.to_owned(),
             topic: topic.to_owned(),
+            ack: true,
+            id: id.clone(),
             options,
         };
-        self.awaiting_ack = Some(("update".to_owned(), tx_ack));
-        self.send(serde_json::to_value(json).unwrap()).unwrap();
+        self.pending.push_back(PendingAck {
+            id,
+            action: action.to_owned(),
+            addresses,
+            deadline: Instant::now() + Duration::from_millis(CONFIG.timeouts.ws_ack as u64),
+            txs,
+            resubscribe,
+        });
+        // A lost connection is noticed by the listener, which sends the request again after reconnecting
+        if let Err(e) = self.send(serde_json::to_value(json).unwrap()) {
+            error!("{}", e);
+        }
+    }
+
+    /// Complete the request an ack belongs to
+    fn ack(&mut self, v: &Value) {
+        let id = v["id"].as_str().unwrap_or_default();
+        match self.pending.iter().position(|p| p.id == id) {
+            Some(i) => {
+                let pending = self.pending.remove(i).unwrap();
+                for tx in pending.txs {
+                    let _ = tx.send(Ok(()));
+                }
+            }
+            None => error!("WS unexpected ack {}", v),
+        }
+    }
+
+    /// Fail requests that were not acked in time
+    /// A resubscription that is not acked is treated as a lost connection
+    fn expire_acks(&mut self) -> Result<(), String> {
+        let now = Instant::now();
+        if self.pending.iter().any(|p| p.resubscribe && p.deadline <= now) {
+            return Err("WS resubscription was not acked".to_owned());
+        }
+        while let Some(i) = self.pending.iter().position(|p| p.deadline <= now) {
+            let pending = self.pending.remove(i).unwrap();
+            let e = format!(
+                "WS {} {} was not acked within {} ms",
+                pending.action,
+                pending.addresses.join(", "),
+                CONFIG.timeouts.ws_ack
+            );
+            error!("{}", e);
+            for address in &pending.addresses {
+                self.watched_accounts.remove(address);
+            }
+            for tx in pending.txs {
+                let _ = tx.send(Err(e.clone()));
+            }
+        }
         Ok(())
     }
 
+    fn connect(url: &str) -> Result<Client<TcpStream>, String> {
+        let mut builder = ClientBuilder::new(url).map_err(|e| e.to_string())?;
+        let client = builder.connect_insecure().map_err(|e| e.to_string())?;
+        client.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(client)
+    }
+
+    fn client(&mut self) -> Result<&mut Client<TcpStream>, String> {
+        self.client.as_mut().ok_or_else(|| "WS not connected".to_owned())
+    }
+
+    /// Reconnect with backoff until it succeeds, none once the client is closed
+    /// It runs without the client lock, so requests are not held up by the backoff
+    fn reconnect(url: &str) -> Option<Client<TcpStream>> {
+        let mut backoff = Duration::from_millis(CONFIG.timeouts.ws_reconnect as u64);
+        let max_backoff = Duration::from_millis(CONFIG.timeouts.ws_reconnect_max as u64);
+        loop {
+            thread::sleep(backoff);
+            if CLOSED.load(Ordering::Relaxed) {
+                return None;
+            }
+            match WsClient::connect(url) {
+                Ok(client) => return Some(client),
+                Err(e) => {
+                    error!("WS reconnect to {} failed, retrying in {:?}: {}", url, backoff, e);
+                    backoff = (backoff * 2).min(max_backoff);
+                }
+            }
+        }
+    }
+
+    /// Subscribe to all topics and watched accounts again after reconnecting,
+    /// and receive the pending blocks that were confirmed while disconnected
+    fn resubscribe(&mut self) {
+        info!("WS reconnected to {}", self.url);
+        metrics::count("nano_pool_ws_reconnects_total", &[], 1.0);
+        self.last_ping = Instant::now();
+        self.last_recv = Instant::now();
+
+        // Requests that were not acked yet are covered by the new subscriptions
+        let mut added = vec![];
+        let mut txs = vec![];
+        for pending in self.pending.drain(..) {
+            added.extend(pending.addresses);
+            txs.extend(pending.txs);
+        }
+        let topics: Vec<(WsTopic, Option<Value>)> = self.topics.iter().map(|(t, o)| (*t, o.clone())).collect();
+        for (topic, options) in topics {
+            self.request("subscribe", topic.as_str(), options, vec![], vec![], false);
+        }
+        if self.watched_accounts.is_empty() {
+            for tx in txs {
+                let _ = tx.send(Ok(()));
+            }
+            return;
+        }
+        let options = self.subscribe_options();
+        self.request("subscribe", "confirmation", options, added, txs, true);
+
+        let accounts: Vec<Arc<Mutex<Account>>> = self.watched_accounts.values().cloned().collect();
+        self.run(move || {
+            for account in accounts {
+                let mut account = account.lock().unwrap();
+                account.update_info();
+                if let Err(e) = account.receive_all() {
+                    error!("WS {} could not receive its pending blocks, they stay pending: {}", account.address(), e);
+                }
+            }
+        });
+    }
+
     fn send(&mut self, json: Value) -> Result<(), String> {
         info!("WS send {}", json);
         let message = Message::text(json.to_string());
-        self.client.send_message(&message).unwrap();
+        self.client()?.send_message(&message).map_err(|e| format!("WS send failed: {}", e))
+    }
+
+    /// Ping the node when it has been quiet for a while, fails when the node stopped answering
+    fn keepalive(&mut self) -> Result<(), String> {
+        let interval = Duration::from_secs(CONFIG.timeouts.ws_ping_interval as u64);
+        if self.last_recv.elapsed() > interval * 2 {
+            return Err(format!("WS no messages from node for {:?}", self.last_recv.elapsed()));
+        }
+        if self.last_ping.elapsed() > interval && self.last_recv.elapsed() > interval {
+            self.last_ping = Instant::now();
+            self.client()?
+                .send_message(&OwnedMessage::Ping(vec![]))
+                .map_err(|e| format!("WS ping failed: {}", e))?;
+        }
         Ok(())
     }
 
     /// Returns empty json if nothing to receive
     fn recv(&mut self) -> Result<Value, String> {
-        let message = self.client.recv_message();
+        let message = self.client()?.recv_message();
+        if message.is_ok() {
+            self.last_recv = Instant::now();
+        }
         match message {
             Ok(OwnedMessage::Text(t)) => {
-                let json = serde_json::from_str(&t).unwrap();
+                let json = serde_json::from_str(&t).map_err(|e| format!("WS invalid json {}: {}", t, e))?;
                 info!("WS recv {}", json);
                 Ok(json)
             }
-            Ok(OwnedMessage::Close(_)) => {
-                let mut builder = ClientBuilder::new(&self.url).unwrap();
-                self.client = builder.connect_insecure().unwrap();
-                Err("WS error, connection closed by server and failed to reconnect".to_owned())
+            Ok(OwnedMessage::Ping(data)) => {
+                self.client()?
+                    .send_message(&OwnedMessage::Pong(data))
+                    .map_err(|e| format!("WS pong failed: {}", e))?;
+                Ok(json!({}))
             }
-            Err(_) => Ok(json!({})),
-            _ => Err("WS error, non-text message received".to_owned()),
+            Ok(OwnedMessage::Pong(_)) => Ok(json!({})),
+            Ok(OwnedMessage::Close(_)) => Err("WS connection closed by server".to_owned()),
+            Ok(OwnedMessage::Binary(_)) => Err("WS error, non-text message received".to_owned()),
+            Err(WebSocketError::IoError(e)) if e.kind() == ErrorKind::WouldBlock => Ok(json!({})),
+            Err(e) => Err(format!("WS connection lost: {}", e)),
         }
     }
 
-    fn watch_account(&mut self, sub: WsSubscription) {
-        let account = sub.account;
-        let address = account.lock().unwrap().address();
-        if self.watched_accounts.len() == 0 {
+    /// Confirmation filter for all watched accounts, none when subscribed to all confirmations
+    fn subscribe_options(&self) -> Option<Value> {
+        if self.all_confirmations {
+            None
+        } else {
+            let addresses: Vec<&Address> = self.watched_accounts.keys().collect();
+            Some(json!({ "accounts": addresses }))
+        }
+    }
+
+    fn watch_account(&mut self, address: Address, account: Arc<Mutex<Account>>, tx_response: Sender<Result<(), String>>) {
+        let first = self.watched_accounts.is_empty();
+        if self.watched_accounts.insert(address.clone(), account).is_some() {
+            let _ = tx_response.send(Ok(()));
+            return;
+        }
+
+        let limit = CONFIG.node.ws_accounts_limit;
+        if self.all_confirmations {
+            let _ = tx_response.send(Ok(()));
+        } else if limit > 0 && self.watched_accounts.len() > limit {
+            info!(
+                "WS watching {} accounts, subscribing to all confirmations",
+                self.watched_accounts.len()
+            );
+            self.all_confirmations = true;
+            self.request("subscribe", "confirmation", None, vec![address], vec![tx_response], true);
+        } else if first {
             let options = json!({ "accounts": vec![address.clone()] });
-            self.subscribe("confirmation".to_owned(), Some(options), sub.tx_response)
-                .unwrap();
+            self.request("subscribe", "confirmation", Some(options), vec![address], vec![tx_response], false);
         } else {
             let options = json!({ "accounts_add": vec![address.clone()] });
-            self.update("confirmation".to_owned(), Some(options), sub.tx_response)
-                .unwrap();
+            self.request("update", "confirmation", Some(options), vec![address], vec![tx_response], false);
         }
-        self.watched_accounts.insert(address, account.clone());
     }
 
-    // fn unwatch_account(&mut self, account: Arc<Mutex<Account>>) {
-    //     let address = account.lock().unwrap().address();
-    //     let options: Value = json!({ "accounts_del": vec![address.clone()] });
-    //     self.update("confirmation".to_owned(), Some(options))
-    //         .unwrap();
-    //     self.watched_accounts.remove(&address);
-    // }
+    fn unwatch_account(&mut self, address: Address, tx_response: Sender<Result<(), String>>) {
+        if self.watched_accounts.remove(&address).is_none() {
+            let _ = tx_response.send(Ok(()));
+            return;
+        }
+
+        // Go back to filtering on the node once the watched accounts fit well within the limit again
+        let limit = CONFIG.node.ws_accounts_limit;
+        if self.all_confirmations && self.watched_accounts.len() <= limit / 2 {
+            info!(
+                "WS watching {} accounts, subscribing to their confirmations only",
+                self.watched_accounts.len()
+            );
+            self.all_confirmations = false;
+            let options = self.subscribe_options();
+            self.request("subscribe", "confirmation", options, vec![], vec![tx_response], true);
+        } else if self.all_confirmations {
+            let _ = tx_response.send(Ok(()));
+        } else {
+            let options = json!({ "accounts_del": vec![address] });
+            self.request("update", "confirmation", Some(options), vec![], vec![tx_response], false);
+        }
+    }
+
+    /// Register a topic handler and subscribe to the topic, replacing earlier options for it
+    fn subscribe_topic(&mut self, topic: WsTopic, options: Option<Value>, handler: WsHandler, tx: Sender<Result<(), String>>) {
+        self.handlers.entry(topic).or_default().push(handler);
+        if topic == WsTopic::Confirmation {
+            let _ = tx.send(Ok(()));
+            return;
+        }
+        self.topics.insert(topic, options.clone());
+        self.request("subscribe", topic.as_str(), options, vec![], vec![tx], false);
+    }
 
     fn run_sender(wsc: Arc<Mutex<WsClient>>, rx: Receiver<WsSubscription>) {
-        loop {
-            let sub = rx.recv().unwrap();
+        for sub in rx {
             let mut wsc = wsc.lock().unwrap();
-            if let Some(_) = wsc.awaiting_ack {
-                // We are already waiting on another command ack, let's wait for that before sending another one...
-                // todo move polling rate to config?
-                drop(wsc);
-                thread::sleep(Duration::from_millis(100));
-                continue;
+            match sub.request {
+                WsRequest::Watch(address, account) => wsc.watch_account(address, account, sub.tx_response),
+                WsRequest::Unwatch(address) => wsc.unwatch_account(address, sub.tx_response),
+                WsRequest::Topic(topic, options, handler) => wsc.subscribe_topic(topic, options, handler, sub.tx_response),
+                WsRequest::Close => {
+                    let _ = sub.tx_response.send(wsc.close());
+                }
+            }
+        }
+    }
+
+    /// Send a close frame to the node unless the connection is already lost, requests that were not acked yet fail
+    fn close(&mut self) -> Result<(), String> {
+        for pending in self.pending.drain(..) {
+            for tx in pending.txs {
+                let _ = tx.send(Err("WS closed".to_owned()));
+            }
+        }
+        if !CONNECTED.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        info!("WS closing connection to {}", self.url);
+        self.client()?
+            .send_message(&OwnedMessage::Close(None))
+            .map_err(|e| format!("WS close failed: {}", e))
+    }
+
+    /// Check the blocks of the watched account whose election stopped without confirmation
+    fn stopped_election(&self, hash: &str) {
+        let accounts: Vec<Arc<Mutex<Account>>> = self.watched_accounts.values().cloned().collect();
+        let hash = hash.to_owned();
+        self.check(move || {
+            for account in accounts {
+                let mut account = account.lock().unwrap();
+                if account.is_unconfirmed(&hash) {
+                    account.check_published();
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Periodically check watched accounts with blocks that are unconfirmed for too long
+    fn check_unconfirmed(&mut self) {
+        let interval = Duration::from_secs(CONFIG.timeouts.unconfirmed_check as u64);
+        if self.last_check.elapsed() < interval || CHECKING.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.last_check = Instant::now();
+        let accounts: Vec<Arc<Mutex<Account>>> = self.watched_accounts.values().cloned().collect();
+        self.check(move || {
+            for account in accounts {
+                let mut account = account.lock().unwrap();
+                if account.has_unconfirmed_older_than(interval) {
+                    account.check_published();
+                }
             }
-            wsc.watch_account(sub);
+            CHECKING.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Receive incoming send blocks to watched accounts and update the watched senders
+    fn confirmed(&self, message: &JsonConfirmation) {
+        let sender = self.watched_accounts.get(&message.account).cloned();
+        let receiver = self.watched_accounts.get(&message.block.link_as_account).cloned();
+        if sender.is_none() && receiver.is_none() {
+            return;
         }
+        let (hash, amount) = (message.hash.clone(), message.amount);
+        self.run(move || {
+            if let Some(account) = sender {
+                account.lock().unwrap().update_info();
+            }
+            if let Some(account) = receiver {
+                let mut account = account.lock().unwrap();
+                if let Err(e) = account.receive_block(hash.clone(), amount) {
+                    error!("WS {} could not receive {}, it stays pending: {}", account.address(), hash, e);
+                }
+            }
+        });
     }
 
-    fn run_listener(wsc: Arc<Mutex<WsClient>>) {
+    fn run_listener(shared: Arc<Mutex<WsClient>>) {
         loop {
-            let mut wsc = wsc.lock().unwrap();
-            match wsc.recv() {
+            let mut wsc = shared.lock().unwrap();
+            if CLOSED.load(Ordering::Relaxed) {
+                info!("WS closed");
+                return;
+            }
+            match wsc.expire_acks().and_then(|_| wsc.keepalive()).and_then(|_| wsc.recv()) {
                 Err(e) => {
-                    panic!("{}", e);
+                    error!("{}, reconnecting", e);
+                    CONNECTED.store(false, Ordering::Relaxed);
+                    wsc.client = None;
+                    let url = wsc.url.clone();
+                    drop(wsc);
+                    // A closed client stops at the top of the loop
+                    if let Some(client) = WsClient::reconnect(&url) {
+                        let mut wsc = shared.lock().unwrap();
+                        wsc.client = Some(client);
+                        CONNECTED.store(true, Ordering::Relaxed);
+                        wsc.resubscribe();
+                    }
                 }
                 Ok(v) => {
                     if v == json!({}) {
-                        // todo move polling rate to config?
+                        wsc.check_unconfirmed();
                         drop(wsc); // key drop which unlocks wsc
-                        thread::sleep(Duration::from_millis(100));
+                        thread::sleep(Duration::from_millis(CONFIG.timeouts.ws_poll_interval as u64));
                         continue;
                     }
 
                     // Check for ack
-                    let ack = v["ack"].as_str();
-                    match (ack, &wsc.awaiting_ack) {
-                        (Some(ack), Some((topic, tx))) => {
-                            tx.send(()).unwrap();
-                            if ack != *topic {
-                                panic!("WS error unexpected ack?!");
-                            }
-                            wsc.awaiting_ack = None;
-                            continue;
-                        }
-                        (None, _) => {}
-                        _ => {
-                            panic!("WS error unexpected ack?!");
-                        }
+                    if v.get("ack").is_some() {
+                        wsc.ack(&v);
+                        continue;
                     }
 
-                    let message: JsonConfirmation =
-                        serde_json::from_value(v["message"].clone()).unwrap();
-
-                    // Update the block sender info upon confirmation
-                    match &wsc.watched_accounts.entry(message.account) {
-                        Vacant(_) => {}
-                        Occupied(entry) => {
-                            entry.get().clone().lock().unwrap().update_info();
+                    let (topic, message) = match WsMessage::parse(&v) {
+                        Ok(m) => m,
+                        Err(e) => {
+                            error!("{}", e);
+                            continue;
                         }
+                    };
+                    metrics::count("nano_pool_ws_messages_total", &[("topic", topic.as_str())], 1.0);
+                    match &message {
+                        WsMessage::Confirmation(confirmation) => wsc.confirmed(confirmation),
+                        WsMessage::StoppedElection(election) => wsc.stopped_election(&election.hash),
+                        _ => {}
                     }
-
-                    // Receive incoming send blocks to watched linked accounts
-                    let linked_account = &wsc.watched_accounts.get(&message.block.link_as_account);
-                    match linked_account {
-                        Some(account) => account
-                            .lock()
-                            .unwrap()
-                            .receive_block(message.hash, message.amount),
-                        None => {}
+                    if let Some(handlers) = wsc.handlers.get(&topic) {
+                        for handler in handlers {
+                            handler(&message);
+                        }
                     }
                 }
             }
@@ -242,16 +769,222 @@ struct JsonSubscribeMessage {
     action: String,
     topic: String,
     ack: bool,
+    id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     options: Option<Value>,
 }
 
-#[derive(Deserialize)]
-struct JsonConfirmation {
-    account: Address,
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonConfirmation {
+    pub account: Address,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    amount: Raw,
-    block: Block,
-    confirmation_type: String,
-    hash: String,
+    pub amount: Raw,
+    pub block: Block,
+    pub confirmation_type: String,
+    pub hash: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonVote {
+    pub account: Address,
+    pub signature: String,
+    pub blocks: Vec<String>,
+    /// "vote", "replay" or "indeterminate", only sent with include_replays or include_indeterminate
+    pub r#type: String,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonStoppedElection {
+    pub hash: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonActiveDifficulty {
+    pub multiplier: String,
+    pub network_current: String,
+    pub network_minimum: String,
+    pub network_receive_current: String,
+    pub network_receive_minimum: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonWsTelemetry {
+    /// the peer that sent the telemetry
+    pub address: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    #[serde(flatten)]
+    pub telemetry: JsonTelemetryResponse,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonWork {
+    #[serde(deserialize_with = "deserialize_bool_from_string")]
+    pub success: bool,
+    pub reason: String,
+    /// milliseconds spent generating
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub duration: u64,
+    pub request: JsonWorkRequest,
+    #[serde(default)]
+    pub result: Option<JsonWorkResult>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonWorkRequest {
+    pub hash: String,
+    pub difficulty: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JsonWorkResult {
+    pub source: String,
+    pub work: String,
+    pub difficulty: String,
+}
+
+/// Fake websocket client for tests, acking every request right away
+#[cfg(test)]
+pub(crate) fn mock_ws() -> Sender<WsSubscription> {
+    let (ws_tx, ws_rx) = mpsc::channel::<WsSubscription>();
+    thread::spawn(move || {
+        for sub in ws_rx {
+            let _ = sub.tx_response.send(Ok(()));
+        }
+    });
+    ws_tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock_node;
+
+    const ADDRESS: &str = "nano_1b9wguhh39at8qtm93oghd6r4f4ubk7zmqc9oi5ape6yyz4s1gamuwn3jjit";
+
+    /// A watch-only account on a node without pending blocks, its websocket requests are acked right away
+    fn watched_account() -> Arc<Mutex<Account>> {
+        let rpc_tx = mock_node(|cmd| match cmd["action"].as_str().unwrap() {
+            "account_balance" => Some(json!({ "balance": "0", "pending": "0", "receivable": "0" })),
+            "account_info" => Some(serde_json::from_str(include_str!("../tests/fixtures/rpc/account_info.json")).unwrap()),
+            "accounts_pending" => Some(json!({ "blocks": "" })),
+            _ => None,
+        });
+        Account::watch(ADDRESS, 0, rpc_tx, mock_ws()).unwrap()
+    }
+
+    fn ack(wsc: &mut WsClient, id: &str) {
+        wsc.ack(&json!({ "ack": "subscribe", "id": id }));
+    }
+
+    #[test]
+    fn ws_resubscribe_no_node_required() {
+        // Requests made while disconnected are queued until the connection is back
+        let mut wsc = WsClient::new("ws://127.0.0.1:1".to_owned());
+        let (tx, rx) = mpsc::channel::<Result<(), String>>();
+        wsc.subscribe_topic(WsTopic::Telemetry, None, Box::new(|_| {}), tx.clone());
+        ack(&mut wsc, "1");
+        assert_eq!(rx.try_recv(), Ok(Ok(())));
+        assert!(wsc.pending.is_empty());
+
+        let account = watched_account();
+        wsc.watch_account(ADDRESS.to_owned(), account.clone(), tx.clone());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(wsc.pending.len(), 1);
+
+        // After reconnecting, the topics are subscribed again and the accounts of unacked requests are
+        // covered by one confirmation subscription with all watched accounts
+        wsc.resubscribe();
+        let requests: Vec<(&str, bool, Vec<Address>)> =
+            wsc.pending.iter().map(|p| (p.id.as_str(), p.resubscribe, p.addresses.clone())).collect();
+        assert_eq!(requests, vec![("3", false, vec![]), ("4", true, vec![ADDRESS.to_owned()])]);
+        assert_eq!(wsc.subscribe_options(), Some(json!({ "accounts": [ADDRESS] })));
+        ack(&mut wsc, "3");
+        assert!(rx.try_recv().is_err());
+        ack(&mut wsc, "4");
+        assert_eq!(rx.try_recv(), Ok(Ok(())));
+        assert!(wsc.expire_acks().is_ok());
+
+        // A request that is not acked in time fails and its account is no longer watched
+        wsc.unwatch_account(ADDRESS.to_owned(), tx.clone());
+        wsc.watch_account(ADDRESS.to_owned(), account, tx.clone());
+        wsc.pending.back_mut().unwrap().deadline = Instant::now();
+        assert!(wsc.expire_acks().is_ok());
+        assert!(rx.try_recv().unwrap().unwrap_err().contains("was not acked"));
+        assert!(wsc.watched_accounts.is_empty());
+
+        // A resubscription that is not acked in time means the connection is lost
+        wsc.request("subscribe", "confirmation", None, vec![], vec![], true);
+        wsc.pending.back_mut().unwrap().deadline = Instant::now();
+        assert!(wsc.expire_acks().is_err());
+    }
+
+    fn parse(fixture: &str) -> (WsTopic, WsMessage) {
+        WsMessage::parse(&serde_json::from_str(fixture).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn ws_messages_no_node_required() {
+        let (topic, message) = parse(include_str!("../tests/fixtures/ws/confirmation.json"));
+        assert_eq!(topic, WsTopic::Confirmation);
+        match message {
+            WsMessage::Confirmation(c) => {
+                assert_eq!(c.amount, 1000000000000000000000000000000);
+                assert_eq!(c.block.subtype, Some("send".to_owned()));
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+
+        match parse(include_str!("../tests/fixtures/ws/vote.json")).1 {
+            WsMessage::Vote(v) => {
+                assert_eq!(v.blocks.len(), 1);
+                assert_eq!(v.r#type, "vote");
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+
+        assert_eq!(
+            parse(include_str!("../tests/fixtures/ws/stopped_election.json")).1,
+            WsMessage::StoppedElection(JsonStoppedElection {
+                hash: "FA6D344ECAB2C5E1C04E62B2BC6EE072938DD47530AB26B2A8FD1B6F6C56E7A7".to_owned()
+            })
+        );
+
+        match parse(include_str!("../tests/fixtures/ws/active_difficulty.json")).1 {
+            WsMessage::ActiveDifficulty(d) => {
+                assert_eq!(d.network_current, "fffffffaaaaaaaab");
+                assert_eq!(d.network_receive_current, "fffffeaaaaaaaaab");
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+
+        match parse(include_str!("../tests/fixtures/ws/telemetry.json")).1 {
+            WsMessage::Telemetry(t) => {
+                assert_eq!(t.port, 7075);
+                assert_eq!(t.telemetry.peer_count, 32);
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+
+        match parse(include_str!("../tests/fixtures/ws/work.json")).1 {
+            WsMessage::Work(w) => {
+                assert!(w.success);
+                assert_eq!(w.duration, 306);
+                assert_eq!(w.result.unwrap().work, "4a8fb104eebbd336");
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+
+        match parse(include_str!("../tests/fixtures/ws/new_unconfirmed_block.json")).1 {
+            WsMessage::NewUnconfirmedBlock(b) => assert_eq!(b.subtype, Some("change".to_owned())),
+            m => panic!("unexpected {:?}", m),
+        }
+
+        let unknown = json!({ "topic": "bootstrap", "message": {} });
+        assert!(WsMessage::parse(&unknown).is_err());
+        let invalid = json!({ "topic": "stopped_election", "message": {} });
+        assert!(WsMessage::parse(&invalid).is_err());
+    }
 }